@@ -0,0 +1,545 @@
+//! Gap free accelerometer data from the LSM303's 32 sample FIFO.
+//!
+//! Polling the data ready flag costs an I2C transaction per check and still
+//! misses samples when the main loop is busy at high data rates. In stream
+//! mode the FIFO keeps the last 32 samples instead, and raises INT1 once
+//! `Config::watermark` of them are waiting. The board then reads all of them
+//! in a single burst.
+//!
+//! The drivers for the LSM303AGR and the LSM303DLHC do not expose the FIFO,
+//! so this talks to the registers directly. Both chips lay them out the same
+//! way, so it works with either.
+//!
+//! The click generator and interrupt generator 1 can share INT1 with the
+//! watermark, to detect taps and freefalls at the full data rate. The board
+//! then asks `events` what they saw whenever INT1 fires.
+//!
+//! Draining the FIFO and reading the event sources change what the chip
+//! reports next, so those reads go through `WriteReadOnce` and are never
+//! repeated after a failure.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::i2c::WriteReadOnce;
+use crate::units::{AccelSensitivity, Acceleration, Chip, Range, Resolution};
+
+/// The accelerometer's I2C address on both chips
+pub const ADDRESS: u8 = 0x19;
+/// How many samples the FIFO holds
+pub const LEN: usize = 32;
+
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG2_A: u8 = 0x21;
+const CTRL_REG3_A: u8 = 0x22;
+const CTRL_REG4_A: u8 = 0x23;
+const CTRL_REG5_A: u8 = 0x24;
+const OUT_X_L_A: u8 = 0x28;
+const FIFO_CTRL_REG_A: u8 = 0x2E;
+const FIFO_SRC_REG_A: u8 = 0x2F;
+const INT1_CFG_A: u8 = 0x30;
+const INT1_SRC_A: u8 = 0x31;
+const INT1_THS_A: u8 = 0x32;
+const INT1_DURATION_A: u8 = 0x33;
+const CLICK_CFG_A: u8 = 0x38;
+const CLICK_SRC_A: u8 = 0x39;
+const CLICK_THS_A: u8 = 0x3A;
+const TIME_LIMIT_A: u8 = 0x3B;
+
+/// Set in a register address to read several registers at once. With the
+/// FIFO enabled, reading past `OUT_Z_H_A` wraps around to `OUT_X_L_A` and
+/// continues with the next sample.
+const AUTO_INCREMENT: u8 = 0x80;
+/// Enables all three axes
+const XYZ_EN: u8 = 0b111;
+/// High resolution mode, 12 bits per axis
+const HR: u8 = 1 << 3;
+const FIFO_EN: u8 = 1 << 6;
+/// Latches interrupt generator 1 until `INT1_SRC_A` is read
+const LIR_INT1: u8 = 1 << 3;
+/// Feeds the click generator high pass filtered data, so gravity is ignored
+const HPCLICK: u8 = 1 << 2;
+/// Routes the watermark interrupt to INT1
+const I1_WTM: u8 = 1 << 2;
+const I1_AOI1: u8 = 1 << 6;
+const I1_CLICK: u8 = 1 << 7;
+/// Single clicks on all three axes
+const CLICK_SINGLE_XYZ: u8 = 0b01_0101;
+/// Latches the click interrupt until `CLICK_SRC_A` is read, the LSM303DLHC
+/// does not have it
+const LIR_CLICK: u8 = 1 << 7;
+/// Interrupt once all of the enabled events happen, rather than any of them
+const AOI_AND: u8 = 1 << 7;
+/// Each axis below the threshold
+const LOW_XYZ: u8 = 0b01_0101;
+const SRC_ACTIVE: u8 = 1 << 6;
+const SRC_SINGLE_CLICK: u8 = 1 << 4;
+/// The longest duration the generators can count, in samples
+const MAX_DURATION: u64 = 127;
+const MODE_BYPASS: u8 = 0b00 << 6;
+const MODE_STREAM: u8 = 0b10 << 6;
+const SRC_OVERRUN: u8 = 1 << 6;
+const SRC_EMPTY: u8 = 1 << 5;
+const SRC_COUNT: u8 = 0b1_1111;
+
+/// Output data rates available in high resolution mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataRate {
+    Hz100,
+    Hz200,
+    Hz400,
+    Hz1344,
+}
+
+impl DataRate {
+    pub fn hz(self) -> u32 {
+        match self {
+            DataRate::Hz100 => 100,
+            DataRate::Hz200 => 200,
+            DataRate::Hz400 => 400,
+            DataRate::Hz1344 => 1344,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            DataRate::Hz100 => 0b0101,
+            DataRate::Hz200 => 0b0110,
+            DataRate::Hz400 => 0b0111,
+            DataRate::Hz1344 => 0b1001,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub chip: Chip,
+    pub data_rate: DataRate,
+    pub range: Range,
+    /// How many samples have to be waiting before INT1 goes high, from 1 to 31
+    pub watermark: u8,
+}
+
+/// What `AccelFifo::drain` read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Drained {
+    /// How many samples were written, oldest first
+    pub count: usize,
+    /// The FIFO was full and the oldest samples were overwritten since the
+    /// last drain
+    pub overrun: bool,
+}
+
+/// What the click and interrupt generators detected
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Events {
+    pub tap: bool,
+    pub freefall: bool,
+}
+
+pub struct AccelFifo<I> {
+    i2c: I,
+    config: Config,
+    /// What is routed to INT1
+    int1: u8,
+}
+
+impl<I, E> AccelFifo<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E> + WriteReadOnce,
+{
+    /// Takes over the bus, `start` then sets up the accelerometer
+    pub fn new(i2c: I, config: Config) -> AccelFifo<I> {
+        AccelFifo {
+            i2c,
+            config,
+            int1: I1_WTM,
+        }
+    }
+
+    /// Sets up the accelerometer and starts filling the FIFO. After an error
+    /// this can simply be called again, it also turns the tap and freefall
+    /// detection back off.
+    pub fn start(&mut self) -> Result<(), E> {
+        let config = self.config;
+        self.int1 = I1_WTM;
+        self.write(CTRL_REG1_A, config.data_rate.bits() << 4 | XYZ_EN)?;
+        self.write(CTRL_REG4_A, (config.range as u8) << 4 | HR)?;
+        self.write(CTRL_REG5_A, FIFO_EN)?;
+        // Bypass mode empties the FIFO, so no stale samples are left over
+        self.write(FIFO_CTRL_REG_A, MODE_BYPASS)?;
+        self.write(
+            FIFO_CTRL_REG_A,
+            MODE_STREAM | config.watermark.clamp(1, LEN as u8 - 1),
+        )?;
+        self.write(CTRL_REG3_A, self.int1)
+    }
+
+    /// Lets the click generator detect taps, spikes above `threshold` on any
+    /// axis that are over within `limit_ms`
+    pub fn enable_taps(&mut self, threshold: Acceleration, limit_ms: u32) -> Result<(), E> {
+        self.write(CTRL_REG2_A, HPCLICK)?;
+        self.write(CLICK_CFG_A, CLICK_SINGLE_XYZ)?;
+        let latch = match self.config.chip {
+            Chip::Lsm303agr => LIR_CLICK,
+            Chip::Lsm303dlhc => 0,
+        };
+        self.write(CLICK_THS_A, latch | self.threshold(threshold))?;
+        self.write(TIME_LIMIT_A, self.samples(limit_ms))?;
+        self.route(I1_CLICK)
+    }
+
+    /// Lets interrupt generator 1 detect freefalls, all axes staying below
+    /// `threshold` for `duration_ms`
+    pub fn enable_freefall(&mut self, threshold: Acceleration, duration_ms: u32) -> Result<(), E> {
+        self.write(CTRL_REG5_A, FIFO_EN | LIR_INT1)?;
+        self.write(INT1_THS_A, self.threshold(threshold))?;
+        self.write(INT1_DURATION_A, self.samples(duration_ms))?;
+        self.write(INT1_CFG_A, AOI_AND | LOW_XYZ)?;
+        self.route(I1_AOI1)
+    }
+
+    /// What the generators detected since the last call. Reading clears the
+    /// latched interrupts.
+    pub fn events(&mut self) -> Result<Events, E> {
+        let mut events = Events::default();
+        if self.int1 & I1_CLICK != 0 {
+            let source = self.read_once(CLICK_SRC_A)?;
+            events.tap = source & SRC_ACTIVE != 0 && source & SRC_SINGLE_CLICK != 0;
+        }
+        if self.int1 & I1_AOI1 != 0 {
+            events.freefall = self.read_once(INT1_SRC_A)? & SRC_ACTIVE != 0;
+        }
+        Ok(events)
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Gives the bus back, the accelerometer keeps running
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    /// Reads all samples waiting in the FIFO, in the sensor's frame
+    pub fn drain(&mut self, samples: &mut [[Acceleration; 3]; LEN]) -> Result<Drained, E> {
+        let source = self.read(FIFO_SRC_REG_A)?;
+        let overrun = source & SRC_OVERRUN != 0;
+        let count = if source & SRC_EMPTY != 0 {
+            0
+        } else if overrun {
+            LEN
+        } else {
+            usize::from(source & SRC_COUNT)
+        };
+
+        let mut buffer = [0; LEN * 6];
+        let buffer = &mut buffer[..count * 6];
+        if count > 0 {
+            self.i2c
+                .write_read_once(ADDRESS, &[OUT_X_L_A | AUTO_INCREMENT], buffer)?;
+        }
+
+        let sensitivity = AccelSensitivity {
+            chip: self.config.chip,
+            range: self.config.range,
+            resolution: Resolution::High,
+        };
+        for (sample, bytes) in samples.iter_mut().zip(buffer.chunks_exact(6)) {
+            for (axis, pair) in sample.iter_mut().zip(bytes.chunks_exact(2)) {
+                *axis = sensitivity.acceleration(i16::from_le_bytes([pair[0], pair[1]]));
+            }
+        }
+        Ok(Drained { count, overrun })
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(ADDRESS, &[register, value])
+    }
+
+    fn read(&mut self, register: u8) -> Result<u8, E> {
+        let mut value = [0];
+        self.i2c.write_read(ADDRESS, &[register], &mut value)?;
+        Ok(value[0])
+    }
+
+    /// Reads a register that clears something when it is read
+    fn read_once(&mut self, register: u8) -> Result<u8, E> {
+        let mut value = [0];
+        self.i2c.write_read_once(ADDRESS, &[register], &mut value)?;
+        Ok(value[0])
+    }
+
+    fn route(&mut self, interrupt: u8) -> Result<(), E> {
+        self.int1 |= interrupt;
+        self.write(CTRL_REG3_A, self.int1)
+    }
+
+    /// The threshold registers have 7 bits for the full scale
+    fn threshold(&self, threshold: Acceleration) -> u8 {
+        let mg_per_lsb = match self.config.range {
+            Range::G2 => 16,
+            Range::G4 => 32,
+            Range::G8 => 62,
+            Range::G16 => 186,
+        };
+        (threshold.mg() / mg_per_lsb).clamp(1, 127) as u8
+    }
+
+    /// Durations are counted in samples
+    fn samples(&self, ms: u32) -> u8 {
+        // Long durations at high rates do not fit into a `u32`
+        let samples = u64::from(ms) * u64::from(self.config.data_rate.hz()) / 1000;
+        samples.clamp(1, MAX_DURATION) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    const CONFIG: Config = Config {
+        chip: Chip::Lsm303dlhc,
+        data_rate: DataRate::Hz400,
+        range: Range::G2,
+        watermark: 16,
+    };
+
+    /// The accelerometer's registers, and the samples waiting in its FIFO
+    struct Bus {
+        registers: [u8; 0x40],
+        fifo: Vec<u8>,
+        writes: Vec<(u8, u8)>,
+        /// Registers read with `write_read_once`
+        read_once: Vec<u8>,
+    }
+
+    impl Bus {
+        fn new() -> Bus {
+            Bus {
+                registers: [0; 0x40],
+                fifo: Vec::new(),
+                writes: Vec::new(),
+                read_once: Vec::new(),
+            }
+        }
+    }
+
+    impl Write for Bus {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            assert_eq!(address, ADDRESS);
+            assert_eq!(bytes.len(), 2);
+            self.writes.push((bytes[0], bytes[1]));
+            Ok(())
+        }
+    }
+
+    impl WriteRead for Bus {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            assert_eq!(address, ADDRESS);
+            assert_eq!(buffer.len(), 1);
+            buffer[0] = self.registers[usize::from(bytes[0])];
+            Ok(())
+        }
+    }
+
+    impl WriteReadOnce for Bus {
+        fn write_read_once(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), ()> {
+            assert_eq!(address, ADDRESS);
+            self.read_once.push(bytes[0]);
+            if bytes[0] == OUT_X_L_A | AUTO_INCREMENT {
+                let bytes: Vec<u8> = self.fifo.drain(..buffer.len()).collect();
+                buffer.copy_from_slice(&bytes);
+            } else {
+                buffer[0] = self.registers[usize::from(bytes[0])];
+            }
+            Ok(())
+        }
+    }
+
+    fn started(config: Config) -> AccelFifo<Bus> {
+        let mut fifo = AccelFifo::new(Bus::new(), config);
+        fifo.start().unwrap();
+        fifo
+    }
+
+    #[test]
+    fn starts_in_stream_mode() {
+        let fifo = AccelFifo::new(Bus::new(), CONFIG);
+        // Nothing is sent before `start`
+        assert!(fifo.i2c.writes.is_empty());
+
+        let mut fifo = fifo;
+        fifo.start().unwrap();
+        assert_eq!(
+            fifo.release().writes,
+            [
+                (CTRL_REG1_A, 0b0111_0111),
+                (CTRL_REG4_A, 0b0000_1000),
+                (CTRL_REG5_A, FIFO_EN),
+                (FIFO_CTRL_REG_A, MODE_BYPASS),
+                (FIFO_CTRL_REG_A, MODE_STREAM | 16),
+                (CTRL_REG3_A, I1_WTM),
+            ]
+        );
+    }
+
+    #[test]
+    fn sets_rate_and_range() {
+        let fifo = started(Config {
+            data_rate: DataRate::Hz1344,
+            range: Range::G16,
+            ..CONFIG
+        });
+        let writes = fifo.release().writes;
+        assert_eq!(writes[0], (CTRL_REG1_A, 0b1001_0111));
+        assert_eq!(writes[1], (CTRL_REG4_A, 0b0011_1000));
+    }
+
+    #[test]
+    fn clamps_the_watermark() {
+        for (watermark, expected) in [(0, 1), (1, 1), (31, 31), (32, 31), (u8::MAX, 31)] {
+            let writes = started(Config {
+                watermark,
+                ..CONFIG
+            })
+            .release()
+            .writes;
+            assert_eq!(writes[4], (FIFO_CTRL_REG_A, MODE_STREAM | expected));
+        }
+    }
+
+    #[test]
+    fn starting_again_turns_the_events_off() {
+        let mut fifo = started(CONFIG);
+        fifo.enable_taps(Acceleration::from_mg(1_000), 10).unwrap();
+        fifo.enable_freefall(Acceleration::from_mg(300), 100)
+            .unwrap();
+        assert_eq!(
+            fifo.i2c.writes.last(),
+            Some(&(CTRL_REG3_A, I1_WTM | I1_CLICK | I1_AOI1))
+        );
+        fifo.events().unwrap();
+        assert_eq!(fifo.i2c.read_once, [CLICK_SRC_A, INT1_SRC_A]);
+
+        fifo.start().unwrap();
+        assert_eq!(fifo.i2c.writes.last(), Some(&(CTRL_REG3_A, I1_WTM)));
+        fifo.i2c.read_once.clear();
+        assert_eq!(fifo.events(), Ok(Events::default()));
+        assert!(fifo.i2c.read_once.is_empty());
+    }
+
+    #[test]
+    fn drains_an_empty_fifo() {
+        let mut fifo = started(CONFIG);
+        fifo.i2c.registers[usize::from(FIFO_SRC_REG_A)] = SRC_EMPTY;
+        let mut samples = [[Acceleration::ZERO; 3]; LEN];
+        assert_eq!(
+            fifo.drain(&mut samples),
+            Ok(Drained {
+                count: 0,
+                overrun: false
+            })
+        );
+        // No burst read for nothing
+        assert!(fifo.i2c.read_once.is_empty());
+    }
+
+    #[test]
+    fn drains_the_waiting_samples() {
+        let mut fifo = started(CONFIG);
+        fifo.i2c.registers[usize::from(FIFO_SRC_REG_A)] = 3;
+        fifo.i2c.fifo = (0..LEN as u8 * 6).collect();
+        let mut samples = [[Acceleration::ZERO; 3]; LEN];
+        assert_eq!(
+            fifo.drain(&mut samples),
+            Ok(Drained {
+                count: 3,
+                overrun: false
+            })
+        );
+        assert_eq!(fifo.i2c.read_once, [OUT_X_L_A | AUTO_INCREMENT]);
+        // Only the waiting samples were read
+        assert_eq!(fifo.i2c.fifo.len(), (LEN - 3) * 6);
+    }
+
+    #[test]
+    fn drains_a_full_fifo_after_an_overrun() {
+        let mut fifo = started(CONFIG);
+        // The count stops at 31, the FIFO holds one more
+        fifo.i2c.registers[usize::from(FIFO_SRC_REG_A)] = SRC_OVERRUN | SRC_COUNT;
+        fifo.i2c.fifo = [0; LEN * 6].to_vec();
+        let mut samples = [[Acceleration::ZERO; 3]; LEN];
+        assert_eq!(
+            fifo.drain(&mut samples),
+            Ok(Drained {
+                count: LEN,
+                overrun: true
+            })
+        );
+        assert!(fifo.i2c.fifo.is_empty());
+    }
+
+    #[test]
+    fn decodes_left_aligned_little_endian_samples() {
+        let mut fifo = started(CONFIG);
+        fifo.i2c.registers[usize::from(FIFO_SRC_REG_A)] = 2;
+        fifo.i2c.fifo = [
+            // 1 g, 1 mg and -1 mg, the 12 bits are in the upper end
+            [0x00, 0x40, 0x10, 0x00, 0xf0, 0xff],
+            // The low 4 bits are dropped, -2 g
+            [0x0f, 0x00, 0x00, 0x00, 0x00, 0x80],
+        ]
+        .concat();
+        let mut samples = [[Acceleration::ZERO; 3]; LEN];
+        fifo.drain(&mut samples).unwrap();
+        assert_eq!(samples[0].map(Acceleration::mg), [1_024, 1, -1],);
+        assert_eq!(samples[1].map(Acceleration::mg), [0, 0, -2_048]);
+    }
+
+    #[test]
+    fn clamps_thresholds() {
+        let fifo = started(CONFIG);
+        // 16 mg per LSB at 2 g
+        assert_eq!(fifo.threshold(Acceleration::from_mg(160)), 10);
+        assert_eq!(fifo.threshold(Acceleration::ZERO), 1);
+        assert_eq!(fifo.threshold(Acceleration::from_mg(-500)), 1);
+        assert_eq!(fifo.threshold(Acceleration::from_mg(10_000)), 127);
+
+        let fifo = started(Config {
+            range: Range::G16,
+            ..CONFIG
+        });
+        assert_eq!(fifo.threshold(Acceleration::from_mg(1_860)), 10);
+        assert_eq!(fifo.threshold(Acceleration::from_mg(i32::MAX)), 127);
+    }
+
+    #[test]
+    fn clamps_durations() {
+        let fifo = started(CONFIG);
+        // 400 Hz
+        assert_eq!(fifo.samples(10), 4);
+        assert_eq!(fifo.samples(0), 1);
+        assert_eq!(fifo.samples(1_000), 127);
+        assert_eq!(fifo.samples(u32::MAX), 127);
+
+        let fifo = started(Config {
+            data_rate: DataRate::Hz1344,
+            ..CONFIG
+        });
+        assert_eq!(fifo.samples(50), 67);
+        assert_eq!(fifo.samples(u32::MAX / 1000), 127);
+    }
+}
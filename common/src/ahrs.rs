@@ -0,0 +1,367 @@
+//! Attitude and heading reference system: estimates the full orientation of
+//! the board by fusing accelerometer, magnetometer and, if the board has one,
+//! gyroscope readings.
+//!
+//! This is Mahony's complementary filter on the special orthogonal group. The
+//! gyroscope is integrated for fast response, while the directions of gravity
+//! and of the Earth's magnetic field slowly pull the estimate back and take
+//! out the gyroscope's drift. Without a gyroscope it still converges, just
+//! more slowly.
+//!
+//! Readings are in the board frame `tilt` describes: x points to the right
+//! edge, y to the top edge and z out of the display. The Earth frame is
+//! east, north, up, so the board lying flat with its top edge pointing to
+//! magnetic north has no rotation at all.
+
+use core::ops::Mul;
+use libm::{asinf, atan2f, cosf, sinf, sqrtf};
+
+use crate::angle::normalize;
+use crate::tilt;
+use crate::vector::Vector3;
+
+/// A rotation as a unit quaternion `w + xi + yj + zk`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Default for Quaternion {
+    fn default() -> Quaternion {
+        Quaternion::IDENTITY
+    }
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+
+    pub const fn new(w: f32, x: f32, y: f32, z: f32) -> Quaternion {
+        Quaternion { w, x, y, z }
+    }
+
+    pub fn conjugate(self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn norm(self) -> f32 {
+        sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z)
+    }
+
+    /// Scales the quaternion to length 1, a zero quaternion becomes the identity
+    pub fn normalize(self) -> Quaternion {
+        let norm = self.norm();
+        if norm == 0.0 {
+            Quaternion::IDENTITY
+        } else {
+            Quaternion::new(self.w / norm, self.x / norm, self.y / norm, self.z / norm)
+        }
+    }
+
+    /// Rotation by `degrees` around `axis`, which has to be a unit vector
+    pub fn from_axis_angle(axis: Vector3, degrees: f32) -> Quaternion {
+        let half = degrees.to_radians() / 2.0;
+        let sin = sinf(half);
+        Quaternion::new(cosf(half), axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    pub fn from_euler(euler: Euler) -> Quaternion {
+        let yaw = Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), euler.yaw);
+        let pitch = Quaternion::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), euler.pitch);
+        let roll = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), euler.roll);
+        yaw * pitch * roll
+    }
+
+    /// Applies the rotation to a vector
+    pub fn rotate(self, v: Vector3) -> Vector3 {
+        let rotated = self * Quaternion::new(0.0, v.x, v.y, v.z) * self.conjugate();
+        Vector3::new(rotated.x, rotated.y, rotated.z)
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// The Hamilton product, `self` is applied after `other`
+    fn mul(self, other: Quaternion) -> Quaternion {
+        let (a, b) = (self, other);
+        Quaternion::new(
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        )
+    }
+}
+
+/// Orientation as Tait-Bryan angles in degrees: starting flat and pointing
+/// north, the board is turned by `yaw`, then tilted by `pitch`, then by `roll`.
+/// Pitch and roll match `tilt::Attitude`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Euler {
+    /// Rotation around the board's x axis, positive while the top edge is
+    /// raised, within `[-90, 90]`
+    pub pitch: f32,
+    /// Rotation around the board's y axis, positive while the right edge is
+    /// lowered, within `[-180, 180]`
+    pub roll: f32,
+    /// Rotation around the vertical axis, counterclockwise seen from above
+    /// and 0 while the top edge points to magnetic north
+    pub yaw: f32,
+}
+
+/// How strongly the accelerometer and magnetometer correct the estimate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains {
+    /// Proportional gain, larger values converge faster but follow noise more
+    pub kp: f32,
+    /// Integral gain, learns the gyroscope's bias. 0 without a gyroscope.
+    pub ki: f32,
+}
+
+impl Default for Gains {
+    fn default() -> Gains {
+        Gains { kp: 1.0, ki: 0.0 }
+    }
+}
+
+pub struct Mahony {
+    /// Rotates vectors from the board frame into the Earth frame
+    orientation: Quaternion,
+    /// Time between two updates in s
+    period: f32,
+    gains: Gains,
+    /// Integral of the error, the estimated gyroscope bias in rad/s
+    bias: Vector3,
+    /// Whether `orientation` has been set from a first reading yet
+    initialized: bool,
+}
+
+impl Mahony {
+    /// `sample_rate` is how often `update` is called, in Hz
+    pub fn new(sample_rate: f32, gains: Gains) -> Mahony {
+        Mahony {
+            orientation: Quaternion::IDENTITY,
+            period: 1.0 / sample_rate,
+            gains,
+            bias: Vector3::default(),
+            initialized: false,
+        }
+    }
+
+    pub fn orientation(&self) -> Quaternion {
+        self.orientation
+    }
+
+    pub fn euler(&self) -> Euler {
+        // Elements of the rotation matrix, which is yaw * pitch * roll
+        let Quaternion { w, x, y, z } = self.orientation;
+        let r01 = 2.0 * (x * y - w * z);
+        let r11 = 1.0 - 2.0 * (x * x + z * z);
+        let r20 = 2.0 * (x * z - w * y);
+        let r21 = 2.0 * (y * z + w * x);
+        let r22 = 1.0 - 2.0 * (x * x + y * y);
+        Euler {
+            pitch: asinf(r21.clamp(-1.0, 1.0)).to_degrees(),
+            roll: atan2f(-r20, r22).to_degrees(),
+            yaw: atan2f(-r01, r11).to_degrees(),
+        }
+    }
+
+    /// Heading of the top edge of the board in degrees, clockwise from
+    /// magnetic north like `tilt::heading`
+    pub fn heading(&self) -> f32 {
+        normalize(-self.euler().yaw)
+    }
+
+    /// Feeds one set of readings. `gyro` is the angular rate in rad/s, the
+    /// other two can be in any unit. A zero reading is ignored.
+    pub fn update(&mut self, gyro: Option<Vector3>, accel: Vector3, mag: Vector3) {
+        if !self.initialized && accel.norm() > 0.0 && mag.norm() > 0.0 {
+            // Start from the static estimate, converging from far off is slow
+            let attitude = tilt::attitude(accel);
+            self.orientation = Quaternion::from_euler(Euler {
+                pitch: attitude.pitch.to_degrees(),
+                roll: attitude.roll.to_degrees(),
+                yaw: -tilt::heading(mag, accel),
+            });
+            self.initialized = true;
+            return;
+        }
+
+        let mut error = Vector3::default();
+        let q = self.orientation;
+
+        if accel.norm() > 0.0 {
+            // Where gravity should point to if the estimate was right, the
+            // accelerometer measures the reaction to it, pointing up
+            let up = q.conjugate().rotate(Vector3::new(0.0, 0.0, 1.0));
+            error = error + accel.normalize().cross(up);
+        }
+        if mag.norm() > 0.0 {
+            // Only trust the magnetometer for the heading: take its measured
+            // inclination and only expect it to point north horizontally
+            let mag = mag.normalize();
+            let field = q.rotate(mag);
+            let horizontal = sqrtf(field.x * field.x + field.y * field.y);
+            let expected = q.conjugate().rotate(Vector3::new(0.0, horizontal, field.z));
+            error = error + mag.cross(expected);
+        }
+
+        if self.gains.ki > 0.0 {
+            self.bias = self.bias + error * (self.gains.ki * self.period);
+        }
+        let rate = gyro.unwrap_or_default() + self.bias + error * self.gains.kp;
+
+        let change = q * Quaternion::new(0.0, rate.x, rate.y, rate.z);
+        self.orientation = Quaternion::new(
+            q.w + 0.5 * change.w * self.period,
+            q.x + 0.5 * change.x * self.period,
+            q.y + 0.5 * change.y * self.period,
+            q.z + 0.5 * change.z * self.period,
+        )
+        .normalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::angle::distance;
+
+    /// Earth's field and the accelerometer's reaction to gravity in east,
+    /// north, up coordinates
+    const FIELD: Vector3 = Vector3::new(0.0, 20_000.0, -44_000.0);
+    const UP: Vector3 = Vector3::new(0.0, 0.0, 1_000.0);
+    const SAMPLE_RATE: f32 = 50.0;
+    const UP_AXIS: Vector3 = Vector3::new(0.0, 0.0, 1.0);
+
+    /// What the accelerometer and magnetometer read in `orientation`
+    fn readings(orientation: Quaternion) -> (Vector3, Vector3) {
+        let to_board = orientation.conjugate();
+        (to_board.rotate(UP), to_board.rotate(FIELD))
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            distance(actual, expected) <= tolerance,
+            "{} is not {} ± {}",
+            actual,
+            expected,
+            tolerance
+        );
+    }
+
+    fn assert_euler(actual: Euler, expected: Euler, tolerance: f32) {
+        assert_close(actual.pitch, expected.pitch, tolerance);
+        assert_close(actual.roll, expected.roll, tolerance);
+        assert_close(actual.yaw, expected.yaw, tolerance);
+    }
+
+    #[test]
+    fn euler_round_trip() {
+        for &(pitch, roll, yaw) in &[(0.0, 0.0, 0.0), (20.0, -10.0, -21.4), (-60.0, 150.0, 100.0)] {
+            let euler = Euler { pitch, roll, yaw };
+            let mut ahrs = Mahony::new(SAMPLE_RATE, Gains::default());
+            ahrs.orientation = Quaternion::from_euler(euler);
+            assert_euler(ahrs.euler(), euler, 0.01);
+        }
+    }
+
+    #[test]
+    fn starts_from_the_first_reading() {
+        let euler = Euler {
+            pitch: 20.0,
+            roll: -10.0,
+            yaw: -21.4,
+        };
+        let (accel, mag) = readings(Quaternion::from_euler(euler));
+        let mut ahrs = Mahony::new(SAMPLE_RATE, Gains::default());
+        ahrs.update(None, accel, mag);
+        assert_euler(ahrs.euler(), euler, 0.1);
+        assert_close(ahrs.heading(), 21.4, 0.1);
+    }
+
+    #[test]
+    fn converges_without_a_gyroscope() {
+        let mut ahrs = Mahony::new(SAMPLE_RATE, Gains { kp: 5.0, ki: 0.0 });
+        ahrs.update(None, UP, FIELD);
+        // The board is turned and tilted while the filter isn't looking
+        let euler = Euler {
+            pitch: -30.0,
+            roll: 40.0,
+            yaw: 120.0,
+        };
+        let (accel, mag) = readings(Quaternion::from_euler(euler));
+        for _ in 0..(30.0 * SAMPLE_RATE) as usize {
+            ahrs.update(None, accel, mag);
+        }
+        assert_euler(ahrs.euler(), euler, 0.5);
+    }
+
+    /// Turns the board, its top edge raised by 15°, at `rate` degrees per
+    /// second around the vertical axis for `seconds`. Returns the final yaw.
+    fn spin(ahrs: &mut Mahony, with_gyro: bool, rate: f32, seconds: f32) -> f32 {
+        let tilt = Quaternion::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), 15.0);
+        let steps = (seconds * SAMPLE_RATE) as usize;
+        let (accel, mag) = readings(tilt);
+        ahrs.update(None, accel, mag);
+        for step in 1..=steps {
+            let yaw = rate * step as f32 / SAMPLE_RATE;
+            let orientation = Quaternion::from_axis_angle(UP_AXIS, yaw) * tilt;
+            let (accel, mag) = readings(orientation);
+            // The gyroscope measures the turn in the board frame
+            let gyro = orientation
+                .conjugate()
+                .rotate(Vector3::new(0.0, 0.0, rate.to_radians()));
+            ahrs.update(with_gyro.then_some(gyro), accel, mag);
+        }
+        rate * seconds
+    }
+
+    #[test]
+    fn gyroscope_follows_fast_turns() {
+        let gains = Gains { kp: 0.5, ki: 0.0 };
+        let mut with_gyro = Mahony::new(SAMPLE_RATE, gains);
+        let yaw = spin(&mut with_gyro, true, 180.0, 1.5);
+        assert_close(with_gyro.euler().yaw, yaw, 1.0);
+        assert_close(with_gyro.euler().pitch, 15.0, 1.0);
+
+        // The magnetometer alone lags far behind
+        let mut without = Mahony::new(SAMPLE_RATE, gains);
+        spin(&mut without, false, 180.0, 1.5);
+        assert!(
+            distance(without.euler().yaw, yaw) > 20.0,
+            "{:?}",
+            without.euler()
+        );
+    }
+
+    #[test]
+    fn learns_the_gyroscope_bias() {
+        let bias = Vector3::new(0.02, -0.03, 0.05);
+        let euler = Euler {
+            pitch: 10.0,
+            roll: 5.0,
+            yaw: 45.0,
+        };
+        let (accel, mag) = readings(Quaternion::from_euler(euler));
+        let mut ahrs = Mahony::new(SAMPLE_RATE, Gains { kp: 1.0, ki: 0.3 });
+        // The integral swings around the bias for a while before it settles
+        for _ in 0..(120.0 * SAMPLE_RATE) as usize {
+            ahrs.update(Some(bias), accel, mag);
+        }
+        assert!((ahrs.bias + bias).norm() < 0.001, "{:?}", ahrs.bias);
+        assert_euler(ahrs.euler(), euler, 0.2);
+    }
+
+    #[test]
+    fn ignores_missing_readings() {
+        let mut ahrs = Mahony::new(SAMPLE_RATE, Gains::default());
+        ahrs.update(None, UP, FIELD);
+        ahrs.update(None, Vector3::default(), Vector3::default());
+        assert_eq!(ahrs.orientation(), Quaternion::IDENTITY);
+    }
+}
@@ -0,0 +1,50 @@
+//! Helpers for compass angles in degrees.
+
+use libm::fabsf;
+
+/// Wraps any angle into `[0, 360)`
+pub fn normalize(degrees: f32) -> f32 {
+    let degrees = degrees % 360.0;
+    // Adding 360 to a tiny negative angle rounds to 360 itself
+    match degrees {
+        degrees if degrees < 0.0 && degrees + 360.0 < 360.0 => degrees + 360.0,
+        degrees if degrees < 0.0 => 0.0,
+        degrees => degrees,
+    }
+}
+
+/// The signed angle to turn from `from` to `to` the short way, in `[-180, 180)`
+pub fn difference(from: f32, to: f32) -> f32 {
+    normalize(to - from + 180.0) - 180.0
+}
+
+/// How far apart two angles are, in `[0, 180]`
+pub fn distance(a: f32, b: f32) -> f32 {
+    fabsf(difference(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_into_one_turn() {
+        assert_eq!(normalize(0.0), 0.0);
+        assert_eq!(normalize(360.0), 0.0);
+        assert_eq!(normalize(370.0), 10.0);
+        assert_eq!(normalize(-90.0), 270.0);
+        assert_eq!(normalize(-720.0), 0.0);
+        // Would round to 360 otherwise
+        assert_eq!(normalize(-1e-6), 0.0);
+    }
+
+    #[test]
+    fn differences_take_the_short_way() {
+        assert_eq!(difference(350.0, 10.0), 20.0);
+        assert_eq!(difference(10.0, 350.0), -20.0);
+        assert_eq!(difference(0.0, 180.0), -180.0);
+        assert_eq!(distance(350.0, 10.0), 20.0);
+        assert_eq!(distance(-90.0, 270.0), 0.0);
+        assert_eq!(distance(0.0, 180.0), 180.0);
+    }
+}
@@ -0,0 +1,396 @@
+//! Turns the raw levels of two push buttons into events like clicks, double
+//! clicks and long presses.
+//!
+//! `Recognizer` knows nothing about pins or interrupts: the board feeds it
+//! every level change together with a timestamp in ms, and calls `poll`
+//! regularly so timeouts are noticed even while nothing changes. Timestamps
+//! may wrap around.
+//!
+//! A click is only reported once the double click window passed without a
+//! second press. Pressing one button while the other is held down is a chord,
+//! neither button then reports a click or long press for that press.
+
+/// The two buttons on the front of the micro:bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ButtonEvent {
+    Pressed(Button),
+    Released(Button),
+    Click(Button),
+    DoubleClick(Button),
+    /// Reported while the button is still held down
+    LongPress(Button),
+    /// Both buttons pressed together
+    Chord,
+}
+
+/// Timing thresholds in ms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// How long a level has to be stable before it counts
+    pub debounce_ms: u32,
+    /// The longest time from releasing a button to pressing it again for a
+    /// double click. 0 reports every click right away.
+    pub double_click_ms: u32,
+    /// How long a button has to be held down for a long press
+    pub long_press_ms: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            debounce_ms: 20,
+            double_click_ms: 300,
+            long_press_ms: 800,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    /// Level as last reported by the board, `true` while pressed
+    raw: bool,
+    /// When `raw` last changed
+    raw_since: u32,
+    /// The debounced level
+    pressed: bool,
+    pressed_at: u32,
+    /// A long press was reported for the current press
+    long_press: bool,
+    /// The current press is part of a chord
+    chord: bool,
+    /// The current press is the second one of a double click
+    second: bool,
+    /// When the button was released after a click that might become a
+    /// double click
+    click_at: Option<u32>,
+}
+
+pub struct Recognizer {
+    config: Config,
+    buttons: [State; 2],
+}
+
+impl Recognizer {
+    pub fn new(config: Config) -> Recognizer {
+        Recognizer {
+            config,
+            buttons: [State::default(); 2],
+        }
+    }
+
+    /// Feeds a level change, `pressed` is the new level of `button`
+    pub fn input<F: FnMut(ButtonEvent)>(
+        &mut self,
+        button: Button,
+        pressed: bool,
+        now: u32,
+        mut emit: F,
+    ) {
+        // Settle the previous level first, it might have been stable long enough
+        self.poll(now, &mut emit);
+        let state = &mut self.buttons[button as usize];
+        if state.raw != pressed {
+            state.raw = pressed;
+            state.raw_since = now;
+        }
+    }
+
+    /// Reports what happened up to `now`
+    pub fn poll<F: FnMut(ButtonEvent)>(&mut self, now: u32, mut emit: F) {
+        for button in [Button::A, Button::B] {
+            let state = self.buttons[button as usize];
+            if state.raw != state.pressed
+                && now.wrapping_sub(state.raw_since) >= self.config.debounce_ms
+            {
+                if state.raw {
+                    self.press(button, state.raw_since, &mut emit);
+                } else {
+                    self.release(button, state.raw_since, &mut emit);
+                }
+            }
+        }
+
+        for button in [Button::A, Button::B] {
+            let state = &mut self.buttons[button as usize];
+            if state.pressed
+                && !state.long_press
+                && !state.chord
+                && now.wrapping_sub(state.pressed_at) >= self.config.long_press_ms
+            {
+                state.long_press = true;
+                state.second = false;
+                emit(ButtonEvent::LongPress(button));
+            }
+            if let Some(click_at) = state.click_at {
+                if now.wrapping_sub(click_at) > self.config.double_click_ms {
+                    state.click_at = None;
+                    emit(ButtonEvent::Click(button));
+                }
+            }
+        }
+    }
+
+    fn press<F: FnMut(ButtonEvent)>(&mut self, button: Button, at: u32, emit: &mut F) {
+        let other = &mut self.buttons[other(button) as usize];
+        let chord = other.pressed;
+        if chord {
+            other.chord = true;
+            // The other button's press is not a click after all
+            other.click_at = None;
+        }
+
+        let state = &mut self.buttons[button as usize];
+        state.pressed = true;
+        state.pressed_at = at;
+        state.long_press = false;
+        state.chord = chord;
+        state.second = !chord
+            && matches!(state.click_at, Some(click_at)
+                if at.wrapping_sub(click_at) <= self.config.double_click_ms);
+        state.click_at = None;
+        emit(ButtonEvent::Pressed(button));
+        if chord {
+            emit(ButtonEvent::Chord);
+        }
+    }
+
+    fn release<F: FnMut(ButtonEvent)>(&mut self, button: Button, at: u32, emit: &mut F) {
+        let state = &mut self.buttons[button as usize];
+        state.pressed = false;
+        emit(ButtonEvent::Released(button));
+        if state.chord || state.long_press {
+            return;
+        }
+        if state.second {
+            state.second = false;
+            emit(ButtonEvent::DoubleClick(button));
+        } else if self.config.double_click_ms == 0 {
+            emit(ButtonEvent::Click(button));
+        } else {
+            state.click_at = Some(at);
+        }
+    }
+}
+
+fn other(button: Button) -> Button {
+    match button {
+        Button::A => Button::B,
+        Button::B => Button::A,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ButtonEvent::*;
+
+    /// Events with the time they were reported at
+    struct Trace {
+        events: [Option<(u32, ButtonEvent)>; 16],
+        len: usize,
+    }
+
+    impl Trace {
+        fn events(&self) -> impl Iterator<Item = ButtonEvent> + '_ {
+            self.events[..self.len]
+                .iter()
+                .flatten()
+                .map(|&(_, event)| event)
+        }
+
+        fn time_of(&self, event: ButtonEvent) -> u32 {
+            self.events[..self.len]
+                .iter()
+                .flatten()
+                .find(|&&(_, e)| e == event)
+                .unwrap()
+                .0
+        }
+    }
+
+    /// Plays level changes given as (ms after `start`, button, pressed) and
+    /// polls every ms like the main loop does, for `duration` ms
+    fn run(config: Config, start: u32, inputs: &[(u32, Button, bool)], duration: u32) -> Trace {
+        let mut recognizer = Recognizer::new(config);
+        let mut trace = Trace {
+            events: [None; 16],
+            len: 0,
+        };
+        for step in 0..duration {
+            let now = start.wrapping_add(step);
+            let mut emit = |event| {
+                trace.events[trace.len] = Some((step, event));
+                trace.len += 1;
+            };
+            for &(_, button, pressed) in inputs.iter().filter(|input| input.0 == step) {
+                recognizer.input(button, pressed, now, &mut emit);
+            }
+            recognizer.poll(now, &mut emit);
+        }
+        trace
+    }
+
+    fn assert_events(trace: &Trace, expected: &[ButtonEvent]) {
+        assert!(
+            trace.events().eq(expected.iter().copied()),
+            "{:?}",
+            &trace.events[..trace.len]
+        );
+    }
+
+    #[test]
+    fn click() {
+        let inputs = [(100, Button::A, true), (200, Button::A, false)];
+        let trace = run(Config::default(), 0, &inputs, 1_000);
+        assert_events(
+            &trace,
+            &[Pressed(Button::A), Released(Button::A), Click(Button::A)],
+        );
+        assert_eq!(trace.time_of(Pressed(Button::A)), 120);
+        assert_eq!(trace.time_of(Released(Button::A)), 220);
+        // Only once no second press can follow
+        assert_eq!(trace.time_of(Click(Button::A)), 501);
+    }
+
+    #[test]
+    fn debounces_bouncing_contacts() {
+        let inputs = [
+            (100, Button::B, true),
+            (103, Button::B, false),
+            (105, Button::B, true),
+            (300, Button::B, false),
+            (302, Button::B, true),
+            (304, Button::B, false),
+        ];
+        let trace = run(Config::default(), 0, &inputs, 1_000);
+        assert_events(
+            &trace,
+            &[Pressed(Button::B), Released(Button::B), Click(Button::B)],
+        );
+        assert_eq!(trace.time_of(Pressed(Button::B)), 125);
+        assert_eq!(trace.time_of(Released(Button::B)), 324);
+    }
+
+    #[test]
+    fn double_click() {
+        let inputs = [
+            (100, Button::A, true),
+            (200, Button::A, false),
+            (350, Button::A, true),
+            (450, Button::A, false),
+        ];
+        let trace = run(Config::default(), 0, &inputs, 2_000);
+        assert_events(
+            &trace,
+            &[
+                Pressed(Button::A),
+                Released(Button::A),
+                Pressed(Button::A),
+                Released(Button::A),
+                DoubleClick(Button::A),
+            ],
+        );
+    }
+
+    #[test]
+    fn two_slow_clicks() {
+        let inputs = [
+            (100, Button::A, true),
+            (200, Button::A, false),
+            (600, Button::A, true),
+            (700, Button::A, false),
+        ];
+        let trace = run(Config::default(), 0, &inputs, 2_000);
+        assert_events(
+            &trace,
+            &[
+                Pressed(Button::A),
+                Released(Button::A),
+                Click(Button::A),
+                Pressed(Button::A),
+                Released(Button::A),
+                Click(Button::A),
+            ],
+        );
+    }
+
+    #[test]
+    fn immediate_clicks_without_double_clicks() {
+        let config = Config {
+            double_click_ms: 0,
+            ..Config::default()
+        };
+        let inputs = [(100, Button::A, true), (200, Button::A, false)];
+        let trace = run(config, 0, &inputs, 1_000);
+        assert_events(
+            &trace,
+            &[Pressed(Button::A), Released(Button::A), Click(Button::A)],
+        );
+        assert_eq!(trace.time_of(Click(Button::A)), 220);
+    }
+
+    #[test]
+    fn long_press() {
+        let inputs = [(100, Button::B, true), (1_500, Button::B, false)];
+        let trace = run(Config::default(), 0, &inputs, 3_000);
+        assert_events(
+            &trace,
+            &[
+                Pressed(Button::B),
+                LongPress(Button::B),
+                Released(Button::B),
+            ],
+        );
+        // While the button is still down
+        assert_eq!(trace.time_of(LongPress(Button::B)), 900);
+    }
+
+    #[test]
+    fn chord() {
+        let inputs = [
+            (100, Button::A, true),
+            (150, Button::B, true),
+            (1_500, Button::A, false),
+            (1_510, Button::B, false),
+        ];
+        let trace = run(Config::default(), 0, &inputs, 3_000);
+        assert_events(
+            &trace,
+            &[
+                Pressed(Button::A),
+                Pressed(Button::B),
+                Chord,
+                Released(Button::A),
+                Released(Button::B),
+            ],
+        );
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let inputs = [
+            (100, Button::A, true),
+            (200, Button::A, false),
+            (350, Button::A, true),
+            (1_500, Button::A, false),
+        ];
+        let trace = run(Config::default(), u32::MAX - 300, &inputs, 3_000);
+        assert_events(
+            &trace,
+            &[
+                Pressed(Button::A),
+                Released(Button::A),
+                Pressed(Button::A),
+                LongPress(Button::A),
+                Released(Button::A),
+            ],
+        );
+        assert_eq!(trace.time_of(LongPress(Button::A)), 1_150);
+    }
+}
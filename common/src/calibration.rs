@@ -0,0 +1,254 @@
+//! Magnetometer calibration and its encodings.
+//!
+//! A calibration removes the hard-iron offset `center` from a sample and then
+//! multiplies it with the soft-iron matrix `scale`, a fixed point number with
+//! `SCALE_SHIFT` fractional bits. What frame the samples are in is up to the
+//! board, it just has to use the same one for calibrating and correcting.
+//!
+//! There are two stable encodings:
+//!
+//! * binary, 13 little endian `i32`: the center, the rows of the scale matrix
+//!   and the radius, see `Calibration::to_bytes`
+//! * text, the same 13 numbers in decimal after a `CAL` tag and separated by
+//!   spaces, which is what `Display` and `FromStr` produce and accept
+//!
+//! Raw samples streamed to a host for calibrating there use the text form of
+//! `Sample`: an `S` tag followed by the magnetometer and accelerometer axes.
+
+use core::fmt;
+use core::num::ParseIntError;
+use core::str::FromStr;
+
+use crate::ellipsoid::EllipsoidFit;
+use crate::fixed::{self, Fixed};
+use crate::vector::Vector3;
+
+/// `Calibration::scale` has this many fractional bits
+pub const SCALE_SHIFT: u32 = 10;
+/// 1.0 in the fixed point format of `Calibration::scale`
+pub const SCALE_ONE: i32 = 1 << SCALE_SHIFT;
+
+/// An element of the soft-iron matrix
+pub type Scale = Fixed<SCALE_SHIFT>;
+
+const CALIBRATION_TAG: &str = "CAL";
+const SAMPLE_TAG: &str = "S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    center: [i32; 3],
+    scale: [[i32; 3]; 3],
+    radius: u32,
+}
+
+impl Default for Calibration {
+    fn default() -> Calibration {
+        Calibration {
+            center: [0; 3],
+            scale: [[SCALE_ONE, 0, 0], [0, SCALE_ONE, 0], [0, 0, SCALE_ONE]],
+            radius: 0,
+        }
+    }
+}
+
+impl Calibration {
+    /// Size of the little endian encoding produced by `to_bytes`
+    pub const ENCODED_LEN: usize = 52;
+
+    pub const fn new(center: [i32; 3], scale: [[i32; 3]; 3], radius: u32) -> Calibration {
+        Calibration {
+            center,
+            scale,
+            radius,
+        }
+    }
+
+    /// The hard-iron offset
+    pub fn center(&self) -> [i32; 3] {
+        self.center
+    }
+
+    /// The soft-iron correction, in units of `SCALE_ONE`
+    pub fn scale(&self) -> [[i32; 3]; 3] {
+        self.scale
+    }
+
+    /// Strength of the field after correction, in the units of the samples
+    pub fn radius(&self) -> u32 {
+        self.radius
+    }
+
+    /// Corrects a sample, saturating instead of overflowing for extreme values
+    pub fn apply(&self, sample: [i32; 3]) -> [i32; 3] {
+        let scale = self.scale.map(|row| row.map(Scale::from_bits));
+        fixed::mul_matrix(scale, fixed::sub(sample, self.center))
+    }
+
+    /// Like `apply`, but without losing precision to the integer math
+    pub fn apply_f32(&self, sample: Vector3) -> Vector3 {
+        let offset = sample
+            - Vector3::new(
+                self.center[0] as f32,
+                self.center[1] as f32,
+                self.center[2] as f32,
+            );
+        let row = |scale: [i32; 3]| {
+            Vector3::new(scale[0] as f32, scale[1] as f32, scale[2] as f32).dot(offset)
+                / SCALE_ONE as f32
+        };
+        Vector3::new(row(self.scale[0]), row(self.scale[1]), row(self.scale[2]))
+    }
+
+    pub fn to_bytes(&self) -> [u8; Calibration::ENCODED_LEN] {
+        let mut bytes = [0; Calibration::ENCODED_LEN];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(self.fields().iter()) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Calibration::ENCODED_LEN]) -> Calibration {
+        let mut fields = [0; 13];
+        for (field, chunk) in fields.iter_mut().zip(bytes.chunks_exact(4)) {
+            *field = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        Calibration::from_fields(fields)
+    }
+
+    /// The numbers both encodings consist of, in order
+    fn fields(&self) -> [i32; 13] {
+        let [row0, row1, row2] = self.scale;
+        [
+            self.center[0],
+            self.center[1],
+            self.center[2],
+            row0[0],
+            row0[1],
+            row0[2],
+            row1[0],
+            row1[1],
+            row1[2],
+            row2[0],
+            row2[1],
+            row2[2],
+            self.radius as i32,
+        ]
+    }
+
+    fn from_fields(fields: [i32; 13]) -> Calibration {
+        Calibration {
+            center: [fields[0], fields[1], fields[2]],
+            scale: [
+                [fields[3], fields[4], fields[5]],
+                [fields[6], fields[7], fields[8]],
+                [fields[9], fields[10], fields[11]],
+            ],
+            radius: fields[12] as u32,
+        }
+    }
+}
+
+impl From<EllipsoidFit> for Calibration {
+    fn from(fit: EllipsoidFit) -> Calibration {
+        let mut scale = [[0; 3]; 3];
+        for (row, fit_row) in scale.iter_mut().zip(fit.transform.iter()) {
+            for (value, fit_value) in row.iter_mut().zip(fit_row.iter()) {
+                *value = Scale::from_f32(*fit_value).to_bits();
+            }
+        }
+        Calibration {
+            center: [
+                fit.center.x as i32,
+                fit.center.y as i32,
+                fit.center.z as i32,
+            ],
+            scale,
+            radius: fit.radius as u32,
+        }
+    }
+}
+
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_fields(f, CALIBRATION_TAG, &self.fields())
+    }
+}
+
+impl FromStr for Calibration {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Calibration, ParseError> {
+        let fields = parse_fields(line, CALIBRATION_TAG)?;
+        if fields[12] < 0 {
+            return Err(ParseError::OutOfRange);
+        }
+        Ok(Calibration::from_fields(fields))
+    }
+}
+
+/// One uncalibrated reading of both sensors
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub mag: [i32; 3],
+    pub accel: [i32; 3],
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [mx, my, mz] = self.mag;
+        let [ax, ay, az] = self.accel;
+        write_fields(f, SAMPLE_TAG, &[mx, my, mz, ax, ay, az])
+    }
+}
+
+impl FromStr for Sample {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Sample, ParseError> {
+        let [mx, my, mz, ax, ay, az] = parse_fields(line, SAMPLE_TAG)?;
+        Ok(Sample {
+            mag: [mx, my, mz],
+            accel: [ax, ay, az],
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line does not start with the expected tag
+    WrongTag,
+    /// The line has too few or too many numbers
+    FieldCount,
+    InvalidNumber(ParseIntError),
+    /// A number is valid but not allowed where it is, e.g. a negative radius
+    OutOfRange,
+}
+
+impl From<ParseIntError> for ParseError {
+    fn from(error: ParseIntError) -> ParseError {
+        ParseError::InvalidNumber(error)
+    }
+}
+
+fn write_fields(f: &mut fmt::Formatter<'_>, tag: &str, fields: &[i32]) -> fmt::Result {
+    f.write_str(tag)?;
+    for field in fields {
+        write!(f, " {}", field)?;
+    }
+    Ok(())
+}
+
+fn parse_fields<const N: usize>(line: &str, tag: &str) -> Result<[i32; N], ParseError> {
+    let mut tokens = line.split_ascii_whitespace();
+    if tokens.next() != Some(tag) {
+        return Err(ParseError::WrongTag);
+    }
+    let mut fields = [0; N];
+    for field in fields.iter_mut() {
+        *field = tokens.next().ok_or(ParseError::FieldCount)?.parse()?;
+    }
+    if tokens.next().is_some() {
+        return Err(ParseError::FieldCount);
+    }
+    Ok(fields)
+}
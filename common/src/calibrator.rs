@@ -0,0 +1,496 @@
+//! The calibration game both boards play, translated from
+//! <https://github.com/lancaster-university/codal-microbit-v2/blob/006abf5566774fbcf674c0c7df27e8a9d20013de/source/MicroBitCompassCalibrator.cpp>
+//!
+//! The board is tilted around until a magnetometer sample was taken in each
+//! of 25 orientations, laid out as a 5x5 grid by how far it is tilted towards
+//! each edge. Coming back to a cell takes another sample while there is room,
+//! more of them only help the fit. The samples are then fitted with one of the
+//! `Algorithm`s. Which
+//! sensor is read and how the game is shown is up to the board, see
+//! `sensor` and `CalibrationUi`.
+
+use embedded_hal::blocking::delay::DelayUs;
+use libm::sqrtf;
+
+use crate::calibration::{Calibration, SCALE_SHIFT};
+use crate::ellipsoid;
+use crate::fixed::{self, Fixed};
+use crate::sensor::{Accelerometer, Magnetometer};
+use crate::vector::Vector3;
+
+/// One sample for every cell of the grid
+const MIN_SAMPLES: usize = 25;
+const MAX_SAMPLES: usize = 64;
+const PIXEL1_THRESHOLD: i32 = 200;
+const PIXEL2_THRESHOLD: i32 = 600;
+const CALIBRATION_INCREMENT: i32 = 200;
+
+/// How long every step of the game shows the grid
+const FRAME_MS: u32 = 200;
+/// How long a rejected calibration is shown before starting over
+const TRY_AGAIN_MS: u32 = 2000;
+/// How long to wait for the sensor to report new data, at 10 Hz it should
+/// take 100 ms at most
+const SENSOR_TIMEOUT_MS: u32 = 500;
+
+/// Middle of the 25 µT to 65 µT the Earth's field ranges over, in nT
+const EARTH_FIELD: f32 = 45_000.0;
+// Thresholds below which `Quality::is_acceptable` rejects a calibration.
+// Samples spread evenly over a cap of the sphere have a coverage of
+// `(1 - cos(angle)) / 2`, so 0.25 asks for a cap reaching at least 60° away
+// from its middle. Tilting the board through the whole grid gets about 70°.
+const MIN_COVERAGE: f32 = 0.25;
+const MAX_RESIDUAL: f32 = 0.1;
+const MAX_SPREAD: f32 = 0.75;
+const MIN_FIELD_RATIO: f32 = 0.4;
+const MAX_FIELD_RATIO: f32 = 1.6;
+
+type Point = [i32; 3];
+/// Q15.16, `spherify` sums up weights of several times 1.0
+type Weight = Fixed<16>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// CODAL's hill climb for the center followed by an axis-aligned scale
+    HillClimb,
+    /// Least-squares ellipsoid fit, also corrects rotated soft-iron effects
+    Ellipsoid,
+}
+
+#[derive(Debug)]
+pub enum CalibrationError<E> {
+    /// The sensor reported an error, even after the bus was recovered
+    Sensor(E),
+    /// The sensor stopped delivering new data
+    Timeout,
+    /// The user gave up, see `Request::Abort`
+    Aborted,
+}
+
+/// What the user can ask for while playing the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Throw away the samples collected so far and start over
+    Restart,
+    /// Give up on the game
+    Abort,
+}
+
+/// How a board shows the game and lets the user interact with it
+pub trait CalibrationUi {
+    /// Shows which cells of the grid have a sample (1) and which do not (0)
+    /// for `ms` milliseconds. `cursor` is the (row, column) of the cell the
+    /// board is tilted towards, `percent` is how much of the current attempt
+    /// is done.
+    fn show_progress<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        collected: [[u8; 5]; 5],
+        cursor: (usize, usize),
+        percent: u8,
+        ms: u32,
+    );
+
+    /// Tells the user for `ms` milliseconds that the calibration was not good
+    /// enough, the game starts over afterwards
+    fn show_rejected<D: DelayUs<u32>>(&mut self, delay: &mut D, ms: u32);
+
+    /// Tells the user the game is over and the calibration was accepted
+    fn show_done<D: DelayUs<u32>>(&mut self, _delay: &mut D) {}
+
+    /// Checked at every step of the game
+    fn request(&mut self) -> Option<Request>;
+
+    /// Called with the quality of every calibration computed, before it is
+    /// accepted or rejected
+    fn report_quality(&mut self, _quality: &Quality) {}
+}
+
+/// Runs the game until it yields a good enough calibration
+pub fn calc_calibration<S, U, D, E>(
+    sensor: &mut S,
+    ui: &mut U,
+    delay: &mut D,
+    algorithm: Algorithm,
+) -> Result<Calibration, CalibrationError<E>>
+where
+    S: Magnetometer<Error = E> + Accelerometer<Error = E>,
+    U: CalibrationUi,
+    D: DelayUs<u32>,
+{
+    loop {
+        let samples = get_data(sensor, ui, delay)?;
+        let data = samples.as_slice();
+        let calibration = match algorithm {
+            Algorithm::HillClimb => hill_climb(data),
+            // Falls back to the hill climb if the samples are degenerate
+            Algorithm::Ellipsoid => fit_ellipsoid(data).unwrap_or_else(|| hill_climb(data)),
+        };
+
+        let quality = Quality::assess(&calibration, data);
+        ui.report_quality(&quality);
+        if quality.is_acceptable() {
+            ui.show_done(delay);
+            return Ok(calibration);
+        }
+        ui.show_rejected(delay, TRY_AGAIN_MS);
+    }
+}
+
+/// How much a calibration can be trusted, judged by the samples it was
+/// computed from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quality {
+    /// How evenly the calibrated samples point in all directions, 0 if they
+    /// all point the same way and close to 1 if they cover the whole sphere
+    pub coverage: f32,
+    /// RMS distance of the calibrated samples from the sphere, relative to its radius
+    pub residual: f32,
+    /// `measure_score` around the center relative to the squared radius
+    pub spread: f32,
+    /// Radius relative to the typical strength of the Earth's field
+    pub field_ratio: f32,
+}
+
+impl Quality {
+    pub fn assess(calibration: &Calibration, data: &[Point]) -> Quality {
+        let radius = calibration.radius() as f32;
+        if data.is_empty() || radius == 0.0 {
+            return Quality {
+                coverage: 0.0,
+                residual: f32::INFINITY,
+                spread: f32::INFINITY,
+                field_ratio: 0.0,
+            };
+        }
+
+        let mut direction_sum = Vector3::default();
+        let mut squared_error = 0.0;
+        for point in data {
+            let corrected = calibration.apply_f32(to_vector(*point));
+            let magnitude = corrected.norm();
+            if magnitude > 0.0 {
+                direction_sum = direction_sum + corrected * (1.0 / magnitude);
+            }
+            let error = (magnitude - radius) / radius;
+            squared_error += error * error;
+        }
+        let samples = data.len() as f32;
+
+        Quality {
+            // One minus the length of the mean direction
+            coverage: 1.0 - direction_sum.norm() / samples,
+            residual: sqrtf(squared_error / samples),
+            spread: measure_score(calibration.center(), data) as f32 / (radius * radius),
+            field_ratio: radius / EARTH_FIELD,
+        }
+    }
+
+    pub fn is_acceptable(&self) -> bool {
+        self.coverage >= MIN_COVERAGE
+            && self.residual <= MAX_RESIDUAL
+            && self.spread <= MAX_SPREAD
+            && (MIN_FIELD_RATIO..=MAX_FIELD_RATIO).contains(&self.field_ratio)
+    }
+}
+
+/// The magnetometer samples of one round of the game
+struct Samples {
+    points: [Point; MAX_SAMPLES],
+    len: usize,
+}
+
+impl Samples {
+    fn as_slice(&self) -> &[Point] {
+        &self.points[..self.len]
+    }
+}
+
+fn get_data<S, U, D, E>(
+    sensor: &mut S,
+    ui: &mut U,
+    delay: &mut D,
+) -> Result<Samples, CalibrationError<E>>
+where
+    S: Magnetometer<Error = E> + Accelerometer<Error = E>,
+    U: CalibrationUi,
+    D: DelayUs<u32>,
+{
+    let mut leds = [[0; 5]; 5];
+    let mut cells = 0;
+    let mut samples = Samples {
+        points: [[0; 3]; MAX_SAMPLES],
+        len: 0,
+    };
+    let mut last_cursor = None;
+
+    while cells < MIN_SAMPLES {
+        match ui.request() {
+            Some(Request::Abort) => return Err(CalibrationError::Aborted),
+            Some(Request::Restart) => {
+                leds = [[0; 5]; 5];
+                cells = 0;
+                samples.len = 0;
+                last_cursor = None;
+            }
+            None => {}
+        }
+        let [x, y, _] = wait_for_data(delay, || sensor.acceleration())?;
+        // Tilting further towards an edge moves the cursor further out
+        let cursor = (grid_index(y), grid_index(x));
+
+        let new_cell = leds[cursor.0][cursor.1] != 1;
+        // Extra samples leave room for one in every cell still missing
+        let room = samples.len < MAX_SAMPLES - (MIN_SAMPLES - cells);
+        if new_cell || (room && last_cursor != Some(cursor)) {
+            samples.points[samples.len] = wait_for_data(delay, || sensor.magnetic_field())?;
+            samples.len += 1;
+        }
+        if new_cell {
+            leds[cursor.0][cursor.1] = 1;
+            cells += 1;
+        }
+        last_cursor = Some(cursor);
+        let percent = (cells * 100 / MIN_SAMPLES) as u8;
+        ui.show_progress(delay, leds, cursor, percent, FRAME_MS);
+    }
+    Ok(samples)
+}
+
+fn grid_index(acceleration: i32) -> usize {
+    if acceleration < -PIXEL2_THRESHOLD {
+        0
+    } else if acceleration < -PIXEL1_THRESHOLD {
+        1
+    } else if acceleration > PIXEL2_THRESHOLD {
+        4
+    } else if acceleration > PIXEL1_THRESHOLD {
+        3
+    } else {
+        2
+    }
+}
+
+/// Polls `read` every millisecond until it returns a reading or
+/// `SENSOR_TIMEOUT_MS` passed
+fn wait_for_data<D, E>(
+    delay: &mut D,
+    mut read: impl FnMut() -> Result<Option<Point>, E>,
+) -> Result<Point, CalibrationError<E>>
+where
+    D: DelayUs<u32>,
+{
+    for _ in 0..SENSOR_TIMEOUT_MS {
+        if let Some(point) = read().map_err(CalibrationError::Sensor)? {
+            return Ok(point);
+        }
+        delay.delay_us(1000);
+    }
+    Err(CalibrationError::Timeout)
+}
+
+fn measure_score(center: Point, data: &[Point]) -> u64 {
+    let mut min_d = fixed::distance_squared(center, data[0]);
+    let mut max_d = min_d;
+
+    for point in data[1..].iter() {
+        let d = fixed::distance_squared(center, *point);
+        if d < min_d {
+            min_d = d;
+        }
+
+        if d > max_d {
+            max_d = d;
+        }
+    }
+
+    max_d - min_d
+}
+
+fn hill_climb(data: &[Point]) -> Calibration {
+    // Approximate a center for the data
+    let mut sum = [0i64; 3];
+    for point in data {
+        for (s, p) in sum.iter_mut().zip(point) {
+            *s += i64::from(*p);
+        }
+    }
+    let center = sum.map(|s| fixed::saturate(s / data.len() as i64));
+
+    let mut best = center;
+    let mut current = center;
+    let mut score = measure_score(current, data);
+
+    // Calculate a fixpoint position
+    loop {
+        for x in [-CALIBRATION_INCREMENT, 0, CALIBRATION_INCREMENT] {
+            for y in [-CALIBRATION_INCREMENT, 0, CALIBRATION_INCREMENT] {
+                for z in [-CALIBRATION_INCREMENT, 0, CALIBRATION_INCREMENT] {
+                    let attempt = fixed::add(current, [x, y, z]);
+
+                    let attempt_score = measure_score(attempt, data);
+                    if attempt_score < score {
+                        score = attempt_score;
+                        best = attempt;
+                    }
+                }
+            }
+        }
+
+        if best == current {
+            break;
+        }
+
+        current = best;
+    }
+
+    spherify(current, data)
+}
+
+fn spherify(center: Point, data: &[Point]) -> Calibration {
+    let mut radius = 0;
+    for point in data {
+        let d = fixed::distance(center, *point);
+        if d > radius {
+            radius = d;
+        }
+    }
+
+    let mut scale = Weight::ZERO;
+    let mut weights = [Weight::ZERO; 3];
+
+    for point in data {
+        let d = fixed::distance(center, *point);
+        if d == 0 {
+            // A sample right on the center says nothing about the shape
+            continue;
+        }
+        let ratio = |value: u32| Weight::from_ratio(i64::from(value), i64::from(d));
+        let s = ratio(radius) - Weight::ONE;
+        scale = scale.max(s);
+
+        let offset = fixed::sub(*point, center);
+        for (weight, o) in weights.iter_mut().zip(offset) {
+            *weight = *weight + s * ratio(o.unsigned_abs());
+        }
+    }
+
+    let wmag = Weight::from_bits(fixed::saturate(i64::from(fixed::norm(
+        weights.map(Weight::to_bits),
+    ))));
+    let axis_scale = |weight: Weight| {
+        let scale = if wmag == Weight::ZERO {
+            Weight::ONE
+        } else {
+            Weight::ONE + scale * (weight / wmag)
+        };
+        scale.convert::<SCALE_SHIFT>().to_bits()
+    };
+
+    Calibration::new(
+        center,
+        [
+            [axis_scale(weights[0]), 0, 0],
+            [0, axis_scale(weights[1]), 0],
+            [0, 0, axis_scale(weights[2])],
+        ],
+        radius,
+    )
+}
+
+fn fit_ellipsoid(data: &[Point]) -> Option<Calibration> {
+    let mut samples = [Vector3::default(); MAX_SAMPLES];
+    for (sample, point) in samples.iter_mut().zip(data) {
+        *sample = to_vector(*point);
+    }
+    ellipsoid::fit(&samples[..data.len()]).map(Calibration::from)
+}
+
+fn to_vector(point: Point) -> Vector3 {
+    Vector3::new(point[0] as f32, point[1] as f32, point[2] as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::SCALE_ONE;
+    use libm::{cosf, sinf};
+
+    const CENTER: Point = [1_000, -2_000, 500];
+    const RADIUS: f32 = 45_000.0;
+
+    /// `count` samples spread evenly over the cap of the sphere around `CENTER`
+    /// reaching up to `degrees` from its top
+    fn cap(degrees: f32, count: usize) -> [Point; MAX_SAMPLES] {
+        let golden_angle = core::f32::consts::PI * (3.0 - sqrtf(5.0));
+        let lowest = cosf(degrees.to_radians());
+        let mut data = [[0; 3]; MAX_SAMPLES];
+        for (i, point) in data.iter_mut().take(count).enumerate() {
+            let z = 1.0 - (1.0 - lowest) * (i as f32 + 0.5) / count as f32;
+            let r = sqrtf(1.0 - z * z);
+            let phi = golden_angle * i as f32;
+            let direction = [r * cosf(phi), r * sinf(phi), z];
+            for axis in 0..3 {
+                point[axis] = CENTER[axis] + (direction[axis] * RADIUS) as i32;
+            }
+        }
+        data
+    }
+
+    fn exact() -> Calibration {
+        Calibration::new(
+            CENTER,
+            [[SCALE_ONE, 0, 0], [0, SCALE_ONE, 0], [0, 0, SCALE_ONE]],
+            RADIUS as u32,
+        )
+    }
+
+    #[test]
+    fn rejects_clustered_samples() {
+        // The board was barely tilted, so even the right calibration can't
+        // be told from a wrong one
+        for &degrees in &[20.0, 50.0] {
+            let data = cap(degrees, MIN_SAMPLES);
+            let quality = Quality::assess(&exact(), &data[..MIN_SAMPLES]);
+            assert!(quality.residual < 0.01, "{:?}", quality);
+            assert!(!quality.is_acceptable(), "{}°: {:?}", degrees, quality);
+        }
+    }
+
+    #[test]
+    fn accepts_samples_from_the_whole_grid() {
+        let data = cap(70.0, MIN_SAMPLES);
+        let quality = Quality::assess(&exact(), &data[..MIN_SAMPLES]);
+        assert!(quality.is_acceptable(), "{:?}", quality);
+    }
+
+    #[test]
+    fn rejects_wrong_center() {
+        let data = cap(180.0, MIN_SAMPLES);
+        let calibration = Calibration::new(
+            [CENTER[0] + 20_000, CENTER[1], CENTER[2]],
+            exact().scale(),
+            exact().radius(),
+        );
+        let quality = Quality::assess(&calibration, &data[..MIN_SAMPLES]);
+        assert!(!quality.is_acceptable(), "{:?}", quality);
+    }
+
+    #[test]
+    fn spherify_saturates_huge_weights() {
+        // Samples next to the center give every axis a weight far beyond
+        // what a `Weight` holds, their length must not wrap to a negative
+        let data = [
+            [1, 0, 0],
+            [0, 1, 0],
+            [0, 0, 1],
+            [100_000, 0, 0],
+            [0, 100_000, 0],
+            [0, 0, 100_000],
+        ];
+        let scale = spherify([0, 0, 0], &data).scale();
+        for axis in 0..3 {
+            assert!(scale[axis][axis] >= SCALE_ONE, "{:?}", scale);
+        }
+    }
+}
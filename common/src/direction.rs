@@ -0,0 +1,148 @@
+//! The eight directions a compass on either board can point to.
+//!
+//! How a direction is drawn is up to the board: the micro:bit shows an arrow
+//! on its LED matrix, the F3 lights one of its eight user LEDs.
+
+use core::fmt;
+
+use crate::angle::normalize;
+
+/// Cardinal and intercardinal directions, clockwise from north
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction {
+    /// All directions, clockwise from north
+    pub const ALL: [Direction; 8] = [
+        Direction::North,
+        Direction::NorthEast,
+        Direction::East,
+        Direction::SouthEast,
+        Direction::South,
+        Direction::SouthWest,
+        Direction::West,
+        Direction::NorthWest,
+    ];
+
+    /// The direction closest to a compass heading, each one covers 45°
+    pub fn from_heading(degrees: f32) -> Direction {
+        Direction::ALL[((normalize(degrees) + 22.5) / 45.0) as usize % 8]
+    }
+
+    /// The heading this direction points to, 0° for north and 90° for east
+    pub fn to_degrees(self) -> f32 {
+        self as u8 as f32 * 45.0
+    }
+
+    /// Turns by `steps` eighths of a full turn, clockwise if positive
+    pub fn rotate(self, steps: i32) -> Direction {
+        // Reducing `steps` first keeps the sum from overflowing
+        Direction::ALL[(self as usize + steps.rem_euclid(8) as usize) % 8]
+    }
+
+    pub fn opposite(self) -> Direction {
+        self.rotate(4)
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::North => "N",
+            Direction::NorthEast => "NE",
+            Direction::East => "E",
+            Direction::SouthEast => "SE",
+            Direction::South => "S",
+            Direction::SouthWest => "SW",
+            Direction::West => "W",
+            Direction::NorthWest => "NW",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::format;
+    use Direction::*;
+
+    #[test]
+    fn splits_the_headings_halfway_between_directions() {
+        assert_eq!(Direction::from_heading(0.0), North);
+        assert_eq!(Direction::from_heading(22.4), North);
+        assert_eq!(Direction::from_heading(22.5), NorthEast);
+        assert_eq!(Direction::from_heading(67.4), NorthEast);
+        assert_eq!(Direction::from_heading(67.5), East);
+        assert_eq!(Direction::from_heading(180.0), South);
+        assert_eq!(Direction::from_heading(337.4), NorthWest);
+        assert_eq!(Direction::from_heading(337.5), North);
+        assert_eq!(Direction::from_heading(359.9), North);
+    }
+
+    #[test]
+    fn takes_headings_outside_of_one_turn() {
+        assert_eq!(Direction::from_heading(360.0), North);
+        assert_eq!(Direction::from_heading(450.0), East);
+        assert_eq!(Direction::from_heading(-22.4), North);
+        assert_eq!(Direction::from_heading(-22.5), North);
+        assert_eq!(Direction::from_heading(-22.6), NorthWest);
+        assert_eq!(Direction::from_heading(-90.0), West);
+        assert_eq!(Direction::from_heading(-1e-6), North);
+    }
+
+    #[test]
+    fn points_to_its_own_heading() {
+        assert_eq!(North.to_degrees(), 0.0);
+        assert_eq!(East.to_degrees(), 90.0);
+        assert_eq!(SouthWest.to_degrees(), 225.0);
+        assert_eq!(NorthWest.to_degrees(), 315.0);
+        for direction in Direction::ALL {
+            assert_eq!(Direction::from_heading(direction.to_degrees()), direction);
+        }
+    }
+
+    #[test]
+    fn rotates_both_ways() {
+        assert_eq!(North.rotate(0), North);
+        assert_eq!(North.rotate(1), NorthEast);
+        assert_eq!(North.rotate(-1), NorthWest);
+        assert_eq!(West.rotate(3), NorthEast);
+        assert_eq!(East.rotate(-10), North);
+        assert_eq!(South.rotate(16), South);
+    }
+
+    #[test]
+    fn rotates_by_any_number_of_steps() {
+        // 2^31 - 1 is 7 modulo 8, -2^31 is 0
+        assert_eq!(NorthWest.rotate(i32::MAX), West);
+        assert_eq!(NorthWest.rotate(i32::MIN), NorthWest);
+        assert_eq!(North.rotate(i32::MIN + 1), NorthEast);
+    }
+
+    #[test]
+    fn opposites() {
+        assert_eq!(North.opposite(), South);
+        assert_eq!(NorthEast.opposite(), SouthWest);
+        assert_eq!(West.opposite(), East);
+        for direction in Direction::ALL {
+            assert_eq!(direction.opposite().opposite(), direction);
+        }
+    }
+
+    #[test]
+    fn displays_the_abbreviation() {
+        let names: [_; 8] = Direction::ALL.map(|direction| format!("{}", direction));
+        assert_eq!(names, ["N", "NE", "E", "SE", "S", "SW", "W", "NW"]);
+    }
+}
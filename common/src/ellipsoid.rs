@@ -0,0 +1,266 @@
+//! Least-squares ellipsoid fit for hard- and soft-iron magnetometer calibration.
+//!
+//! Without distortion the magnetometer readings of a board turned in every
+//! direction lie on a sphere around the origin. Hard-iron effects move its
+//! center, soft-iron effects squash and rotate it into an ellipsoid. We fit the
+//! general quadric
+//!
+//! `a x² + b y² + c z² + 2f yz + 2g xz + 2h xy + 2p x + 2q y + 2r z = 1`
+//!
+//! to the samples and derive the center and the symmetric matrix that maps the
+//! ellipsoid back onto a sphere from it.
+
+use libm::{cbrtf, fabsf, sqrtf};
+
+use crate::vector::Vector3;
+
+/// Unknowns of the quadric, at least this many samples are needed
+const PARAMETERS: usize = 9;
+const JACOBI_SWEEPS: usize = 16;
+
+const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EllipsoidFit {
+    /// The hard-iron offset
+    pub center: Vector3,
+    /// Soft-iron correction, `transform * (sample - center)` lies on a sphere
+    pub transform: [[f32; 3]; 3],
+    /// Radius of that sphere, the geometric mean of the ellipsoid's radii
+    pub radius: f32,
+}
+
+impl EllipsoidFit {
+    pub fn apply(&self, sample: Vector3) -> Vector3 {
+        mul(self.transform, sample - self.center)
+    }
+}
+
+/// Fits an ellipsoid to the samples. Returns `None` if there are too few of
+/// them or they do not describe an ellipsoid, e.g. because they all lie in
+/// one plane.
+pub fn fit(samples: &[Vector3]) -> Option<EllipsoidFit> {
+    if samples.len() < PARAMETERS {
+        return None;
+    }
+
+    // Move the samples around the origin and scale them to about 1 to keep
+    // the normal equations well conditioned in f32.
+    let mean = samples
+        .iter()
+        .fold(Vector3::default(), |sum, sample| sum + *sample)
+        * (1.0 / samples.len() as f32);
+    let spread = samples
+        .iter()
+        .map(|sample| (*sample - mean).norm())
+        .fold(0.0, f32::max);
+    if spread == 0.0 {
+        return None;
+    }
+
+    let mut normal = [[0.0; PARAMETERS]; PARAMETERS];
+    let mut rhs = [0.0; PARAMETERS];
+    for sample in samples {
+        let u = (*sample - mean) * (1.0 / spread);
+        let row = [
+            u.x * u.x,
+            u.y * u.y,
+            u.z * u.z,
+            2.0 * u.y * u.z,
+            2.0 * u.x * u.z,
+            2.0 * u.x * u.y,
+            2.0 * u.x,
+            2.0 * u.y,
+            2.0 * u.z,
+        ];
+        for i in 0..PARAMETERS {
+            rhs[i] += row[i];
+            for j in 0..PARAMETERS {
+                normal[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    let v = solve(normal, rhs)?;
+
+    let quadric = [[v[0], v[5], v[4]], [v[5], v[1], v[3]], [v[4], v[3], v[2]]];
+    let linear = [-v[6], -v[7], -v[8]];
+    let center = solve(quadric, linear)?;
+    let center = Vector3::new(center[0], center[1], center[2]);
+
+    // Around its center the ellipsoid is `x^T quadric x = scale`
+    let scale = 1.0 + center.dot(mul(quadric, center));
+    let (eigenvalues, eigenvectors) = symmetric_eigen(quadric);
+    let mut radii = [0.0; 3];
+    for (radius, eigenvalue) in radii.iter_mut().zip(eigenvalues.iter()) {
+        if *eigenvalue <= 0.0 || scale <= 0.0 {
+            return None;
+        }
+        *radius = sqrtf(scale / eigenvalue);
+    }
+    let radius = cbrtf(radii[0] * radii[1] * radii[2]);
+
+    // Stretch every principal axis to the mean radius
+    let mut transform = [[0.0; 3]; 3];
+    for (i, row) in transform.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|k| eigenvectors[i][k] * (radius / radii[k]) * eigenvectors[j][k])
+                .sum();
+        }
+    }
+
+    Some(EllipsoidFit {
+        center: mean + center * spread,
+        transform,
+        radius: radius * spread,
+    })
+}
+
+fn mul(matrix: [[f32; 3]; 3], vector: Vector3) -> Vector3 {
+    let row = |r: [f32; 3]| r[0] * vector.x + r[1] * vector.y + r[2] * vector.z;
+    Vector3::new(row(matrix[0]), row(matrix[1]), row(matrix[2]))
+}
+
+/// Gaussian elimination with partial pivoting, `None` if `a` is singular
+fn solve<const N: usize>(mut a: [[f32; N]; N], mut b: [f32; N]) -> Option<[f32; N]> {
+    for column in 0..N {
+        let pivot = (column..N).fold(column, |best, row| {
+            if fabsf(a[row][column]) > fabsf(a[best][column]) {
+                row
+            } else {
+                best
+            }
+        });
+        if fabsf(a[pivot][column]) < 1e-12 {
+            return None;
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+
+        for row in column + 1..N {
+            let factor = a[row][column] / a[column][column];
+            let (upper, lower) = a.split_at_mut(row);
+            for (value, pivot_value) in lower[0][column..].iter_mut().zip(&upper[column][column..])
+            {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f32 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Jacobi eigenvalue algorithm. Returns the eigenvalues and a matrix with the
+/// matching eigenvectors as its columns.
+fn symmetric_eigen(mut a: [[f32; 3]; 3]) -> ([f32; 3], [[f32; 3]; 3]) {
+    let mut v = IDENTITY;
+    for _ in 0..JACOBI_SWEEPS {
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if fabsf(a[p][q]) < 1e-12 {
+                continue;
+            }
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let sign = if theta < 0.0 { -1.0 } else { 1.0 };
+            let t = sign / (fabsf(theta) + sqrtf(theta * theta + 1.0));
+            let c = 1.0 / sqrtf(t * t + 1.0);
+            let s = t * c;
+
+            for row in a.iter_mut() {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+            let (row_p, row_q) = (a[p], a[q]);
+            a[p] = [0, 1, 2].map(|k| c * row_p[k] - s * row_q[k]);
+            a[q] = [0, 1, 2].map(|k| s * row_p[k] + c * row_q[k]);
+            for row in v.iter_mut() {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+        }
+    }
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::{cosf, sinf};
+
+    const FIELD: f32 = 45_000.0;
+    const HARD_IRON: Vector3 = Vector3::new(3_000.0, -12_000.0, 7_500.0);
+    /// Squashes one axis and shears the others, like a nearby battery would
+    const SOFT_IRON: [[f32; 3]; 3] = [[1.2, 0.15, 0.0], [0.15, 0.8, 0.1], [0.0, 0.1, 1.05]];
+
+    /// `count` directions spread evenly over the sphere
+    fn sphere(count: usize) -> impl Iterator<Item = Vector3> {
+        let golden_angle = core::f32::consts::PI * (3.0 - sqrtf(5.0));
+        (0..count).map(move |i| {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = sqrtf(1.0 - z * z);
+            let phi = golden_angle * i as f32;
+            Vector3::new(r * cosf(phi), r * sinf(phi), z)
+        })
+    }
+
+    fn distorted(count: usize) -> [Vector3; 64] {
+        let mut samples = [Vector3::default(); 64];
+        for (sample, direction) in samples.iter_mut().zip(sphere(count)) {
+            *sample = mul(SOFT_IRON, direction * FIELD) + HARD_IRON;
+        }
+        samples
+    }
+
+    #[test]
+    fn undoes_hard_and_soft_iron() {
+        let samples = distorted(64);
+        let fit = fit(&samples).unwrap();
+        assert!((fit.center - HARD_IRON).norm() < 50.0, "{:?}", fit.center);
+
+        for sample in samples.iter() {
+            let corrected = fit.apply(*sample).norm();
+            assert!(
+                fabsf(corrected - fit.radius) / fit.radius < 0.005,
+                "{}",
+                corrected
+            );
+        }
+        // The determinant of the distortion is close to 1, so is the scale
+        assert!(fabsf(fit.radius - FIELD) / FIELD < 0.05, "{}", fit.radius);
+    }
+
+    #[test]
+    fn more_samples_average_out_a_bad_one() {
+        let error = |count: usize| {
+            let mut samples = distorted(count);
+            samples[3] = samples[3] * 1.1;
+            let fit = fit(&samples[..count]).unwrap();
+            (fit.center - HARD_IRON).norm()
+        };
+        assert!(error(64) < error(25));
+    }
+
+    #[test]
+    fn rejects_too_few_samples() {
+        let samples = distorted(8);
+        assert_eq!(fit(&samples[..8]), None);
+    }
+
+    #[test]
+    fn rejects_flat_samples() {
+        // The board was only turned around on the table
+        let mut samples = [Vector3::default(); 32];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let angle = i as f32 * 0.2;
+            *sample = Vector3::new(cosf(angle), sinf(angle), 0.0) * FIELD + HARD_IRON;
+        }
+        assert_eq!(fit(&samples), None);
+    }
+}
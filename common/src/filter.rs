@@ -0,0 +1,282 @@
+//! Smoothing for compass headings.
+//!
+//! Headings wrap around at 360°, so averaging them like plain numbers goes
+//! badly wrong near north: the mean of 350° and 10° is 180°. The filters here
+//! work on the direction instead.
+
+use libm::{atan2f, cosf, sinf};
+
+use crate::angle::{difference, distance, normalize};
+
+/// Exponential moving average of the unit vectors pointing at the angles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircularEma {
+    alpha: f32,
+    /// Sine and cosine of the average so far
+    state: Option<(f32, f32)>,
+}
+
+impl CircularEma {
+    /// `alpha` is the weight of every new sample, between 0 (never moves) and
+    /// 1 (no smoothing at all)
+    pub const fn new(alpha: f32) -> CircularEma {
+        CircularEma { alpha, state: None }
+    }
+
+    /// A NaN or infinite sample is skipped, otherwise it would stick in the
+    /// average for good. The average so far is returned instead, or the
+    /// sample itself if there is none yet.
+    pub fn update(&mut self, degrees: f32) -> f32 {
+        if !degrees.is_finite() {
+            return match self.state {
+                Some((sin, cos)) => normalize(atan2f(sin, cos).to_degrees()),
+                None => degrees,
+            };
+        }
+        let radians = degrees.to_radians();
+        let (sin, cos) = (sinf(radians), cosf(radians));
+        let (sin, cos) = match self.state {
+            Some((average_sin, average_cos)) => (
+                average_sin + self.alpha * (sin - average_sin),
+                average_cos + self.alpha * (cos - average_cos),
+            ),
+            None => (sin, cos),
+        };
+        self.state = Some((sin, cos));
+        normalize(atan2f(sin, cos).to_degrees())
+    }
+}
+
+/// Median of the last `N` angles: the one with the smallest total distance to
+/// all others. Unlike an average it ignores single outliers completely.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircularMedian<const N: usize> {
+    samples: [f32; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> CircularMedian<N> {
+    pub const fn new() -> CircularMedian<N> {
+        CircularMedian {
+            samples: [0.0; N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    pub fn update(&mut self, degrees: f32) -> f32 {
+        if N == 0 {
+            return normalize(degrees);
+        }
+        self.samples[self.next] = normalize(degrees);
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+
+        let samples = &self.samples[..self.len];
+        let total_distance = |a: f32| samples.iter().map(|b| distance(a, *b)).sum::<f32>();
+        samples
+            .iter()
+            .copied()
+            .min_by(|a, b| total_distance(*a).total_cmp(&total_distance(*b)))
+            .unwrap_or(degrees)
+    }
+}
+
+impl<const N: usize> Default for CircularMedian<N> {
+    fn default() -> CircularMedian<N> {
+        CircularMedian::new()
+    }
+}
+
+/// A configurable smoothing stage, `N` is the window of the median
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeadingFilter<const N: usize> {
+    Raw,
+    Average(CircularEma),
+    Median(CircularMedian<N>),
+}
+
+impl<const N: usize> HeadingFilter<N> {
+    pub fn update(&mut self, degrees: f32) -> f32 {
+        match self {
+            HeadingFilter::Raw => normalize(degrees),
+            HeadingFilter::Average(filter) => filter.update(degrees),
+            HeadingFilter::Median(filter) => filter.update(degrees),
+        }
+    }
+}
+
+/// Splits the circle into `sectors` equal parts, the first one centered on 0°,
+/// and tells which one an angle falls into. It only switches to another
+/// sector once the angle is `margin` degrees past the border, so an angle
+/// right on it does not flicker between both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectorHysteresis {
+    sectors: usize,
+    margin: f32,
+    current: Option<usize>,
+}
+
+impl SectorHysteresis {
+    /// Panics if `sectors` is 0
+    pub const fn new(sectors: usize, margin: f32) -> SectorHysteresis {
+        assert!(sectors > 0, "there has to be at least one sector");
+        SectorHysteresis {
+            sectors,
+            margin,
+            current: None,
+        }
+    }
+
+    pub fn update(&mut self, degrees: f32) -> usize {
+        let width = 360.0 / self.sectors as f32;
+        let nearest = (normalize(degrees + width / 2.0) / width) as usize % self.sectors;
+        let sector = match self.current {
+            Some(current) => {
+                let from_center = difference(current as f32 * width, degrees);
+                if distance(from_center, 0.0) > width / 2.0 + self.margin {
+                    nearest
+                } else {
+                    current
+                }
+            }
+            None => nearest,
+        };
+        self.current = Some(sector);
+        sector
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            distance(actual, expected) < 0.01,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn ema_starts_at_the_first_sample() {
+        let mut ema = CircularEma::new(0.3);
+        assert_close(ema.update(123.0), 123.0);
+    }
+
+    #[test]
+    fn ema_averages_across_north() {
+        let mut ema = CircularEma::new(0.5);
+        ema.update(350.0);
+        assert_close(ema.update(10.0), 0.0);
+        // And follows a steady heading on the other side
+        let heading = (0..50).map(|_| ema.update(20.0)).last().unwrap();
+        assert_close(heading, 20.0);
+    }
+
+    #[test]
+    fn ema_smooths_by_alpha() {
+        let mut ema = CircularEma::new(0.1);
+        ema.update(0.0);
+        let heading = ema.update(90.0);
+        assert!(heading > 0.0 && heading < 10.0, "{}", heading);
+
+        let mut raw = CircularEma::new(1.0);
+        raw.update(0.0);
+        assert_close(raw.update(90.0), 90.0);
+    }
+
+    #[test]
+    fn ema_skips_non_finite_samples() {
+        let mut ema = CircularEma::new(0.5);
+        assert!(ema.update(f32::NAN).is_nan());
+        assert_close(ema.update(40.0), 40.0);
+        assert_close(ema.update(f32::NAN), 40.0);
+        assert_close(ema.update(f32::INFINITY), 40.0);
+        assert_close(ema.update(f32::NEG_INFINITY), 40.0);
+        assert_close(ema.update(60.0), 50.0);
+    }
+
+    #[test]
+    fn median_ignores_an_outlier() {
+        let mut median = CircularMedian::<5>::new();
+        for &heading in &[100.0, 101.0, 99.0, 102.0] {
+            median.update(heading);
+        }
+        assert_close(median.update(280.0), 101.0);
+    }
+
+    #[test]
+    fn median_across_north() {
+        let mut median = CircularMedian::<3>::new();
+        median.update(355.0);
+        median.update(5.0);
+        assert_close(median.update(-1.0), 359.0);
+    }
+
+    #[test]
+    fn median_forgets_old_samples() {
+        let mut median = CircularMedian::<3>::new();
+        for &heading in &[10.0, 10.0, 10.0, 200.0, 200.0] {
+            median.update(heading);
+        }
+        assert_close(median.update(200.0), 200.0);
+    }
+
+    #[test]
+    fn raw_only_normalizes() {
+        let mut filter = HeadingFilter::<5>::Raw;
+        assert_close(filter.update(-30.0), 330.0);
+    }
+
+    #[test]
+    fn sectors_switch_past_the_margin() {
+        // Eight 45° sectors, the borders of the first are at ±22.5°
+        let mut sectors = SectorHysteresis::new(8, 5.0);
+        assert_eq!(sectors.update(10.0), 0);
+        assert_eq!(sectors.update(25.0), 0);
+        assert_eq!(sectors.update(27.0), 0);
+        assert_eq!(sectors.update(28.0), 1);
+        // Back across the border, still inside the margin of sector 1
+        assert_eq!(sectors.update(20.0), 1);
+        assert_eq!(sectors.update(17.0), 0);
+    }
+
+    #[test]
+    fn sectors_hold_across_north() {
+        let mut sectors = SectorHysteresis::new(8, 5.0);
+        assert_eq!(sectors.update(1.0), 0);
+        assert_eq!(sectors.update(359.0), 0);
+        assert_eq!(sectors.update(-20.0), 0);
+        assert_eq!(sectors.update(335.0), 0);
+        assert_eq!(sectors.update(332.0), 7);
+        assert_eq!(sectors.update(340.0), 7);
+        assert_eq!(sectors.update(343.0), 0);
+        assert_eq!(sectors.update(20.0), 0);
+    }
+
+    #[test]
+    fn first_sector_is_the_nearest() {
+        let mut sectors = SectorHysteresis::new(4, 10.0);
+        assert_eq!(sectors.update(350.0), 0);
+        let mut sectors = SectorHysteresis::new(4, 10.0);
+        assert_eq!(sectors.update(-100.0), 3);
+    }
+
+    #[test]
+    fn a_single_sector_covers_everything() {
+        let mut sectors = SectorHysteresis::new(1, 10.0);
+        for degrees in [0.0, 90.0, 179.0, 181.0, 359.0, -720.0] {
+            assert_eq!(sectors.update(degrees), 0);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_zero_sectors() {
+        SectorHysteresis::new(0, 10.0);
+    }
+}
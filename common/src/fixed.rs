@@ -0,0 +1,383 @@
+//! Fixed point and integer vector math.
+//!
+//! The micro:bit v1's nRF51 has no FPU, every float operation there is done
+//! in software. The calibration pipeline sticks to integers instead. All
+//! operations saturate rather than wrap, so an absurd reading or scale gives
+//! a clamped result instead of a silently wrong one.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+use libm::roundf;
+
+/// A number with `FRAC` fractional bits stored in an `i32`, Q(31 - FRAC).FRAC
+/// in Q notation. The arithmetic operators saturate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed<const FRAC: u32>(i32);
+
+impl<const FRAC: u32> Fixed<FRAC> {
+    pub const ZERO: Fixed<FRAC> = Fixed(0);
+    pub const ONE: Fixed<FRAC> = Fixed(1 << FRAC);
+    pub const MAX: Fixed<FRAC> = Fixed(i32::MAX);
+    pub const MIN: Fixed<FRAC> = Fixed(i32::MIN);
+
+    /// Takes the raw representation, `bits / 2^FRAC`
+    pub const fn from_bits(bits: i32) -> Fixed<FRAC> {
+        Fixed(bits)
+    }
+
+    pub const fn to_bits(self) -> i32 {
+        self.0
+    }
+
+    pub fn from_int(value: i32) -> Fixed<FRAC> {
+        Fixed(saturate(i64::from(value) << FRAC))
+    }
+
+    /// Rounds to the nearest representable number, NaN becomes 0
+    pub fn from_f32(value: f32) -> Fixed<FRAC> {
+        // `as` saturates and turns NaN into 0
+        Fixed(roundf(value * (1u64 << FRAC) as f32) as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / (1u64 << FRAC) as f32
+    }
+
+    /// Rounds towards negative infinity
+    pub fn to_int(self) -> i32 {
+        self.0 >> FRAC
+    }
+
+    /// `numerator / denominator`, rounded towards zero. Saturates, also when
+    /// dividing by zero.
+    pub fn from_ratio(numerator: i64, denominator: i64) -> Fixed<FRAC> {
+        let numerator = i128::from(numerator) << FRAC;
+        let quotient = match denominator {
+            0 if numerator < 0 => i128::MIN,
+            0 => i128::MAX,
+            denominator => numerator / i128::from(denominator),
+        };
+        Fixed(quotient.clamp(i128::from(i32::MIN), i128::from(i32::MAX)) as i32)
+    }
+
+    /// The same number with `TO` fractional bits, rounded towards negative
+    /// infinity if that is fewer
+    pub fn convert<const TO: u32>(self) -> Fixed<TO> {
+        let bits = i64::from(self.0);
+        Fixed(saturate(if TO >= FRAC {
+            bits << (TO - FRAC)
+        } else {
+            bits >> (FRAC - TO)
+        }))
+    }
+
+    /// `self * value` as an integer, rounded towards negative infinity
+    pub fn mul_int(self, value: i32) -> i32 {
+        saturate((i64::from(self.0) * i64::from(value)) >> FRAC)
+    }
+}
+
+impl<const FRAC: u32> Add for Fixed<FRAC> {
+    type Output = Fixed<FRAC>;
+
+    fn add(self, other: Fixed<FRAC>) -> Fixed<FRAC> {
+        Fixed(self.0.saturating_add(other.0))
+    }
+}
+
+impl<const FRAC: u32> Sub for Fixed<FRAC> {
+    type Output = Fixed<FRAC>;
+
+    fn sub(self, other: Fixed<FRAC>) -> Fixed<FRAC> {
+        Fixed(self.0.saturating_sub(other.0))
+    }
+}
+
+impl<const FRAC: u32> Mul for Fixed<FRAC> {
+    type Output = Fixed<FRAC>;
+
+    fn mul(self, other: Fixed<FRAC>) -> Fixed<FRAC> {
+        Fixed(saturate((i64::from(self.0) * i64::from(other.0)) >> FRAC))
+    }
+}
+
+impl<const FRAC: u32> Div for Fixed<FRAC> {
+    type Output = Fixed<FRAC>;
+
+    /// Saturates when dividing by zero instead of panicking
+    fn div(self, other: Fixed<FRAC>) -> Fixed<FRAC> {
+        Fixed::from_ratio(i64::from(self.0), i64::from(other.0))
+    }
+}
+
+impl<const FRAC: u32> Neg for Fixed<FRAC> {
+    type Output = Fixed<FRAC>;
+
+    fn neg(self) -> Fixed<FRAC> {
+        Fixed(self.0.saturating_neg())
+    }
+}
+
+/// Clamps to the range of an `i32`
+pub fn saturate(value: i64) -> i32 {
+    value.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32
+}
+
+/// The integer square root, rounded down
+pub fn isqrt(value: u64) -> u32 {
+    // Digit by digit in base 4, see "Integer square root" on Wikipedia
+    let mut remainder = value;
+    let mut root = 0u64;
+    let mut bit = 1u64 << 62;
+    while bit > remainder {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if remainder >= root + bit {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root as u32
+}
+
+pub fn sub(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    [
+        a[0].saturating_sub(b[0]),
+        a[1].saturating_sub(b[1]),
+        a[2].saturating_sub(b[2]),
+    ]
+}
+
+pub fn add(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    [
+        a[0].saturating_add(b[0]),
+        a[1].saturating_add(b[1]),
+        a[2].saturating_add(b[2]),
+    ]
+}
+
+/// Cannot overflow: every square is at most 2^62
+pub fn norm_squared(v: [i32; 3]) -> u64 {
+    v.iter()
+        .map(|c| {
+            let c = u64::from(c.unsigned_abs());
+            c * c
+        })
+        .sum()
+}
+
+pub fn norm(v: [i32; 3]) -> u32 {
+    isqrt(norm_squared(v))
+}
+
+/// Squared distance between two points, saturated if they are further
+/// apart than an `i32` can hold along any axis
+pub fn distance_squared(a: [i32; 3], b: [i32; 3]) -> u64 {
+    norm_squared(sub(a, b))
+}
+
+pub fn distance(a: [i32; 3], b: [i32; 3]) -> u32 {
+    isqrt(distance_squared(a, b))
+}
+
+/// Multiplies a vector with a fixed point matrix. The rows are summed up
+/// exactly before the result is rounded down and saturated.
+pub fn mul_matrix<const FRAC: u32>(matrix: [[Fixed<FRAC>; 3]; 3], v: [i32; 3]) -> [i32; 3] {
+    matrix.map(|row| {
+        // Three products of two `i32` can exceed an `i64`
+        let sum: i128 = row
+            .iter()
+            .zip(v.iter())
+            .map(|(m, c)| i128::from(m.to_bits()) * i128::from(*c))
+            .sum();
+        (sum >> FRAC).clamp(i128::from(i32::MIN), i128::from(i32::MAX)) as i32
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    //! Property tests: random operands, every result compared to the same
+    //! math in `f64`, clamped to the range of the result
+    use super::*;
+
+    type Q16 = Fixed<16>;
+
+    const ROUNDS: usize = 10_000;
+
+    /// xorshift64, reproducible and good enough to spread the operands
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Any `i32`, but small ones as often as large ones
+        fn i32(&mut self) -> i32 {
+            let shift = self.next() % 32;
+            (self.next() as i32) >> shift
+        }
+    }
+
+    fn clamp(value: f64) -> f64 {
+        value.clamp(f64::from(i32::MIN), f64::from(i32::MAX))
+    }
+
+    /// The exact result in units of the last place, compared to the
+    /// fixed point one
+    fn assert_within(actual: i32, exact_bits: f64, ulps: f64, case: impl core::fmt::Debug) {
+        let error = (f64::from(actual) - clamp(exact_bits)).abs();
+        assert!(
+            error <= ulps,
+            "{:?}: {} is {} off {}",
+            case,
+            actual,
+            error,
+            exact_bits
+        );
+    }
+
+    #[test]
+    fn add_and_sub_saturate() {
+        let mut random = Random(1);
+        for _ in 0..ROUNDS {
+            let (a, b) = (Q16::from_bits(random.i32()), Q16::from_bits(random.i32()));
+            let (x, y) = (f64::from(a.to_bits()), f64::from(b.to_bits()));
+            assert_within((a + b).to_bits(), x + y, 0.0, (a, b));
+            assert_within((a - b).to_bits(), x - y, 0.0, (a, b));
+            assert_within((-a).to_bits(), -x, 0.0, a);
+        }
+    }
+
+    #[test]
+    fn mul_rounds_down() {
+        let mut random = Random(2);
+        for _ in 0..ROUNDS {
+            let (a, b) = (Q16::from_bits(random.i32()), Q16::from_bits(random.i32()));
+            let exact = f64::from(a.to_bits()) * f64::from(b.to_bits()) / 65536.0;
+            assert_within((a * b).to_bits(), exact.floor(), 1.0, (a, b));
+        }
+    }
+
+    #[test]
+    fn div_rounds_towards_zero() {
+        let mut random = Random(3);
+        for _ in 0..ROUNDS {
+            let (a, b) = (Q16::from_bits(random.i32()), Q16::from_bits(random.i32()));
+            if b == Q16::ZERO {
+                continue;
+            }
+            let exact = f64::from(a.to_bits()) * 65536.0 / f64::from(b.to_bits());
+            assert_within((a / b).to_bits(), exact.trunc(), 1.0, (a, b));
+        }
+        assert_eq!(Q16::ONE / Q16::ZERO, Q16::MAX);
+        assert_eq!(-Q16::ONE / Q16::ZERO, Q16::MIN);
+    }
+
+    #[test]
+    fn ratios() {
+        let mut random = Random(4);
+        for _ in 0..ROUNDS {
+            let numerator = i64::from(random.i32()) * i64::from(random.i32());
+            let denominator = i64::from(random.i32());
+            if denominator == 0 {
+                continue;
+            }
+            let exact = numerator as f64 * 65536.0 / denominator as f64;
+            let ratio = Q16::from_ratio(numerator, denominator);
+            assert_within(
+                ratio.to_bits(),
+                exact.trunc(),
+                1.0,
+                (numerator, denominator),
+            );
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let mut random = Random(5);
+        for _ in 0..ROUNDS {
+            let a = Q16::from_bits(random.i32());
+            let x = f64::from(a.to_bits());
+            assert_within(a.convert::<10>().to_bits(), (x / 64.0).floor(), 0.0, a);
+            assert_within(a.convert::<20>().to_bits(), x * 16.0, 0.0, a);
+            assert_within(a.to_int(), (x / 65536.0).floor(), 0.0, a);
+            assert!((f64::from(a.to_f32()) - x / 65536.0).abs() <= (x / 65536.0).abs() * 1e-7);
+
+            let value = random.i32() >> 16;
+            assert_eq!(Q16::from_int(value).to_int(), value);
+            let bits = f64::from(random.i32()) / 65536.0;
+            assert_within(
+                Q16::from_f32(bits as f32).to_bits(),
+                bits * 65536.0,
+                256.0,
+                bits,
+            );
+
+            let factor = random.i32();
+            let exact = x * f64::from(factor) / 65536.0;
+            assert_within(a.mul_int(factor), exact.floor(), 1.0, (a, factor));
+        }
+        assert_eq!(Q16::from_int(i32::MAX), Q16::MAX);
+        assert_eq!(Q16::from_f32(f32::NAN), Q16::ZERO);
+    }
+
+    #[test]
+    fn square_roots() {
+        let mut random = Random(6);
+        for _ in 0..ROUNDS {
+            let value = random.next() >> (random.next() % 64);
+            let root = u128::from(isqrt(value));
+            assert!(root * root <= u128::from(value), "{}", value);
+            assert!((root + 1) * (root + 1) > u128::from(value), "{}", value);
+        }
+        assert_eq!(isqrt(u64::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn vectors() {
+        let mut random = Random(7);
+        for _ in 0..ROUNDS {
+            let a = [random.i32(), random.i32(), random.i32()];
+            let b = [random.i32(), random.i32(), random.i32()];
+            let exact = |v: [f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+            let length = exact(a.map(f64::from));
+            assert!((f64::from(norm(a)) - length).abs() <= 1.0, "{:?}", a);
+
+            for axis in 0..3 {
+                let (x, y) = (f64::from(a[axis]), f64::from(b[axis]));
+                assert_within(add(a, b)[axis], x + y, 0.0, (a, b));
+                assert_within(sub(a, b)[axis], x - y, 0.0, (a, b));
+            }
+            let difference = exact(sub(a, b).map(f64::from));
+            assert!((f64::from(distance(a, b)) - difference).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn matrices() {
+        let mut random = Random(8);
+        for _ in 0..ROUNDS {
+            let matrix = [(); 3].map(|_| [(); 3].map(|_| Q16::from_bits(random.i32())));
+            let v = [random.i32(), random.i32(), random.i32()];
+            let product = mul_matrix(matrix, v);
+            for (row, result) in matrix.iter().zip(product) {
+                let exact: f64 = row
+                    .iter()
+                    .zip(v)
+                    .map(|(m, c)| f64::from(m.to_bits()) * f64::from(c) / 65536.0)
+                    .sum();
+                // `f64` loses the last bits of sums this large
+                let ulps = 1.0 + exact.abs() * 1e-15;
+                assert_within(result, exact.floor(), ulps, (matrix, v));
+            }
+        }
+    }
+}
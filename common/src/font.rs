@@ -0,0 +1,137 @@
+//! Digits for the micro:bit's 5x5 LED matrix, for showing numbers one
+//! character at a time or scrolling them across the display.
+
+/// An image with 1 for every lit LED
+pub type Glyph = [[u8; 5]; 5];
+
+const DIGITS: [Glyph; 10] = [
+    [
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 0, 1, 0, 0],
+        [0, 1, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 0, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 0, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 1, 0, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 0, 0, 1, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 0, 0],
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 0, 0],
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 0, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ],
+    [
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ],
+];
+
+const POINT: Glyph = [
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+];
+
+const MINUS: Glyph = [
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+];
+
+pub const BLANK: Glyph = [[0; 5]; 5];
+
+/// How many columns a character takes up when scrolling, the three columns
+/// of its glyph and a gap
+const SCROLL_COLUMNS: usize = 4;
+
+/// The glyph for a digit, `.` or `-`. Other characters are blank.
+pub fn glyph(c: char) -> Glyph {
+    match c {
+        '0'..='9' => DIGITS[c as usize - '0' as usize],
+        '.' => POINT,
+        '-' => MINUS,
+        _ => BLANK,
+    }
+}
+
+/// How many columns `scroll` moves `len` characters until they have left the
+/// display
+pub fn scroll_len(len: usize) -> usize {
+    5 + len * SCROLL_COLUMNS
+}
+
+/// The display with `text` scrolled to the left by `offset` columns. At 0 the
+/// text starts just right of the display.
+pub fn scroll(text: &[char], offset: usize) -> Glyph {
+    let mut image = BLANK;
+    for col in 0..5 {
+        // The first five columns are right of the display
+        let column = match (offset + col).checked_sub(5) {
+            Some(column) => column,
+            None => continue,
+        };
+        let (index, within) = (column / SCROLL_COLUMNS, column % SCROLL_COLUMNS);
+        if index < text.len() && within < 3 {
+            let glyph = glyph(text[index]);
+            for (row, leds) in image.iter_mut().enumerate() {
+                leds[col] = glyph[row][within + 1];
+            }
+        }
+    }
+    image
+}
@@ -0,0 +1,521 @@
+//! Recognizes gestures like shakes, taps and the board being turned over
+//! from accelerometer readings.
+//!
+//! Like `button::Recognizer`, `GestureDetector` knows nothing about the
+//! sensor: the board feeds it every sample in the board frame `tilt`
+//! describes, together with the time it was taken in ms. Timestamps may wrap
+//! around, and one older than the last is taken as happening at the same time.
+//!
+//! Taps and freefalls can be detected in software, which needs a data rate of
+//! a few hundred Hz to see the short spike of a tap, or by the accelerometer's
+//! click and interrupt generators. For those the board reports what the
+//! sensor saw with `hardware`, and the detector still pairs taps into double
+//! taps and reports every fall once.
+//!
+//! A tap is only reported once the double tap window passed without a second
+//! tap, like a click of a button.
+
+use libm::sqrtf;
+
+use crate::units::Acceleration;
+
+/// Which side of the board points up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    /// Lying flat with the display up
+    FaceUp,
+    /// Lying flat with the display down
+    FaceDown,
+    TopUp,
+    BottomUp,
+    LeftUp,
+    RightUp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    Shake,
+    Tap,
+    DoubleTap,
+    Freefall,
+    /// The board settled in a new orientation
+    Orientation(Orientation),
+}
+
+/// Something the accelerometer's interrupt generators detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HardwareEvent {
+    Tap,
+    Freefall,
+}
+
+/// Who detects a gesture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Software,
+    /// The board reports it with `GestureDetector::hardware`
+    Hardware,
+}
+
+/// Thresholds and durations in ms. Taps and shakes are measured by how far
+/// the magnitude of the acceleration is off 1 g, so gravity does not count
+/// whichever way the board is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub taps: Source,
+    pub tap_threshold: Acceleration,
+    /// The longest a spike may stay above `tap_threshold` to be a tap
+    pub tap_limit_ms: u32,
+    /// Taps closer together than this are the same tap ringing on
+    pub tap_latency_ms: u32,
+    /// The longest time between two taps for a double tap. 0 reports every
+    /// tap right away.
+    pub double_tap_ms: u32,
+    pub shake_threshold: Acceleration,
+    /// How often the signal has to cross `shake_threshold` within
+    /// `shake_window_ms` for a shake
+    pub shake_count: u8,
+    pub shake_window_ms: u32,
+    pub freefall: Source,
+    /// The magnitude has to stay below this for `freefall_ms` for a fall
+    pub freefall_threshold: Acceleration,
+    pub freefall_ms: u32,
+    /// Gravity has to be at least this strong along one axis to tell the
+    /// orientation, otherwise the board is tilted in between
+    pub orientation_threshold: Acceleration,
+    /// How long a new orientation has to be held before it is reported
+    pub orientation_ms: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            taps: Source::Software,
+            tap_threshold: Acceleration::from_mg(1_000),
+            tap_limit_ms: 30,
+            tap_latency_ms: 80,
+            double_tap_ms: 300,
+            shake_threshold: Acceleration::from_mg(1_200),
+            shake_count: 4,
+            shake_window_ms: 1_000,
+            freefall: Source::Software,
+            freefall_threshold: Acceleration::from_mg(350),
+            freefall_ms: 60,
+            orientation_threshold: Acceleration::from_mg(800),
+            orientation_ms: 300,
+        }
+    }
+}
+
+pub struct GestureDetector {
+    config: Config,
+    /// The latest timestamp seen
+    last: Option<u32>,
+    /// When the magnitude went above `tap_threshold`
+    spike_since: Option<u32>,
+    /// When the last tap happened that might become a double tap
+    tap_at: Option<u32>,
+    above_shake: bool,
+    /// When the current run of crossings of `shake_threshold` started
+    shake_start: u32,
+    last_crossing: u32,
+    crossings: u8,
+    /// A shake was reported and the board has not calmed down since
+    shaking: bool,
+    /// When the magnitude went below `freefall_threshold`
+    fall_since: Option<u32>,
+    /// The current fall was reported
+    fallen: bool,
+    orientation: Option<Orientation>,
+    /// A different orientation and since when it is held
+    candidate: Option<(Orientation, u32)>,
+}
+
+impl GestureDetector {
+    pub const fn new(config: Config) -> GestureDetector {
+        GestureDetector {
+            config,
+            last: None,
+            spike_since: None,
+            tap_at: None,
+            above_shake: false,
+            shake_start: 0,
+            last_crossing: 0,
+            crossings: 0,
+            shaking: false,
+            fall_since: None,
+            fallen: false,
+            orientation: None,
+            candidate: None,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// The last orientation reported, if any
+    pub fn orientation(&self) -> Option<Orientation> {
+        self.orientation
+    }
+
+    /// Feeds the next sample, `[x, y, z]` in the board frame
+    pub fn update<F: FnMut(Gesture)>(&mut self, accel: [Acceleration; 3], now: u32, mut emit: F) {
+        let now = self.advance(now);
+        self.poll(now, &mut emit);
+
+        let [x, y, z] = accel.map(|axis| axis.mg() as f32);
+        let magnitude = Acceleration::from_mg(sqrtf(x * x + y * y + z * z) as i32);
+        let deviation = (magnitude - Acceleration::from_mg(1_000)).abs();
+
+        self.update_shake(deviation, now, &mut emit);
+        if self.config.taps == Source::Software {
+            self.update_tap(deviation, now, &mut emit);
+        }
+        self.update_freefall(magnitude, now, &mut emit);
+        self.update_orientation(accel, now, &mut emit);
+    }
+
+    /// Feeds something the accelerometer detected by itself
+    pub fn hardware<F: FnMut(Gesture)>(&mut self, event: HardwareEvent, now: u32, mut emit: F) {
+        let now = self.advance(now);
+        self.poll(now, &mut emit);
+        match event {
+            HardwareEvent::Tap => self.tap(now, &mut emit),
+            HardwareEvent::Freefall if !self.fallen => {
+                self.fallen = true;
+                emit(Gesture::Freefall);
+            }
+            HardwareEvent::Freefall => {}
+        }
+    }
+
+    /// Reports what happened up to `now`
+    pub fn poll<F: FnMut(Gesture)>(&mut self, now: u32, mut emit: F) {
+        let now = self.advance(now);
+        if let Some(tap_at) = self.tap_at {
+            // A spike in progress might still become the second tap
+            if self.spike_since.is_none() && now.wrapping_sub(tap_at) > self.config.double_tap_ms {
+                self.tap_at = None;
+                emit(Gesture::Tap);
+            }
+        }
+        if self.shaking && now.wrapping_sub(self.last_crossing) > self.config.shake_window_ms {
+            self.shaking = false;
+        }
+    }
+
+    /// Keeps time from running backwards
+    fn advance(&mut self, now: u32) -> u32 {
+        let now = match self.last {
+            // Anything more than half the range ahead is behind instead
+            Some(last) if now.wrapping_sub(last) > u32::MAX / 2 => last,
+            _ => now,
+        };
+        self.last = Some(now);
+        now
+    }
+
+    fn update_shake<F: FnMut(Gesture)>(&mut self, deviation: Acceleration, now: u32, emit: &mut F) {
+        let above = deviation > self.config.shake_threshold;
+        if above && !self.above_shake {
+            if self.crossings == 0
+                || now.wrapping_sub(self.shake_start) > self.config.shake_window_ms
+            {
+                self.shake_start = now;
+                self.crossings = 0;
+            }
+            self.crossings = self.crossings.saturating_add(1);
+            self.last_crossing = now;
+            if self.crossings == self.config.shake_count {
+                self.shaking = true;
+                // The spikes of a shake are no taps
+                self.tap_at = None;
+                self.spike_since = None;
+                emit(Gesture::Shake);
+            }
+        }
+        self.above_shake = above;
+    }
+
+    fn update_tap<F: FnMut(Gesture)>(&mut self, deviation: Acceleration, now: u32, emit: &mut F) {
+        match self.spike_since {
+            None if deviation > self.config.tap_threshold => self.spike_since = Some(now),
+            Some(since) if deviation <= self.config.tap_threshold => {
+                self.spike_since = None;
+                // Anything longer is the board being moved
+                if now.wrapping_sub(since) <= self.config.tap_limit_ms {
+                    self.tap(since, emit);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn tap<F: FnMut(Gesture)>(&mut self, at: u32, emit: &mut F) {
+        if self.shaking {
+            return;
+        }
+        match self.tap_at {
+            Some(tap_at) if at.wrapping_sub(tap_at) < self.config.tap_latency_ms => {}
+            Some(tap_at) if at.wrapping_sub(tap_at) <= self.config.double_tap_ms => {
+                self.tap_at = None;
+                emit(Gesture::DoubleTap);
+            }
+            _ if self.config.double_tap_ms == 0 => emit(Gesture::Tap),
+            _ => self.tap_at = Some(at),
+        }
+    }
+
+    fn update_freefall<F: FnMut(Gesture)>(
+        &mut self,
+        magnitude: Acceleration,
+        now: u32,
+        emit: &mut F,
+    ) {
+        if magnitude >= self.config.freefall_threshold {
+            // Caught, or the hardware fall is over as well
+            self.fall_since = None;
+            self.fallen = false;
+            return;
+        }
+        let since = *self.fall_since.get_or_insert(now);
+        if self.config.freefall == Source::Software
+            && !self.fallen
+            && now.wrapping_sub(since) >= self.config.freefall_ms
+        {
+            self.fallen = true;
+            emit(Gesture::Freefall);
+        }
+    }
+
+    fn update_orientation<F: FnMut(Gesture)>(
+        &mut self,
+        accel: [Acceleration; 3],
+        now: u32,
+        emit: &mut F,
+    ) {
+        let (axis, value) = accel
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, value)| value.abs())
+            .map(|(axis, value)| (axis, *value))
+            .unwrap();
+        if value.abs() < self.config.orientation_threshold {
+            // Tilted in between, keep whatever was held before
+            self.candidate = None;
+            return;
+        }
+        // The accelerometer reads +1 g along the axis pointing up
+        let orientation = match (axis, value > Acceleration::ZERO) {
+            (0, true) => Orientation::RightUp,
+            (0, false) => Orientation::LeftUp,
+            (1, true) => Orientation::TopUp,
+            (1, false) => Orientation::BottomUp,
+            (_, true) => Orientation::FaceUp,
+            (_, false) => Orientation::FaceDown,
+        };
+
+        if self.orientation == Some(orientation) {
+            self.candidate = None;
+            return;
+        }
+        match self.candidate {
+            Some((candidate, since)) if candidate == orientation => {
+                if now.wrapping_sub(since) >= self.config.orientation_ms {
+                    self.orientation = Some(orientation);
+                    self.candidate = None;
+                    emit(Gesture::Orientation(orientation));
+                }
+            }
+            _ => self.candidate = Some((orientation, now)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lying flat with the display up
+    const FLAT: [i32; 3] = [0, 0, 1_000];
+
+    /// Gestures with the time they were reported at
+    struct Trace {
+        gestures: [Option<(u32, Gesture)>; 16],
+        len: usize,
+    }
+
+    impl Trace {
+        fn new() -> Trace {
+            Trace {
+                gestures: [None; 16],
+                len: 0,
+            }
+        }
+
+        fn push(&mut self, time: u32, gesture: Gesture) {
+            self.gestures[self.len] = Some((time, gesture));
+            self.len += 1;
+        }
+
+        /// Everything but the orientation, which settles at the start of
+        /// every trace
+        fn gestures(&self) -> impl Iterator<Item = Gesture> + '_ {
+            self.gestures[..self.len]
+                .iter()
+                .flatten()
+                .map(|&(_, gesture)| gesture)
+                .filter(|gesture| !matches!(gesture, Gesture::Orientation(_)))
+        }
+
+        fn orientations(&self) -> impl Iterator<Item = (u32, Orientation)> + '_ {
+            self.gestures[..self.len].iter().flatten().filter_map(
+                |&(time, gesture)| match gesture {
+                    Gesture::Orientation(orientation) => Some((time, orientation)),
+                    _ => None,
+                },
+            )
+        }
+    }
+
+    /// Samples the acceleration `accel(t)` in mg once per ms for `duration` ms
+    fn run(config: Config, duration: u32, accel: impl Fn(u32) -> [i32; 3]) -> Trace {
+        let mut detector = GestureDetector::new(config);
+        let mut trace = Trace::new();
+        for now in 0..duration {
+            let sample = accel(now).map(Acceleration::from_mg);
+            detector.update(sample, now, |gesture| trace.push(now, gesture));
+        }
+        trace
+    }
+
+    /// Flat, with a 10 ms knock on the table at every time in `taps`
+    fn taps(taps: &'static [u32]) -> impl Fn(u32) -> [i32; 3] {
+        move |t| match taps.iter().any(|&tap| (tap..tap + 10).contains(&t)) {
+            true => [0, 0, 2_500],
+            false => FLAT,
+        }
+    }
+
+    fn assert_gestures(trace: &Trace, expected: &[Gesture]) {
+        assert!(
+            trace.gestures().eq(expected.iter().copied()),
+            "{:?}",
+            &trace.gestures[..trace.len]
+        );
+    }
+
+    #[test]
+    fn tap() {
+        let trace = run(Config::default(), 1_000, taps(&[400]));
+        assert_gestures(&trace, &[Gesture::Tap]);
+    }
+
+    #[test]
+    fn double_tap_inside_the_window() {
+        let trace = run(Config::default(), 1_500, taps(&[400, 650]));
+        assert_gestures(&trace, &[Gesture::DoubleTap]);
+    }
+
+    #[test]
+    fn two_taps_outside_the_window() {
+        let trace = run(Config::default(), 1_500, taps(&[400, 750]));
+        assert_gestures(&trace, &[Gesture::Tap, Gesture::Tap]);
+    }
+
+    #[test]
+    fn ringing_is_one_tap() {
+        let trace = run(Config::default(), 1_500, taps(&[400, 440]));
+        assert_gestures(&trace, &[Gesture::Tap]);
+    }
+
+    #[test]
+    fn hardware_double_tap() {
+        let config = Config {
+            taps: Source::Hardware,
+            ..Config::default()
+        };
+        let mut detector = GestureDetector::new(config);
+        let mut trace = Trace::new();
+        for now in 0..1_500 {
+            let sample = taps(&[400, 650])(now).map(Acceleration::from_mg);
+            detector.update(sample, now, |gesture| trace.push(now, gesture));
+            if now == 400 || now == 650 || now == 1_100 {
+                detector.hardware(HardwareEvent::Tap, now, |gesture| trace.push(now, gesture));
+            }
+        }
+        // Spikes in the samples are left to the hardware
+        assert_gestures(&trace, &[Gesture::DoubleTap, Gesture::Tap]);
+    }
+
+    #[test]
+    fn shake_is_no_tap() {
+        // Swinging the board left and right, 60 ms out every 150 ms
+        let shake = |t: u32| match (300..1_200).contains(&t) && t % 150 < 60 {
+            true => [2_500, 0, 1_000],
+            false => FLAT,
+        };
+        let trace = run(Config::default(), 2_000, shake);
+        assert_gestures(&trace, &[Gesture::Shake]);
+    }
+
+    #[test]
+    fn short_knocks_while_shaking_are_no_taps() {
+        let shake = |t: u32| match (300..1_200).contains(&t) && t % 150 < 60 {
+            true => [2_500, 0, 1_000],
+            false => taps(&[1_000])(t),
+        };
+        let trace = run(Config::default(), 2_000, shake);
+        assert_gestures(&trace, &[Gesture::Shake]);
+    }
+
+    #[test]
+    fn freefall_is_reported_once() {
+        let falls = |t: u32| match t {
+            300..=500 | 800..=820 | 1_000..=1_200 => [0, 0, 50],
+            _ => FLAT,
+        };
+        let trace = run(Config::default(), 1_500, falls);
+        // The short drop in between is too short for a fall
+        assert_gestures(&trace, &[Gesture::Freefall, Gesture::Freefall]);
+        let times = trace.gestures[..trace.len]
+            .iter()
+            .flatten()
+            .filter(|&&(_, gesture)| gesture == Gesture::Freefall)
+            .map(|&(time, _)| time);
+        assert!(times.eq([360, 1_060]));
+    }
+
+    #[test]
+    fn orientation_has_to_be_held() {
+        let turns = |t: u32| match t {
+            // Turned over briefly, then tilted in between, then for good
+            500..=700 => [0, 0, -1_000],
+            800..=1_000 => [0, 700, 700],
+            1_200..=2_000 => [0, 0, -1_000],
+            _ => FLAT,
+        };
+        let trace = run(Config::default(), 2_000, turns);
+        assert!(trace.gestures().next().is_none());
+        assert!(trace
+            .orientations()
+            .eq([(300, Orientation::FaceUp), (1_500, Orientation::FaceDown),]));
+    }
+
+    #[test]
+    fn orientation_of_every_axis() {
+        let expected = [
+            ([1_000, 0, 0], Orientation::RightUp),
+            ([-1_000, 0, 0], Orientation::LeftUp),
+            ([0, 1_000, 0], Orientation::TopUp),
+            ([0, -1_000, 0], Orientation::BottomUp),
+            ([0, 0, 1_000], Orientation::FaceUp),
+            ([0, 0, -1_000], Orientation::FaceDown),
+        ];
+        for &(accel, orientation) in expected.iter() {
+            let trace = run(Config::default(), 400, |_| accel);
+            assert!(trace.orientations().eq([(300, orientation)]));
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! An I2C bus wrapper with timeouts, bounded retries and bus-clear recovery.
+//!
+//! The HAL transactions themselves block, so the timeout bounds how long we
+//! wait for the bus to become idle before handing it to the peripheral. A bus
+//! that stays stuck (usually a slave holding SDA low after a glitch) is freed
+//! with the standard sequence of up to nine SCL pulses followed by a STOP.
+//!
+//! A transaction that hangs inside the HAL can't be interrupted and resumed,
+//! so `BusRecovery::arm` starts a watchdog around it that resets the chip
+//! once `Config::transaction_timeout_us` passed. The bus is then cleared when
+//! the wrapper is created again after the reset.
+//!
+//! Repeating a transaction is only harmless if it reads or writes the same
+//! thing again. Reads that change the device, like draining a FIFO or
+//! clearing a latched interrupt, go through `WriteReadOnce` instead: a failure
+//! in the middle may already have popped data, which a retry would skip.
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// Board specific part of the bus recovery.
+pub trait BusRecovery<B> {
+    /// Returns `true` if SDA and SCL are both released and the peripheral is not busy.
+    fn is_idle(&mut self, bus: &mut B) -> bool;
+
+    /// Tears down `bus`, clocks SCL until SDA is released (at most nine
+    /// times), issues a STOP and sets the peripheral up again.
+    fn bus_clear(&mut self, bus: B) -> B;
+
+    fn delay_us(&mut self, us: u32);
+
+    /// Starts the watchdog for a transaction that should be done within
+    /// `timeout_us`. Does nothing by default.
+    fn arm(&mut self, _timeout_us: u32) {}
+
+    /// Stops the watchdog, the transaction finished in time
+    fn disarm(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// How long to wait for an idle bus before a transaction
+    pub timeout_us: u32,
+    /// How long a single transaction may take before the watchdog fires
+    pub transaction_timeout_us: u32,
+    /// How many times a failed transaction is repeated before giving up,
+    /// except for `WriteReadOnce`
+    pub retries: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            timeout_us: 10_000,
+            transaction_timeout_us: 50_000,
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub transactions: u32,
+    pub errors: u32,
+    pub timeouts: u32,
+    pub retries: u32,
+    pub recoveries: u32,
+    /// Transactions that still failed after all retries
+    pub failures: u32,
+}
+
+/// A `write_read` that is never repeated, for reads with side effects
+pub trait WriteReadOnce: WriteRead {
+    fn write_read_once(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The error reported by the underlying bus
+    Bus(E),
+    /// The bus did not become idle within `Config::timeout_us`
+    Timeout,
+}
+
+const POLL_INTERVAL_US: u32 = 10;
+
+pub struct RecoveringI2c<B, R> {
+    // Only `None` while `bus_clear` owns the bus
+    bus: Option<B>,
+    recovery: R,
+    config: Config,
+    stats: Stats,
+}
+
+impl<B, R> RecoveringI2c<B, R>
+where
+    R: BusRecovery<B>,
+{
+    /// Clears the bus right away if it is stuck, like after the watchdog
+    /// reset the chip in the middle of a transaction
+    pub fn new(bus: B, recovery: R, config: Config) -> RecoveringI2c<B, R> {
+        let mut i2c = RecoveringI2c {
+            bus: Some(bus),
+            recovery,
+            config,
+            stats: Stats::default(),
+        };
+        if !i2c.wait_idle() {
+            i2c.recover();
+        }
+        i2c
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn free(self) -> (B, R) {
+        (self.bus.unwrap(), self.recovery)
+    }
+
+    fn wait_idle(&mut self) -> bool {
+        let bus = self.bus.as_mut().unwrap();
+        let mut waited = 0;
+        while !self.recovery.is_idle(bus) {
+            if waited >= self.config.timeout_us {
+                return false;
+            }
+            self.recovery.delay_us(POLL_INTERVAL_US);
+            waited += POLL_INTERVAL_US;
+        }
+        true
+    }
+
+    fn recover(&mut self) {
+        let bus = self.bus.take().unwrap();
+        self.bus = Some(self.recovery.bus_clear(bus));
+        self.stats.recoveries += 1;
+    }
+
+    fn transaction<T, E, F>(&mut self, retries: u8, mut f: F) -> Result<T, Error<E>>
+    where
+        F: FnMut(&mut B) -> Result<T, E>,
+    {
+        self.stats.transactions += 1;
+        let mut attempt = 0;
+        loop {
+            let result = if self.wait_idle() {
+                self.recovery.arm(self.config.transaction_timeout_us);
+                let result = f(self.bus.as_mut().unwrap());
+                self.recovery.disarm();
+                result.map_err(Error::Bus)
+            } else {
+                self.stats.timeouts += 1;
+                Err(Error::Timeout)
+            };
+
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            self.stats.errors += 1;
+            // A NACK leaves the bus idle, anything else might have left it or
+            // the peripheral in a state only a bus clear gets us out of.
+            let idle = self.recovery.is_idle(self.bus.as_mut().unwrap());
+            if !idle {
+                self.recover();
+            }
+
+            if attempt >= retries {
+                self.stats.failures += 1;
+                return Err(error);
+            }
+            attempt += 1;
+            self.stats.retries += 1;
+        }
+    }
+}
+
+impl<B, R, E> Write for RecoveringI2c<B, R>
+where
+    B: Write<Error = E>,
+    R: BusRecovery<B>,
+{
+    type Error = Error<E>;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.transaction(self.config.retries, |bus| bus.write(address, bytes))
+    }
+}
+
+impl<B, R, E> Read for RecoveringI2c<B, R>
+where
+    B: Read<Error = E>,
+    R: BusRecovery<B>,
+{
+    type Error = Error<E>;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.transaction(self.config.retries, |bus| bus.read(address, buffer))
+    }
+}
+
+impl<B, R, E> WriteRead for RecoveringI2c<B, R>
+where
+    B: WriteRead<Error = E>,
+    R: BusRecovery<B>,
+{
+    type Error = Error<E>;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.transaction(self.config.retries, |bus| {
+            bus.write_read(address, bytes, buffer)
+        })
+    }
+}
+
+impl<B, R, E> WriteReadOnce for RecoveringI2c<B, R>
+where
+    B: WriteRead<Error = E>,
+    R: BusRecovery<B>,
+{
+    fn write_read_once(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        // Still clears the bus after a failure, so the next transaction works
+        self.transaction(0, |bus| bus.write_read(address, bytes, buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bus that fails with a stuck SDA for the first `failures` transactions
+    #[derive(Default)]
+    struct Bus {
+        failures: u32,
+        stuck: bool,
+        writes: u32,
+    }
+
+    impl Write for Bus {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), ()> {
+            self.writes += 1;
+            if self.failures > 0 {
+                self.failures -= 1;
+                self.stuck = true;
+                return Err(());
+            }
+            Ok(())
+        }
+    }
+
+    impl WriteRead for Bus {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], _buffer: &mut [u8]) -> Result<(), ()> {
+            self.write(address, bytes)
+        }
+    }
+
+    #[derive(Default)]
+    struct Recovery {
+        clears: u32,
+        armed: Option<u32>,
+        arms: u32,
+    }
+
+    impl BusRecovery<Bus> for Recovery {
+        fn is_idle(&mut self, bus: &mut Bus) -> bool {
+            !bus.stuck
+        }
+
+        fn bus_clear(&mut self, mut bus: Bus) -> Bus {
+            self.clears += 1;
+            bus.stuck = false;
+            bus
+        }
+
+        fn delay_us(&mut self, _us: u32) {}
+
+        fn arm(&mut self, timeout_us: u32) {
+            assert_eq!(self.armed, None);
+            self.armed = Some(timeout_us);
+            self.arms += 1;
+        }
+
+        fn disarm(&mut self) {
+            assert!(self.armed.is_some());
+            self.armed = None;
+        }
+    }
+
+    #[test]
+    fn clears_stuck_bus_on_creation() {
+        let bus = Bus {
+            stuck: true,
+            ..Bus::default()
+        };
+        let i2c = RecoveringI2c::new(bus, Recovery::default(), Config::default());
+        assert_eq!(i2c.stats().recoveries, 1);
+        let (bus, recovery) = i2c.free();
+        assert!(!bus.stuck);
+        assert_eq!(recovery.clears, 1);
+    }
+
+    #[test]
+    fn arms_watchdog_around_every_transaction() {
+        let bus = Bus {
+            failures: 1,
+            ..Bus::default()
+        };
+        let mut i2c = RecoveringI2c::new(bus, Recovery::default(), Config::default());
+        i2c.write(0x19, &[0x20, 0x57]).unwrap();
+        let (bus, recovery) = i2c.free();
+        assert_eq!(bus.writes, 2);
+        assert_eq!(recovery.arms, 2);
+        assert_eq!(recovery.armed, None);
+        assert_eq!(recovery.clears, 1);
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let bus = Bus {
+            failures: 10,
+            ..Bus::default()
+        };
+        let config = Config::default();
+        let mut i2c = RecoveringI2c::new(bus, Recovery::default(), config);
+        assert!(matches!(i2c.write(0x19, &[0]), Err(Error::Bus(()))));
+        let stats = i2c.stats();
+        assert_eq!(stats.retries, u32::from(config.retries));
+        assert_eq!(stats.failures, 1);
+    }
+
+    #[test]
+    fn never_repeats_reads_with_side_effects() {
+        let bus = Bus {
+            failures: 1,
+            ..Bus::default()
+        };
+        let mut i2c = RecoveringI2c::new(bus, Recovery::default(), Config::default());
+        let mut buffer = [0; 6];
+        assert!(i2c.write_read_once(0x19, &[0xa8], &mut buffer).is_err());
+        // The bus was still cleared, so the next read goes through
+        i2c.write_read_once(0x19, &[0xa8], &mut buffer).unwrap();
+        let stats = i2c.stats();
+        assert_eq!(stats.retries, 0);
+        assert_eq!(stats.failures, 1);
+        let (bus, recovery) = i2c.free();
+        assert_eq!(bus.writes, 2);
+        assert_eq!(recovery.clears, 1);
+    }
+}
@@ -0,0 +1,28 @@
+//! Board independent code shared by the micro:bit and F3 chapters
+
+#![no_std]
+
+pub mod accel_fifo;
+pub mod ahrs;
+pub mod angle;
+pub mod button;
+pub mod calibration;
+pub mod calibrator;
+pub mod direction;
+pub mod ellipsoid;
+pub mod filter;
+pub mod fixed;
+pub mod font;
+pub mod gesture;
+pub mod i2c;
+pub mod line;
+pub mod needle;
+pub mod pedometer;
+pub mod peak;
+pub mod probe;
+pub mod queue;
+pub mod sensor;
+pub mod tilt;
+pub mod units;
+pub mod vector;
+pub mod wmm;
@@ -0,0 +1,65 @@
+//! Collects bytes received over serial into lines.
+
+use core::str;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineError {
+    /// The line did not fit into the buffer, everything up to its end was dropped
+    TooLong,
+    InvalidUtf8,
+}
+
+/// Buffers up to `N` bytes of a line. Lines end with `\r`, `\n` or both,
+/// empty lines are skipped.
+pub struct LineBuffer<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+    overflow: bool,
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> LineBuffer<N> {
+        LineBuffer::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> LineBuffer<N> {
+        LineBuffer {
+            buffer: [0; N],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Whether part of a line has been received
+    pub fn is_receiving(&self) -> bool {
+        self.len > 0 || self.overflow
+    }
+
+    /// Adds a byte, returns the line once it is complete
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, LineError>> {
+        if byte != b'\r' && byte != b'\n' {
+            match self.buffer.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+        if !self.is_receiving() {
+            return None;
+        }
+
+        let len = self.len;
+        let overflow = self.overflow;
+        self.len = 0;
+        self.overflow = false;
+        if overflow {
+            return Some(Err(LineError::TooLong));
+        }
+        Some(str::from_utf8(&self.buffer[..len]).map_err(|_| LineError::InvalidUtf8))
+    }
+}
@@ -0,0 +1,145 @@
+//! Draws a compass needle on the 16 outer LEDs of a 5x5 matrix.
+//!
+//! The needle falls between two LEDs most of the time. Splitting the
+//! brightness between them by how close it is to each gives a much finer
+//! resolution than the 22.5° a single LED can show.
+
+use libm::{atan2f, roundf};
+
+use crate::angle::normalize;
+
+/// Brightness of a fully lit LED, the greyscale range of the micro:bit display
+pub const MAX_BRIGHTNESS: u8 = 9;
+
+/// The outer LEDs as (row, column), clockwise from the middle of the top edge
+const PERIMETER: [(usize, usize); 16] = [
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 4),
+    (2, 4),
+    (3, 4),
+    (4, 4),
+    (4, 3),
+    (4, 2),
+    (4, 1),
+    (4, 0),
+    (3, 0),
+    (2, 0),
+    (1, 0),
+    (0, 0),
+    (0, 1),
+];
+
+/// Lights the outer LEDs towards `degrees`, measured clockwise from the top
+/// edge of the matrix. With `center_dot` the middle LED is lit as well.
+pub fn needle(degrees: f32, center_dot: bool) -> [[u8; 5]; 5] {
+    let mut image = [[0; 5]; 5];
+    if center_dot {
+        image[2][2] = MAX_BRIGHTNESS;
+    }
+
+    let target = normalize(degrees);
+    for (i, &(row, column)) in PERIMETER.iter().enumerate() {
+        let (next_row, next_column) = PERIMETER[(i + 1) % PERIMETER.len()];
+        let start = angle(row, column);
+        // The last LED's neighbor is the first one again, a full turn later
+        let end = match angle(next_row, next_column) {
+            end if end <= start => end + 360.0,
+            end => end,
+        };
+        if (start..end).contains(&target) {
+            let fraction = (target - start) / (end - start);
+            image[row][column] = brightness(1.0 - fraction);
+            image[next_row][next_column] = brightness(fraction);
+            break;
+        }
+    }
+    image
+}
+
+/// Direction of an LED seen from the middle one, clockwise from the top edge
+fn angle(row: usize, column: usize) -> f32 {
+    let (x, y) = (column as f32 - 2.0, 2.0 - row as f32);
+    normalize(atan2f(x, y).to_degrees())
+}
+
+fn brightness(fraction: f32) -> u8 {
+    roundf(fraction * MAX_BRIGHTNESS as f32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The LEDs that are lit, as (row, column, brightness)
+    fn lit(image: [[u8; 5]; 5]) -> [Option<(usize, usize, u8)>; 3] {
+        let mut lit = [None; 3];
+        let mut count = 0;
+        for (row, line) in image.iter().enumerate() {
+            for (column, &brightness) in line.iter().enumerate() {
+                if brightness > 0 {
+                    lit[count] = Some((row, column, brightness));
+                    count += 1;
+                }
+            }
+        }
+        lit
+    }
+
+    #[test]
+    fn cardinal_and_diagonal_angles_light_one_led() {
+        let expected = [
+            (0.0, (0, 2)),
+            (45.0, (0, 4)),
+            (90.0, (2, 4)),
+            (135.0, (4, 4)),
+            (180.0, (4, 2)),
+            (225.0, (4, 0)),
+            (270.0, (2, 0)),
+            (315.0, (0, 0)),
+        ];
+        for &(degrees, (row, column)) in expected.iter() {
+            assert_eq!(
+                lit(needle(degrees, false)),
+                [Some((row, column, MAX_BRIGHTNESS)), None, None],
+                "{}°",
+                degrees
+            );
+        }
+    }
+
+    #[test]
+    fn angles_outside_one_turn_wrap() {
+        assert_eq!(needle(-90.0, false), needle(270.0, false));
+        assert_eq!(needle(405.0, false), needle(45.0, false));
+        assert_eq!(needle(360.0, false), needle(0.0, false));
+    }
+
+    #[test]
+    fn splits_the_brightness_between_neighbors() {
+        // Halfway between the top middle LED and its right neighbor
+        let half = angle(0, 3) / 2.0;
+        assert_eq!(
+            lit(needle(half, false)),
+            [Some((0, 2, 5)), Some((0, 3, 5)), None]
+        );
+        // Just left of north, across the end of the perimeter
+        assert_eq!(
+            lit(needle(-5.0, false)),
+            [Some((0, 1, 2)), Some((0, 2, 7)), None]
+        );
+    }
+
+    #[test]
+    fn center_dot() {
+        assert_eq!(
+            lit(needle(90.0, true)),
+            [
+                Some((2, 2, MAX_BRIGHTNESS)),
+                Some((2, 4, MAX_BRIGHTNESS)),
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Finds the peak of a burst of acceleration, like a punch.
+//!
+//! The detector only arms once the signal is below the threshold, so a board
+//! that is already being shaken does not start a measurement right away. A
+//! crossing of the threshold starts a measurement window, at its end the
+//! largest sample seen is reported. During the cooldown after that, the
+//! detector ignores the signal so the board swinging back is not taken for
+//! the next punch.
+//!
+//! Samples carry timestamps in ms, which may wrap around.
+
+/// Thresholds and durations, in the unit of the samples and in ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config<T> {
+    /// A sample above this starts a measurement
+    pub threshold: T,
+    /// How long a measurement lasts, from the first sample above the threshold
+    pub window_ms: u32,
+    /// How long the signal is ignored after a measurement
+    pub cooldown_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State<T> {
+    /// Waiting for the signal to drop below the threshold
+    Idle,
+    /// Waiting for the signal to cross the threshold
+    Armed,
+    Measuring {
+        start: u32,
+        peak: Peak<T>,
+    },
+    /// Ignoring the signal until the cooldown is over
+    Reporting {
+        since: u32,
+    },
+}
+
+/// The largest sample of a measurement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak<T> {
+    pub value: T,
+    /// When the peak was sampled
+    pub time: u32,
+}
+
+pub struct PeakDetector<T> {
+    config: Config<T>,
+    state: State<T>,
+}
+
+impl<T: Copy + PartialOrd> PeakDetector<T> {
+    pub const fn new(config: Config<T>) -> PeakDetector<T> {
+        PeakDetector {
+            config,
+            state: State::Idle,
+        }
+    }
+
+    pub fn state(&self) -> State<T> {
+        self.state
+    }
+
+    /// Throws away a running measurement and waits for a calm signal again
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Feeds the next sample, returns the peak once a measurement is over
+    pub fn update(&mut self, value: T, now: u32) -> Option<Peak<T>> {
+        let Config {
+            threshold,
+            window_ms,
+            cooldown_ms,
+        } = self.config;
+        let mut result = None;
+
+        // A sample can end one state and already count for the next
+        if let State::Reporting { since } = self.state {
+            if now.wrapping_sub(since) >= cooldown_ms {
+                self.state = State::Idle;
+            }
+        }
+        if let State::Measuring { start, peak } = self.state {
+            if now.wrapping_sub(start) >= window_ms {
+                result = Some(peak);
+                self.state = State::Reporting { since: now };
+            }
+        }
+
+        self.state = match self.state {
+            State::Idle if value > threshold => State::Idle,
+            State::Idle => State::Armed,
+            State::Armed if value > threshold => State::Measuring {
+                start: now,
+                peak: Peak { value, time: now },
+            },
+            State::Measuring { start, peak } if value > peak.value => State::Measuring {
+                start,
+                peak: Peak { value, time: now },
+            },
+            state => state,
+        };
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: Config<i32> = Config {
+        threshold: 1_000,
+        window_ms: 100,
+        cooldown_ms: 500,
+    };
+
+    fn armed(start: u32) -> PeakDetector<i32> {
+        let mut detector = PeakDetector::new(CONFIG);
+        assert_eq!(detector.update(0, start), None);
+        assert_eq!(detector.state(), State::Armed);
+        detector
+    }
+
+    #[test]
+    fn arms_once_the_signal_is_calm() {
+        let mut detector = PeakDetector::new(CONFIG);
+        // Already shaking when it starts
+        assert_eq!(detector.update(2_000, 0), None);
+        assert_eq!(detector.state(), State::Idle);
+        assert_eq!(detector.update(1_000, 10), None);
+        assert_eq!(detector.state(), State::Armed);
+    }
+
+    #[test]
+    fn measures_from_the_first_crossing() {
+        let mut detector = armed(0);
+        assert_eq!(detector.update(1_000, 10), None);
+        assert_eq!(detector.state(), State::Armed);
+        assert_eq!(detector.update(1_500, 20), None);
+        let peak = Peak {
+            value: 1_500,
+            time: 20,
+        };
+        assert_eq!(detector.state(), State::Measuring { start: 20, peak });
+    }
+
+    #[test]
+    fn reports_the_peak_when_the_window_is_over() {
+        let mut detector = armed(0);
+        for &(value, time) in &[(1_500, 20), (3_000, 30), (2_000, 40), (500, 100)] {
+            assert_eq!(detector.update(value, time), None);
+        }
+        let peak = Peak {
+            value: 3_000,
+            time: 30,
+        };
+        assert_eq!(detector.update(0, 120), Some(peak));
+        assert_eq!(detector.state(), State::Reporting { since: 120 });
+    }
+
+    #[test]
+    fn ignores_the_signal_during_the_cooldown() {
+        let mut detector = armed(0);
+        detector.update(1_500, 20);
+        assert!(detector.update(0, 120).is_some());
+        // The board swinging back
+        assert_eq!(detector.update(5_000, 200), None);
+        assert_eq!(detector.update(0, 619), None);
+        assert_eq!(detector.state(), State::Reporting { since: 120 });
+        // Calm at the end of the cooldown arms it right away
+        assert_eq!(detector.update(0, 620), None);
+        assert_eq!(detector.state(), State::Armed);
+    }
+
+    #[test]
+    fn waits_for_calm_after_the_cooldown() {
+        let mut detector = armed(0);
+        detector.update(1_500, 20);
+        assert!(detector.update(1_500, 120).is_some());
+        assert_eq!(detector.update(1_500, 620), None);
+        assert_eq!(detector.state(), State::Idle);
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let start = u32::MAX - 50;
+        let mut detector = armed(start);
+        detector.update(2_000, start.wrapping_add(20));
+        assert_eq!(detector.update(2_500, start.wrapping_add(99)), None);
+        let peak = Peak {
+            value: 2_500,
+            time: start.wrapping_add(99),
+        };
+        assert_eq!(detector.update(0, start.wrapping_add(120)), Some(peak));
+        assert_eq!(detector.update(0, start.wrapping_add(619)), None);
+        assert_eq!(detector.update(0, start.wrapping_add(620)), None);
+        assert_eq!(detector.state(), State::Armed);
+    }
+
+    #[test]
+    fn reset_drops_a_measurement() {
+        let mut detector = armed(0);
+        detector.update(1_500, 20);
+        detector.reset();
+        assert_eq!(detector.state(), State::Idle);
+        assert_eq!(detector.update(0, 200), None);
+        assert_eq!(detector.state(), State::Armed);
+    }
+}
@@ -0,0 +1,418 @@
+//! Counts steps in accelerometer readings.
+//!
+//! Every step makes the magnitude of the acceleration swing around 1 g once,
+//! whichever way the board is worn. The magnitude is smoothed to get rid of
+//! noise, and a slow average of it is taken off so only the swing is left.
+//! A peak of the swing is a step if it is above the threshold, the middle
+//! between the highest and lowest value of the last window. That way the
+//! threshold follows along when walking turns into running.
+//!
+//! Single bumps are not steps, so steps only count once `Config::min_steps`
+//! of them came in a rhythm. Those are then added all at once.
+//!
+//! Samples carry timestamps in ms, which may wrap around. The data rate
+//! should be at least 25 Hz.
+
+use libm::sqrtf;
+
+use crate::units::Acceleration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// Time constant of the smoothing, in ms
+    pub smoothing_ms: u32,
+    /// Time constant of the average that is taken off, in ms
+    pub baseline_ms: u32,
+    /// How often the threshold is updated, in ms
+    pub window_ms: u32,
+    /// A smaller swing within the last window is the board being carried
+    /// around rather than someone walking
+    pub min_amplitude: Acceleration,
+    /// Peaks closer together than this are part of the same step
+    pub min_step_ms: u32,
+    /// A longer pause breaks the rhythm
+    pub max_step_ms: u32,
+    /// How many steps in a rhythm it takes before they count
+    pub min_steps: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            smoothing_ms: 40,
+            baseline_ms: 1_500,
+            window_ms: 1_000,
+            min_amplitude: Acceleration::from_mg(150),
+            min_step_ms: 250,
+            max_step_ms: 2_000,
+            min_steps: 4,
+        }
+    }
+}
+
+pub struct Pedometer {
+    config: Config,
+    steps: u32,
+    /// When the last sample was taken
+    last: Option<u32>,
+    /// The smoothed magnitude and its slow average, in mg
+    filtered: f32,
+    baseline: f32,
+    /// The previous value of the swing
+    previous: f32,
+    rising: bool,
+    window_start: u32,
+    window_max: f32,
+    window_min: f32,
+    threshold: f32,
+    /// The swing within the last window
+    amplitude: f32,
+    /// The swing went below the threshold since the last step
+    armed: bool,
+    last_step: Option<u32>,
+    /// Steps in the current rhythm, up to `Config::min_steps`
+    in_rhythm: u8,
+}
+
+impl Pedometer {
+    pub const fn new(config: Config) -> Pedometer {
+        Pedometer {
+            config,
+            steps: 0,
+            last: None,
+            filtered: 0.0,
+            baseline: 0.0,
+            previous: 0.0,
+            rising: false,
+            window_start: 0,
+            window_max: 0.0,
+            window_min: 0.0,
+            threshold: 0.0,
+            amplitude: 0.0,
+            armed: false,
+            last_step: None,
+            in_rhythm: 0,
+        }
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Continues counting from `steps`, like after a reset
+    pub fn set_steps(&mut self, steps: u32) {
+        self.steps = steps;
+    }
+
+    /// Feeds the next sample, returns whether the step count changed
+    pub fn update(&mut self, accel: [Acceleration; 3], now: u32) -> bool {
+        let [x, y, z] = accel.map(|axis| axis.mg() as f32);
+        let magnitude = sqrtf(x * x + y * y + z * z);
+        let dt = match self.last {
+            Some(last) => now.wrapping_sub(last) as f32,
+            None => {
+                self.last = Some(now);
+                self.filtered = magnitude;
+                self.baseline = magnitude;
+                self.window_start = now;
+                return false;
+            }
+        };
+        self.last = Some(now);
+
+        let smoothing = self.config.smoothing_ms as f32;
+        let baseline = self.config.baseline_ms as f32;
+        self.filtered += (magnitude - self.filtered) * dt / (smoothing + dt);
+        self.baseline += (magnitude - self.baseline) * dt / (baseline + dt);
+        let swing = self.filtered - self.baseline;
+
+        self.window_max = self.window_max.max(swing);
+        self.window_min = self.window_min.min(swing);
+        if now.wrapping_sub(self.window_start) >= self.config.window_ms {
+            self.threshold = (self.window_max + self.window_min) / 2.0;
+            self.amplitude = self.window_max - self.window_min;
+            self.window_start = now;
+            self.window_max = swing;
+            self.window_min = swing;
+        }
+
+        if swing < self.threshold {
+            self.armed = true;
+        }
+        // The previous sample was the top of a peak
+        let peak = self.rising && swing < self.previous;
+        let top = self.previous;
+        if swing != self.previous {
+            self.rising = swing > self.previous;
+        }
+        self.previous = swing;
+
+        peak && self.armed
+            && top > self.threshold
+            && self.amplitude >= self.config.min_amplitude.mg() as f32
+            && self.step(now)
+    }
+
+    fn step(&mut self, now: u32) -> bool {
+        let interval = self.last_step.map(|last| now.wrapping_sub(last));
+        if matches!(interval, Some(interval) if interval < self.config.min_step_ms) {
+            return false;
+        }
+        if !matches!(interval, Some(interval) if interval <= self.config.max_step_ms) {
+            self.in_rhythm = 0;
+        }
+        self.armed = false;
+        self.last_step = Some(now);
+
+        if self.in_rhythm < self.config.min_steps {
+            self.in_rhythm += 1;
+            if self.in_rhythm < self.config.min_steps {
+                return false;
+            }
+            // The rhythm is confirmed, the steps so far count as well
+            self.steps = self.steps.wrapping_add(u32::from(self.in_rhythm));
+        } else {
+            self.steps = self.steps.wrapping_add(1);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::f32::consts::PI;
+    use libm::{expf, sinf};
+    use std::vec::Vec;
+
+    /// 50 Hz, like the chapter's accelerometer
+    const PERIOD_MS: u32 = 20;
+
+    /// Walking: the magnitude swings by `amplitude` mg around 1 g `rate`
+    /// times a second, along `gravity` which has a length of 1
+    fn walk(rate: f32, amplitude: f32, gravity: [f32; 3]) -> impl Fn(u32) -> [i32; 3] {
+        move |t| {
+            let g = 1_000.0 + amplitude * sinf(2.0 * PI * rate * t as f32 / 1_000.0);
+            gravity.map(|axis| (axis * g) as i32)
+        }
+    }
+
+    /// Feeds `accel(t)` for `duration` ms from `start`
+    fn run(pedometer: &mut Pedometer, start: u32, duration: u32, accel: impl Fn(u32) -> [i32; 3]) {
+        for t in (0..duration).step_by(PERIOD_MS as usize) {
+            pedometer.update(accel(t).map(Acceleration::from_mg), start.wrapping_add(t));
+        }
+    }
+
+    #[test]
+    fn counts_walking() {
+        let mut pedometer = Pedometer::new(Config::default());
+        run(&mut pedometer, 0, 10_000, walk(2.0, 300.0, [0.0, 0.0, 1.0]));
+        // The first window only learns the threshold
+        assert!(
+            (17..=20).contains(&pedometer.steps()),
+            "{}",
+            pedometer.steps()
+        );
+    }
+
+    #[test]
+    fn follows_walking_into_running() {
+        let mut pedometer = Pedometer::new(Config::default());
+        run(&mut pedometer, 0, 10_000, walk(2.0, 300.0, [0.0, 0.0, 1.0]));
+        let walked = pedometer.steps();
+        let run_fast = walk(3.0, 1_200.0, [0.0, 0.0, 1.0]);
+        run(&mut pedometer, 10_000, 10_000, run_fast);
+        let ran = pedometer.steps() - walked;
+        assert!((27..=30).contains(&ran), "{}", ran);
+    }
+
+    #[test]
+    fn any_way_up() {
+        let tilted = [0.6, -0.48, 0.64];
+        let mut pedometer = Pedometer::new(Config::default());
+        run(&mut pedometer, 0, 10_000, walk(2.0, 300.0, tilted));
+        assert!(
+            (17..=20).contains(&pedometer.steps()),
+            "{}",
+            pedometer.steps()
+        );
+    }
+
+    #[test]
+    fn ignores_small_movements() {
+        let mut pedometer = Pedometer::new(Config::default());
+        run(&mut pedometer, 0, 10_000, walk(2.0, 50.0, [0.0, 0.0, 1.0]));
+        assert_eq!(pedometer.steps(), 0);
+    }
+
+    #[test]
+    fn single_bumps_are_no_steps() {
+        // A bump every 3 s is too slow for a rhythm
+        let bumps = |t: u32| match t % 3_000 {
+            1_500..=1_740 => [0, 0, 1_400],
+            _ => [0, 0, 1_000],
+        };
+        let mut pedometer = Pedometer::new(Config::default());
+        run(&mut pedometer, 0, 20_000, bumps);
+        assert_eq!(pedometer.steps(), 0);
+    }
+
+    #[test]
+    fn steps_count_once_in_rhythm() {
+        let mut pedometer = Pedometer::new(Config::default());
+        let walking = walk(2.0, 300.0, [0.0, 0.0, 1.0]);
+        let mut counts = [0; 3];
+        let mut changes = 0;
+        for t in (0..10_000).step_by(PERIOD_MS as usize) {
+            let sample = walking(t).map(Acceleration::from_mg);
+            if pedometer.update(sample, t) {
+                if changes < counts.len() {
+                    counts[changes] = pedometer.steps();
+                }
+                changes += 1;
+            }
+        }
+        // The first steps come all at once, then one at a time
+        assert_eq!(counts, [4, 5, 6]);
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let walking = walk(2.0, 300.0, [0.0, 0.0, 1.0]);
+        let mut from_zero = Pedometer::new(Config::default());
+        run(&mut from_zero, 0, 10_000, &walking);
+        let mut wrapping = Pedometer::new(Config::default());
+        run(&mut wrapping, u32::MAX - 5_000, 10_000, &walking);
+        assert_eq!(wrapping.steps(), from_zero.steps());
+    }
+
+    #[test]
+    fn continues_from_a_saved_count() {
+        let mut pedometer = Pedometer::new(Config::default());
+        pedometer.set_steps(1_000);
+        run(&mut pedometer, 0, 10_000, walk(2.0, 300.0, [0.0, 0.0, 1.0]));
+        assert!(
+            (1_017..=1_020).contains(&pedometer.steps()),
+            "{}",
+            pedometer.steps()
+        );
+    }
+
+    /// Deterministic noise, so a failing trace can be replayed
+    struct Noise(u32);
+
+    impl Noise {
+        /// Uniform within `[0, 1)`
+        fn next(&mut self) -> f32 {
+            // xorshift32
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn between(&mut self, low: f32, high: f32) -> f32 {
+            low + (high - low) * self.next()
+        }
+
+        /// Roughly normal with a standard deviation of `sigma`
+        fn gaussian(&mut self, sigma: f32) -> f32 {
+            (self.next() + self.next() + self.next() - 1.5) * 2.0 * sigma
+        }
+    }
+
+    /// A stretch of a recording: standing still, or a number of steps each
+    /// taking between the two durations in ms with a peak of about `impact`
+    /// mg
+    enum Segment {
+        Stand(u32),
+        Walk {
+            steps: u32,
+            step_ms: (f32, f32),
+            impact: f32,
+        },
+    }
+
+    /// Builds a labeled trace like a pocket would record it: every heel strike
+    /// is a sharp peak followed by a slower dip, the hips sway sideways, the
+    /// board is tilted, the sensor is noisy and the samples are not taken
+    /// exactly every 20 ms. Returns the samples with their time and the
+    /// number of steps taken.
+    fn recording(segments: &[Segment], seed: u32) -> (Vec<(u32, [i32; 3])>, u32) {
+        // Gravity along the board tilted in a pocket, with a length of 1
+        let down = [0.36, 0.48, 0.8];
+        let mut noise = Noise(seed);
+        let mut samples = Vec::new();
+        let mut labeled = 0;
+        let mut step_start = 0.0;
+        let mut t = 0.0;
+        for segment in segments {
+            let (steps, step_ms, impact) = match *segment {
+                Segment::Stand(ms) => (0, (ms as f32, ms as f32), 0.0),
+                Segment::Walk {
+                    steps,
+                    step_ms,
+                    impact,
+                } => (steps, step_ms, impact),
+            };
+            for step in 0..steps.max(1) {
+                let duration = noise.between(step_ms.0, step_ms.1);
+                let impact = impact * noise.between(0.8, 1.2);
+                // The hips sway to the side of the foot that is down
+                let side = if (labeled + step) % 2 == 0 { 1.0 } else { -1.0 };
+                while t < step_start + duration {
+                    let phase = (t - step_start) / duration;
+                    let strike = expf(-((phase - 0.12) / 0.06).powi(2));
+                    let dip = expf(-((phase - 0.55) / 0.18).powi(2));
+                    let vertical = 1_000.0 + impact * (strike - 0.4 * dip);
+                    let sideways = side * 0.2 * impact * sinf(PI * phase);
+                    let accel = [
+                        down[0] * vertical + sideways + noise.gaussian(40.0),
+                        down[1] * vertical + noise.gaussian(40.0),
+                        down[2] * vertical - 0.5 * sideways + noise.gaussian(40.0),
+                    ];
+                    samples.push((t as u32, accel.map(|axis| axis as i32)));
+                    t += PERIOD_MS as f32 + noise.between(-3.0, 3.0);
+                }
+                step_start += duration;
+            }
+            labeled += steps;
+        }
+        (samples, labeled)
+    }
+
+    #[test]
+    fn counts_a_labeled_walk() {
+        let segments = [
+            Segment::Stand(2_000),
+            Segment::Walk {
+                steps: 60,
+                step_ms: (450.0, 650.0),
+                impact: 500.0,
+            },
+            // Waiting at a crossing
+            Segment::Stand(3_000),
+            Segment::Walk {
+                steps: 40,
+                step_ms: (360.0, 520.0),
+                impact: 800.0,
+            },
+            Segment::Stand(2_000),
+        ];
+        for seed in [1, 0x2545_f491, 0xdead_beef] {
+            let (samples, labeled) = recording(&segments, seed);
+            assert_eq!(labeled, 100);
+            let mut pedometer = Pedometer::new(Config::default());
+            for (t, accel) in samples {
+                pedometer.update(accel.map(Acceleration::from_mg), t);
+            }
+            // Each start of walking may lose a few steps while the threshold
+            // adapts, but noise must not add any
+            let steps = pedometer.steps();
+            assert!((94..=100).contains(&steps), "seed {}: {}", seed, steps);
+        }
+    }
+}
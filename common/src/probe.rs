@@ -0,0 +1,292 @@
+//! Checks that the LSM303 on the I2C bus is the chip we expect it to be.
+
+use embedded_hal::blocking::i2c::WriteRead;
+
+pub const ACCELEROMETER_ADDR: u8 = 0b0011001;
+pub const MAGNETOMETER_ADDR: u8 = 0b0011110;
+
+pub const ACCELEROMETER_ID_REG: u8 = 0x0f;
+pub const MAGNETOMETER_ID_REG: u8 = 0x4f;
+
+/// `IRA_REG_M`, the first of the three identification registers of the
+/// LSM303DLHC magnetometer
+const DLHC_MAGNETOMETER_ID_REG: u8 = 0x0a;
+
+/// `WHO_AM_I_A` and `WHO_AM_I_M` of the LSM303AGR, see section 8 of its
+/// datasheet (DocID027765). The LSM303DLHC datasheet doesn't list
+/// `WHO_AM_I_A`, but its accelerometer answers with the same 0x33 there,
+/// which is also what ST's own F3 Discovery drivers check.
+pub const ACCELEROMETER_ID: u8 = 0x33;
+pub const MAGNETOMETER_ID: u8 = 0x40;
+/// `IRA_REG_M` to `IRC_REG_M` of the LSM303DLHC, "H43" in ASCII, see section
+/// 7.2 of its datasheet (DocID018771)
+const DLHC_MAGNETOMETER_ID: [u8; 3] = *b"H43";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Lsm303agr,
+    Lsm303dlhc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensor {
+    Accelerometer,
+    Magnetometer,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProbeError<E> {
+    /// Nobody acknowledged the address, the sensor is most likely missing
+    Nack(Sensor),
+    /// Something answered, but it is not the chip we were looking for
+    WrongId {
+        sensor: Sensor,
+        expected: u8,
+        found: u8,
+    },
+    /// Any other error reported by the bus
+    Bus(E),
+}
+
+/// Reads the identification registers of both sensors and returns the
+/// detected chip variant.
+///
+/// `is_nack` tells a missing device apart from other bus errors, it should
+/// only be `true` for an address NACK. A data NACK means the device
+/// acknowledged its address, so it is there and that is a bus error like any
+/// other.
+pub fn probe<I, E, F>(i2c: &mut I, is_nack: F) -> Result<Variant, ProbeError<E>>
+where
+    I: WriteRead<Error = E>,
+    F: Fn(&E) -> bool,
+{
+    // The LSM303DLHC has no WHO_AM_I_M, reading it will not give us 0x40 there.
+    let mut mag = [0];
+    read_registers(
+        i2c,
+        &is_nack,
+        Sensor::Magnetometer,
+        MAGNETOMETER_ADDR,
+        MAGNETOMETER_ID_REG,
+        &mut mag,
+    )?;
+    let variant = if mag[0] == MAGNETOMETER_ID {
+        Variant::Lsm303agr
+    } else {
+        // The magnetometer of the LSM303DLHC moves on to the next register by
+        // itself, so this reads all three
+        let mut ir = [0; 3];
+        read_registers(
+            i2c,
+            &is_nack,
+            Sensor::Magnetometer,
+            MAGNETOMETER_ADDR,
+            DLHC_MAGNETOMETER_ID_REG,
+            &mut ir,
+        )?;
+        if ir != DLHC_MAGNETOMETER_ID {
+            return Err(ProbeError::WrongId {
+                sensor: Sensor::Magnetometer,
+                expected: MAGNETOMETER_ID,
+                found: mag[0],
+            });
+        }
+        Variant::Lsm303dlhc
+    };
+
+    let mut acc = [0];
+    read_registers(
+        i2c,
+        &is_nack,
+        Sensor::Accelerometer,
+        ACCELEROMETER_ADDR,
+        ACCELEROMETER_ID_REG,
+        &mut acc,
+    )?;
+    if acc[0] != ACCELEROMETER_ID {
+        return Err(ProbeError::WrongId {
+            sensor: Sensor::Accelerometer,
+            expected: ACCELEROMETER_ID,
+            found: acc[0],
+        });
+    }
+    Ok(variant)
+}
+
+fn read_registers<I, E, F>(
+    i2c: &mut I,
+    is_nack: &F,
+    sensor: Sensor,
+    address: u8,
+    register: u8,
+    buffer: &mut [u8],
+) -> Result<(), ProbeError<E>>
+where
+    I: WriteRead<Error = E>,
+    F: Fn(&E) -> bool,
+{
+    // First write the address + register onto the bus, then read the chip's responses
+    match i2c.write_read(address, &[register], buffer) {
+        Ok(()) => Ok(()),
+        Err(e) if is_nack(&e) => Err(ProbeError::Nack(sensor)),
+        Err(e) => Err(ProbeError::Bus(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Error {
+        AddressNack,
+        DataNack,
+    }
+
+    fn is_nack(e: &Error) -> bool {
+        *e == Error::AddressNack
+    }
+
+    /// A bus with up to two devices that answer with the contents of their
+    /// registers, auto-incrementing like the LSM303 does
+    struct Bus {
+        accelerometer: Option<[u8; 0x40]>,
+        magnetometer: Option<[u8; 0x80]>,
+        /// Fails every transaction to this address with a data NACK
+        broken: Option<u8>,
+        reads: u32,
+    }
+
+    impl Bus {
+        fn agr() -> Bus {
+            let mut accelerometer = [0; 0x40];
+            accelerometer[usize::from(ACCELEROMETER_ID_REG)] = ACCELEROMETER_ID;
+            let mut magnetometer = [0; 0x80];
+            magnetometer[usize::from(MAGNETOMETER_ID_REG)] = MAGNETOMETER_ID;
+            Bus {
+                accelerometer: Some(accelerometer),
+                magnetometer: Some(magnetometer),
+                broken: None,
+                reads: 0,
+            }
+        }
+
+        fn dlhc() -> Bus {
+            let mut bus = Bus::agr();
+            let mut magnetometer = [0; 0x80];
+            magnetometer[0x0a..0x0d].copy_from_slice(b"H43");
+            bus.magnetometer = Some(magnetometer);
+            bus
+        }
+    }
+
+    impl WriteRead for Bus {
+        type Error = Error;
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Error> {
+            self.reads += 1;
+            if self.broken == Some(address) {
+                return Err(Error::DataNack);
+            }
+            let registers: &[u8] = match address {
+                ACCELEROMETER_ADDR => self.accelerometer.as_ref().map(|r| &r[..]),
+                MAGNETOMETER_ADDR => self.magnetometer.as_ref().map(|r| &r[..]),
+                _ => None,
+            }
+            .ok_or(Error::AddressNack)?;
+            let start = usize::from(bytes[0]);
+            buffer.copy_from_slice(&registers[start..start + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finds_the_lsm303agr() {
+        let mut bus = Bus::agr();
+        assert_eq!(probe(&mut bus, is_nack), Ok(Variant::Lsm303agr));
+        assert_eq!(bus.reads, 2);
+    }
+
+    #[test]
+    fn finds_the_lsm303dlhc() {
+        let mut bus = Bus::dlhc();
+        assert_eq!(probe(&mut bus, is_nack), Ok(Variant::Lsm303dlhc));
+        assert_eq!(bus.reads, 3);
+    }
+
+    #[test]
+    fn checks_the_accelerometer_of_the_lsm303dlhc() {
+        let mut bus = Bus::dlhc();
+        bus.accelerometer = Some([0; 0x40]);
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::WrongId {
+                sensor: Sensor::Accelerometer,
+                expected: ACCELEROMETER_ID,
+                found: 0,
+            })
+        );
+
+        let mut bus = Bus::dlhc();
+        bus.accelerometer = None;
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::Nack(Sensor::Accelerometer))
+        );
+    }
+
+    #[test]
+    fn reports_missing_devices() {
+        let mut bus = Bus::agr();
+        bus.magnetometer = None;
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::Nack(Sensor::Magnetometer))
+        );
+
+        let mut bus = Bus::agr();
+        bus.accelerometer = None;
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::Nack(Sensor::Accelerometer))
+        );
+    }
+
+    #[test]
+    fn reports_unknown_chips() {
+        let mut bus = Bus::agr();
+        let mut magnetometer = [0; 0x80];
+        magnetometer[usize::from(MAGNETOMETER_ID_REG)] = 0x3c;
+        bus.magnetometer = Some(magnetometer);
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::WrongId {
+                sensor: Sensor::Magnetometer,
+                expected: MAGNETOMETER_ID,
+                found: 0x3c,
+            })
+        );
+    }
+
+    #[test]
+    fn reports_other_bus_errors() {
+        let mut bus = Bus::agr();
+        bus.broken = Some(MAGNETOMETER_ADDR);
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::Bus(Error::DataNack))
+        );
+
+        let mut bus = Bus::agr();
+        bus.broken = Some(ACCELEROMETER_ADDR);
+        assert_eq!(
+            probe(&mut bus, is_nack),
+            Err(ProbeError::Bus(Error::DataNack))
+        );
+    }
+}
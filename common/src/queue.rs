@@ -0,0 +1,61 @@
+//! A fixed size first in, first out queue, for handing events from an
+//! interrupt handler to the main loop.
+
+/// Holds up to `N` items. When it is full, new items are dropped rather than
+/// overwriting ones the main loop has not seen yet.
+#[derive(Debug, Clone)]
+pub struct Queue<T, const N: usize> {
+    items: [Option<T>; N],
+    /// Index of the oldest item
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> Default for Queue<T, N> {
+    fn default() -> Queue<T, N> {
+        Queue::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    pub const fn new() -> Queue<T, N> {
+        Queue {
+            items: [None; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Adds an item at the end, gives it back if the queue is full
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(item);
+        }
+        self.items[(self.head + self.len) % N] = Some(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Takes the oldest item
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        item
+    }
+}
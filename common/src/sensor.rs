@@ -0,0 +1,22 @@
+//! Interfaces to the accelerometer and magnetometer, so the compass code does
+//! not need to know which LSM303 a board carries.
+//!
+//! Readings are `[x, y, z]`. Accelerometer readings are in the board frame
+//! `tilt` describes. The magnetometer may use any frame, as long as the board
+//! applies the calibration computed from it in the same frame.
+
+/// Reads the magnetic field
+pub trait Magnetometer {
+    type Error;
+
+    /// The field in nT, or `None` if there is no new reading since the last one
+    fn magnetic_field(&mut self) -> Result<Option<[i32; 3]>, Self::Error>;
+}
+
+/// Reads the acceleration
+pub trait Accelerometer {
+    type Error;
+
+    /// The acceleration in mg, or `None` if there is no new reading since the last one
+    fn acceleration(&mut self) -> Result<Option<[i32; 3]>, Self::Error>;
+}
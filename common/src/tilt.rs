@@ -0,0 +1,154 @@
+//! Tilt compensated compass heading.
+//!
+//! All vectors are in the board frame: x points to the right edge, y to the
+//! top edge and z out of the display. Lying flat with the display up, the
+//! accelerometer reads +1g on z. The math follows Freescale AN4248.
+
+use core::f32::consts::PI;
+use libm::{atan2f, atanf, cosf, sinf, sqrtf};
+
+use crate::vector::Vector3;
+
+/// Keeps the roll stable while the board is pointing straight up or down,
+/// see equation 38 of AN4248.
+const SINGULARITY_DAMPING: f32 = 0.01;
+
+/// Orientation of the board relative to the horizontal plane, in radians
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Attitude {
+    /// Positive while the top edge is raised, within `[-PI/2, PI/2]`
+    pub pitch: f32,
+    /// Positive while the right edge is lowered, within `[-PI, PI]`
+    pub roll: f32,
+}
+
+/// Computes pitch and roll from a static accelerometer reading.
+///
+/// Around a pitch of ±90° the roll is undefined. Instead of jumping around
+/// it smoothly goes towards 0 there.
+pub fn attitude(accel: Vector3) -> Attitude {
+    // Switch to the x forward, y right, z down frame AN4248 is written in,
+    // in which a board lying flat reads +1g on z.
+    let gx = -accel.y;
+    let gy = -accel.x;
+    let gz = accel.z;
+
+    let sign = if gz < 0.0 { -1.0 } else { 1.0 };
+    let roll = atan2f(gy, sign * sqrtf(gz * gz + SINGULARITY_DAMPING * gx * gx));
+    let pitch = atanf(-gx / (gy * sinf(roll) + gz * cosf(roll)));
+    Attitude { pitch, roll }
+}
+
+/// Rotates a magnetometer reading into the horizontal plane. Returns the
+/// components pointing to the front and to the right of the board.
+pub fn level(mag: Vector3, attitude: Attitude) -> (f32, f32) {
+    let bx = mag.y;
+    let by = mag.x;
+    let bz = -mag.z;
+
+    let (sin_pitch, cos_pitch) = (sinf(attitude.pitch), cosf(attitude.pitch));
+    let (sin_roll, cos_roll) = (sinf(attitude.roll), cosf(attitude.roll));
+
+    let forward = bx * cos_pitch + by * sin_pitch * sin_roll + bz * sin_pitch * cos_roll;
+    let right = by * cos_roll - bz * sin_roll;
+    (forward, right)
+}
+
+/// Heading of the top edge of the board in degrees, 0 is magnetic north and
+/// the heading grows clockwise. Valid in any orientation except with the top
+/// edge pointing straight up or down.
+pub fn heading(mag: Vector3, accel: Vector3) -> f32 {
+    let (forward, right) = level(mag, attitude(accel));
+    let heading = atan2f(-right, forward) * 180.0 / PI;
+    if heading < 0.0 {
+        heading + 360.0
+    } else {
+        heading
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::angle::distance;
+
+    /// Earth's field in east, north, up coordinates, pointing down into the
+    /// ground like in Europe
+    const FIELD: Vector3 = Vector3::new(0.0, 20_000.0, -44_000.0);
+    const GRAVITY: Vector3 = Vector3::new(0.0, 0.0, 1_000.0);
+
+    fn rotate_x(v: Vector3, angle: f32) -> Vector3 {
+        let (sin, cos) = (sinf(angle), cosf(angle));
+        Vector3::new(v.x, v.y * cos - v.z * sin, v.y * sin + v.z * cos)
+    }
+
+    fn rotate_y(v: Vector3, angle: f32) -> Vector3 {
+        let (sin, cos) = (sinf(angle), cosf(angle));
+        Vector3::new(v.x * cos + v.z * sin, v.y, -v.x * sin + v.z * cos)
+    }
+
+    fn rotate_z(v: Vector3, angle: f32) -> Vector3 {
+        let (sin, cos) = (sinf(angle), cosf(angle));
+        Vector3::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos, v.z)
+    }
+
+    /// What the board reads when its top edge points to `heading`, then is
+    /// pitched and rolled, all in degrees. Returns `(mag, accel)`.
+    fn readings(heading: f32, pitch: f32, roll: f32) -> (Vector3, Vector3) {
+        // The inverse of turning the board, applied to the world vectors
+        let to_board = |v: Vector3| {
+            let v = rotate_z(v, heading.to_radians());
+            let v = rotate_x(v, -pitch.to_radians());
+            rotate_y(v, -roll.to_radians())
+        };
+        (to_board(FIELD), to_board(GRAVITY))
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            distance(actual, expected) <= tolerance,
+            "{} is not {} ± {}",
+            actual,
+            expected,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn flat_headings() {
+        for heading in (0..360).step_by(15) {
+            let (mag, accel) = readings(heading as f32, 0.0, 0.0);
+            assert_close(super::heading(mag, accel), heading as f32, 0.1);
+        }
+    }
+
+    #[test]
+    fn attitude_of_tilted_board() {
+        for &(pitch, roll) in &[(30.0, 0.0), (0.0, 45.0), (-20.0, -60.0), (60.0, 150.0)] {
+            let (_, accel) = readings(123.0, pitch, roll);
+            let attitude = attitude(accel);
+            assert_close(attitude.pitch.to_degrees(), pitch, 0.5);
+            assert_close(attitude.roll.to_degrees(), roll, 0.5);
+        }
+    }
+
+    #[test]
+    fn tilt_compensated_headings() {
+        for heading in (0..360).step_by(30) {
+            for &(pitch, roll) in &[(30.0, 0.0), (0.0, -40.0), (-25.0, 35.0), (45.0, 170.0)] {
+                let (mag, accel) = readings(heading as f32, pitch, roll);
+                assert_close(super::heading(mag, accel), heading as f32, 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn roll_settles_when_pointing_up() {
+        // Close to vertical the roll would follow the noise, it is pulled
+        // towards 0 instead
+        let (_, accel) = readings(0.0, 89.0, 20.0);
+        let attitude = attitude(accel);
+        assert_close(attitude.pitch.to_degrees(), 89.0, 0.5);
+        assert_close(attitude.roll.to_degrees(), 0.0, 5.0);
+    }
+}
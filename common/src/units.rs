@@ -0,0 +1,394 @@
+//! Sensor readings with their unit attached, and the conversions from the
+//! raw register values of both LSM303s.
+//!
+//! Accelerations are stored as whole mg and magnetic fields as whole nT, the
+//! resolution of the sensors, so they stay integers on the nRF51. The float
+//! getters are for printing and further math. `Display` prints the unit and
+//! takes the precision from the format string, like `{:.1}`.
+
+use core::fmt;
+use core::ops::{Add, Neg, Sub};
+
+use crate::angle::normalize;
+
+const STANDARD_GRAVITY: f32 = 9.806_65;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Acceleration(i32);
+
+impl Acceleration {
+    pub const ZERO: Acceleration = Acceleration(0);
+
+    pub const fn from_mg(mg: i32) -> Acceleration {
+        Acceleration(mg)
+    }
+
+    pub const fn mg(self) -> i32 {
+        self.0
+    }
+
+    pub fn g(self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+
+    pub fn m_per_s2(self) -> f32 {
+        self.g() * STANDARD_GRAVITY
+    }
+
+    pub fn abs(self) -> Acceleration {
+        Acceleration(self.0.saturating_abs())
+    }
+}
+
+impl fmt::Display for Acceleration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.*} g", f.precision().unwrap_or(3), self.g())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MagneticField(i32);
+
+impl MagneticField {
+    pub const ZERO: MagneticField = MagneticField(0);
+
+    pub const fn from_nt(nt: i32) -> MagneticField {
+        MagneticField(nt)
+    }
+
+    pub const fn nt(self) -> i32 {
+        self.0
+    }
+
+    pub fn ut(self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+
+    pub fn abs(self) -> MagneticField {
+        MagneticField(self.0.saturating_abs())
+    }
+}
+
+impl fmt::Display for MagneticField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.*} µT", f.precision().unwrap_or(1), self.ut())
+    }
+}
+
+/// An angle, like a heading or a tilt
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Angle(f32);
+
+impl Angle {
+    pub const fn from_degrees(degrees: f32) -> Angle {
+        Angle(degrees)
+    }
+
+    pub fn from_radians(radians: f32) -> Angle {
+        Angle(radians.to_degrees())
+    }
+
+    pub const fn degrees(self) -> f32 {
+        self.0
+    }
+
+    pub fn radians(self) -> f32 {
+        self.0.to_radians()
+    }
+
+    /// The same angle within `[0, 360)`, like a compass heading
+    pub fn normalize(self) -> Angle {
+        Angle(normalize(self.0))
+    }
+}
+
+impl fmt::Display for Angle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.*}°", f.precision().unwrap_or(1), self.0)
+    }
+}
+
+macro_rules! impl_ops {
+    ($($unit:ident),* => $add:path, $sub:path, $neg:path) => {$(
+        impl Add for $unit {
+            type Output = $unit;
+
+            fn add(self, other: $unit) -> $unit {
+                $unit($add(self.0, other.0))
+            }
+        }
+
+        impl Sub for $unit {
+            type Output = $unit;
+
+            fn sub(self, other: $unit) -> $unit {
+                $unit($sub(self.0, other.0))
+            }
+        }
+
+        impl Neg for $unit {
+            type Output = $unit;
+
+            fn neg(self) -> $unit {
+                $unit($neg(self.0))
+            }
+        }
+    )*};
+}
+
+// Like `abs`, the integer units saturate rather than overflow
+impl_ops!(
+    Acceleration, MagneticField =>
+    i32::saturating_add, i32::saturating_sub, i32::saturating_neg
+);
+impl_ops!(Angle => f32::add, f32::sub, f32::neg);
+
+/// Which LSM303 a reading comes from, their sensitivities differ slightly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Lsm303agr,
+    Lsm303dlhc,
+}
+
+/// Accelerometer full scale. The `lsm303dlhc` crate calls these `G1`, `G2`,
+/// `G4` and `G12`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Range {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+/// How many bits the accelerometer resolves, set by its power mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Low power mode, 8 bits
+    LowPower,
+    /// Normal mode, 10 bits
+    Normal,
+    /// High resolution mode, 12 bits
+    High,
+}
+
+/// Everything needed to turn a raw accelerometer reading into an acceleration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccelSensitivity {
+    pub chip: Chip,
+    pub range: Range,
+    pub resolution: Resolution,
+}
+
+impl AccelSensitivity {
+    /// µg per LSB, from the datasheets
+    fn ug_per_lsb(self) -> i32 {
+        let high = match (self.chip, self.range) {
+            (Chip::Lsm303agr, Range::G2) => 980,
+            (Chip::Lsm303agr, Range::G4) => 1_950,
+            (Chip::Lsm303agr, Range::G8) => 3_900,
+            (Chip::Lsm303agr, Range::G16) => 11_720,
+            (Chip::Lsm303dlhc, Range::G2) => 1_000,
+            (Chip::Lsm303dlhc, Range::G4) => 2_000,
+            (Chip::Lsm303dlhc, Range::G8) => 4_000,
+            (Chip::Lsm303dlhc, Range::G16) => 12_000,
+        };
+        // Every bit less resolution doubles the step
+        high << (12 - self.bits())
+    }
+
+    fn bits(self) -> u32 {
+        match self.resolution {
+            Resolution::LowPower => 8,
+            Resolution::Normal => 10,
+            Resolution::High => 12,
+        }
+    }
+
+    /// Converts the value of an `OUT_*_A` register pair. The reading is left
+    /// aligned, the unused low bits are dropped.
+    pub fn acceleration(self, raw: i16) -> Acceleration {
+        let value = i32::from(raw) >> (16 - self.bits());
+        Acceleration(value * self.ug_per_lsb() / 1000)
+    }
+}
+
+/// Full scale of the LSM303DLHC's magnetometer in gauss. The LSM303AGR has a
+/// single range, see `lsm303agr_magnetic_field`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MagRange {
+    Gauss1_3,
+    Gauss1_9,
+    Gauss2_5,
+    Gauss4_0,
+    Gauss4_7,
+    Gauss5_6,
+    Gauss8_1,
+}
+
+impl MagRange {
+    /// LSB per gauss of the x and y axes and of the z axis
+    fn gain(self) -> (i32, i32) {
+        match self {
+            MagRange::Gauss1_3 => (1100, 980),
+            MagRange::Gauss1_9 => (855, 760),
+            MagRange::Gauss2_5 => (670, 600),
+            MagRange::Gauss4_0 => (450, 400),
+            MagRange::Gauss4_7 => (400, 355),
+            MagRange::Gauss5_6 => (330, 295),
+            MagRange::Gauss8_1 => (230, 205),
+        }
+    }
+
+    /// Converts a raw LSM303DLHC reading, `[x, y, z]`
+    pub fn magnetic_field(self, raw: [i16; 3]) -> [MagneticField; 3] {
+        const NT_PER_GAUSS: i32 = 100_000;
+        let (xy, z) = self.gain();
+        // A full scale reading times `NT_PER_GAUSS` does not fit into an `i32`
+        let convert = |raw: i16, gain: i32| {
+            MagneticField((i64::from(raw) * i64::from(NT_PER_GAUSS) / i64::from(gain)) as i32)
+        };
+        [convert(raw[0], xy), convert(raw[1], xy), convert(raw[2], z)]
+    }
+}
+
+/// Converts a raw LSM303AGR magnetometer reading, `[x, y, z]`. All axes have
+/// the same 1.5 mgauss, 150 nT, per LSB (table 3 of its datasheet).
+pub fn lsm303agr_magnetic_field(raw: [i16; 3]) -> [MagneticField; 3] {
+    const NT_PER_LSB: i32 = 150;
+    raw.map(|raw| MagneticField(i32::from(raw) * NT_PER_LSB))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::format;
+
+    /// mg per digit in table 3 of the LSM303AGR datasheet (DocID027765),
+    /// for `RANGES` in high resolution, normal and low power mode
+    const LSM303AGR: [[f32; 4]; 3] = [
+        [0.98, 1.95, 3.9, 11.72],
+        [3.9, 7.82, 15.63, 46.9],
+        [15.63, 31.26, 62.52, 187.58],
+    ];
+    /// The LSM303DLHC datasheet (DocID018771) only lists the high resolution
+    /// sensitivities in its table 3, the other modes drop the low bits
+    const LSM303DLHC: [[f32; 4]; 3] = [
+        [1.0, 2.0, 4.0, 12.0],
+        [4.0, 8.0, 16.0, 48.0],
+        [16.0, 32.0, 64.0, 192.0],
+    ];
+    const RANGES: [Range; 4] = [Range::G2, Range::G4, Range::G8, Range::G16];
+    const RESOLUTIONS: [Resolution; 3] =
+        [Resolution::High, Resolution::Normal, Resolution::LowPower];
+
+    #[test]
+    fn converts_accelerations_like_the_datasheets() {
+        for (chip, table) in [(Chip::Lsm303agr, LSM303AGR), (Chip::Lsm303dlhc, LSM303DLHC)] {
+            for (resolution, row) in RESOLUTIONS.iter().copied().zip(table) {
+                for (range, mg_per_digit) in RANGES.iter().copied().zip(row) {
+                    let sensitivity = AccelSensitivity {
+                        chip,
+                        range,
+                        resolution,
+                    };
+                    // 100 digits fit even the 8 bits of the low power mode
+                    for digits in [100, -100] {
+                        let raw = digits << (16 - sensitivity.bits());
+                        let expected = f32::from(digits) * mg_per_digit;
+                        let mg = sensitivity.acceleration(raw).mg() as f32;
+                        assert!(
+                            (mg - expected).abs() <= expected.abs() * 0.01 + 1.0,
+                            "{:?}: {} mg instead of {} mg",
+                            sensitivity,
+                            mg,
+                            expected
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn drops_the_unused_low_bits() {
+        let sensitivity = AccelSensitivity {
+            chip: Chip::Lsm303dlhc,
+            range: Range::G2,
+            resolution: Resolution::High,
+        };
+        assert_eq!(sensitivity.acceleration(0x000f).mg(), 0);
+        assert_eq!(sensitivity.acceleration(0x0010).mg(), 1);
+        assert_eq!(sensitivity.acceleration(i16::MAX).mg(), 2_047);
+        assert_eq!(sensitivity.acceleration(i16::MIN).mg(), -2_048);
+    }
+
+    #[test]
+    fn converts_magnetic_fields_per_gain() {
+        // LSB per gauss of the x and y axes and of the z axis, table 3 of the
+        // LSM303DLHC datasheet
+        let gains = [
+            (MagRange::Gauss1_3, 1100, 980),
+            (MagRange::Gauss1_9, 855, 760),
+            (MagRange::Gauss2_5, 670, 600),
+            (MagRange::Gauss4_0, 450, 400),
+            (MagRange::Gauss4_7, 400, 355),
+            (MagRange::Gauss5_6, 330, 295),
+            (MagRange::Gauss8_1, 230, 205),
+        ];
+        for (range, xy, z) in gains {
+            // One gauss is 100 µT
+            let field = range.magnetic_field([xy, -xy, z]);
+            assert_eq!(field.map(MagneticField::nt), [100_000, -100_000, 100_000]);
+        }
+        // Full scale readings do not overflow
+        let field = MagRange::Gauss1_3.magnetic_field([i16::MAX, i16::MIN, i16::MAX]);
+        assert_eq!(
+            field.map(MagneticField::nt),
+            [2_978_818, -2_978_909, 3_343_571]
+        );
+    }
+
+    #[test]
+    fn converts_lsm303agr_magnetic_fields() {
+        let field = lsm303agr_magnetic_field([1, -1, 320]);
+        assert_eq!(field.map(MagneticField::nt), [150, -150, 48_000]);
+        let field = lsm303agr_magnetic_field([i16::MAX, i16::MIN, 0]);
+        assert_eq!(field.map(MagneticField::nt), [4_915_050, -4_915_200, 0]);
+    }
+
+    #[test]
+    fn displays_with_the_requested_precision() {
+        let acceleration = Acceleration::from_mg(1_234);
+        assert_eq!(format!("{}", acceleration), "1.234 g");
+        assert_eq!(format!("{:.1}", acceleration), "1.2 g");
+        assert_eq!(format!("{}", -acceleration), "-1.234 g");
+
+        let field = MagneticField::from_nt(48_550);
+        assert_eq!(format!("{}", field), "48.5 µT");
+        assert_eq!(format!("{:.3}", field), "48.550 µT");
+
+        let angle = Angle::from_degrees(12.345);
+        assert_eq!(format!("{}", angle), "12.3°");
+        assert_eq!(format!("{:.0}", angle), "12°");
+    }
+
+    #[test]
+    fn saturates_instead_of_overflowing() {
+        let max = Acceleration::from_mg(i32::MAX);
+        let min = Acceleration::from_mg(i32::MIN);
+        let one = Acceleration::from_mg(1);
+        assert_eq!(max + one, max);
+        assert_eq!(min - one, min);
+        assert_eq!(-min, max);
+        assert_eq!(min.abs(), max);
+        assert_eq!(one + one - one, one);
+
+        let max = MagneticField::from_nt(i32::MAX);
+        let min = MagneticField::from_nt(i32::MIN);
+        assert_eq!(max + max, max);
+        assert_eq!(min + min, min);
+        assert_eq!(-min, max);
+    }
+}
@@ -0,0 +1,157 @@
+//! Frees the I2C bus when the LSM303DLHC got stuck holding SDA low.
+//!
+//! TIM7 watches every transaction. If one hangs, the app's `TIM7` interrupt
+//! handler calls `on_timeout` to reset the chip. The bus is cleared when
+//! `RecoveringI2c` is created again after the reset.
+
+// Reading the line levels and setting up TIM7 needs the raw PAC
+#![allow(unsafe_code)]
+
+use cortex_m::peripheral::{NVIC, SCB};
+use discovery_common::i2c::BusRecovery;
+use stm32f3_discovery::stm32f3xx_hal::{
+    gpio::gpiob::{PB6, PB7, AFRL, MODER, OTYPER},
+    gpio::{OpenDrain, Output, AF4},
+    i2c::I2c,
+    prelude::*,
+    rcc::{Clocks, APB1},
+    stm32::{self, GPIOB, I2C1, RCC, TIM7},
+    time::Hertz,
+};
+
+pub type I2cBus = I2c<I2C1, (PB6<AF4>, PB7<AF4>)>;
+
+// Half a clock period at 100kHz, slow enough for any slave
+const HALF_PERIOD_US: u32 = 5;
+
+// TIM7 counts to 16 bits, in us
+const MAX_TIMEOUT_US: u32 = 0xffff;
+
+/// Owns what's needed to turn the I2C pins into GPIOs and back, and the
+/// timer watching the transactions.
+pub struct BusClear {
+    moder: MODER,
+    otyper: OTYPER,
+    afrl: AFRL,
+    clocks: Clocks,
+    apb1: APB1,
+    timer: TIM7,
+    /// What the peripheral is set up with again after a bus clear
+    frequency: Hertz,
+}
+
+impl BusClear {
+    /// `frequency` has to be the one the I2C peripheral was created with
+    pub fn new<F: Into<Hertz>>(
+        moder: MODER,
+        otyper: OTYPER,
+        afrl: AFRL,
+        clocks: Clocks,
+        apb1: APB1,
+        timer: TIM7,
+        frequency: F,
+    ) -> BusClear {
+        unsafe { (*RCC::ptr()).apb1enr.modify(|_, w| w.tim7en().set_bit()) };
+        // The timer runs at twice the APB1 clock unless that is not divided
+        let timer_clock = if clocks.ppre1() == 1 {
+            clocks.pclk1().0
+        } else {
+            clocks.pclk1().0 * 2
+        };
+        // Count once per us, stop after one period and only interrupt when the
+        // period is over, not when the prescaler is loaded
+        let prescaler = timer_clock / 1_000_000 - 1;
+        timer.psc.write(|w| unsafe { w.bits(prescaler) });
+        timer.cr1.write(|w| w.opm().set_bit().urs().set_bit());
+        timer.egr.write(|w| w.ug().set_bit());
+        timer.dier.write(|w| w.uie().set_bit());
+        unsafe { NVIC::unmask(stm32::Interrupt::TIM7) };
+
+        BusClear {
+            moder,
+            otyper,
+            afrl,
+            clocks,
+            apb1,
+            timer,
+            frequency: frequency.into(),
+        }
+    }
+
+    pub fn free(self) -> TIM7 {
+        self.timer.cr1.modify(|_, w| w.cen().clear_bit());
+        NVIC::mask(stm32::Interrupt::TIM7);
+        self.timer
+    }
+
+    fn sda_is_high(&self, _sda: &PB7<Output<OpenDrain>>) -> bool {
+        unsafe { (*GPIOB::ptr()).idr.read().idr7().bit_is_set() }
+    }
+}
+
+impl BusRecovery<I2cBus> for BusClear {
+    fn is_idle(&mut self, _bus: &mut I2cBus) -> bool {
+        let (idr, isr) = unsafe {
+            (
+                (*GPIOB::ptr()).idr.read(),
+                (*I2C1::ptr()).isr.read(),
+            )
+        };
+        idr.idr6().bit_is_set() && idr.idr7().bit_is_set() && isr.busy().bit_is_clear()
+    }
+
+    fn bus_clear(&mut self, bus: I2cBus) -> I2cBus {
+        let (i2c, (scl, sda)) = bus.free();
+        let mut scl = scl.into_open_drain_output(&mut self.moder, &mut self.otyper);
+        let mut sda = sda.into_open_drain_output(&mut self.moder, &mut self.otyper);
+        scl.set_high().unwrap();
+        sda.set_high().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+
+        // Clock out whatever the slave still wants to send until it lets go of SDA
+        for _ in 0..9 {
+            if self.sda_is_high(&sda) {
+                break;
+            }
+            scl.set_low().unwrap();
+            self.delay_us(HALF_PERIOD_US);
+            scl.set_high().unwrap();
+            self.delay_us(HALF_PERIOD_US);
+        }
+
+        // STOP condition: SDA goes high while SCL is high
+        scl.set_low().unwrap();
+        sda.set_low().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+        scl.set_high().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+        sda.set_high().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+
+        let scl = scl.into_af4(&mut self.moder, &mut self.afrl);
+        let sda = sda.into_af4(&mut self.moder, &mut self.afrl);
+        I2c::new(i2c, (scl, sda), self.frequency, self.clocks, &mut self.apb1)
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        cortex_m::asm::delay(us * (self.clocks.sysclk().0 / 1_000_000));
+    }
+
+    fn arm(&mut self, timeout_us: u32) {
+        let timeout = timeout_us.min(MAX_TIMEOUT_US);
+        self.timer.arr.write(|w| unsafe { w.bits(timeout) });
+        self.timer.cnt.write(|w| unsafe { w.bits(0) });
+        self.timer.cr1.modify(|_, w| w.cen().set_bit());
+    }
+
+    fn disarm(&mut self) {
+        self.timer.cr1.modify(|_, w| w.cen().clear_bit());
+        self.timer.sr.write(|w| w.uif().clear_bit());
+    }
+}
+
+/// A transaction hung, start over. Call this from the `TIM7` interrupt
+/// handler, it is only enabled while `BusClear` owns the timer.
+pub fn on_timeout() -> ! {
+    SCB::sys_reset()
+}
@@ -0,0 +1,6 @@
+//! Board support shared by the F3 chapters
+
+#![deny(unsafe_code)]
+#![no_std]
+
+pub mod bus_clear;
@@ -10,15 +10,18 @@ pub use cortex_m_rt::entry;
 pub use stm32f3_discovery::stm32f3xx_hal::{delay::Delay, prelude, stm32::i2c1};
 
 use cortex_m::peripheral::ITM;
+use discovery_common::i2c::{Config, RecoveringI2c};
 use stm32f3_discovery::{
     lsm303dlhc::Lsm303dlhc,
     stm32f3xx_hal::{
         i2c::I2c,
         prelude::*,
-        stm32::{self, I2C1},
+        stm32::{self, interrupt, I2C1},
     },
 };
 
+use discovery_f3::bus_clear::{self, BusClear};
+
 pub fn init() -> (&'static i2c1::RegisterBlock, Delay, ITM) {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = stm32::Peripherals::take().unwrap();
@@ -32,7 +35,21 @@ pub fn init() -> (&'static i2c1::RegisterBlock, Delay, ITM) {
     let scl = gpiob.pb6.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
     let sda = gpiob.pb7.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
 
-    let i2c = I2c::new(dp.I2C1, (scl, sda), 400.khz(), clocks, &mut rcc.apb1);
+    let frequency = 400.khz();
+    let i2c = I2c::new(dp.I2C1, (scl, sda), frequency, clocks, &mut rcc.apb1);
+    let bus_clear = BusClear::new(
+        gpiob.moder,
+        gpiob.otyper,
+        gpiob.afrl,
+        clocks,
+        rcc.apb1,
+        dp.TIM7,
+        frequency,
+    );
+    // The chapter talks to the registers directly, so the wrapper is only
+    // used to free a bus left stuck by a reset in the middle of a transfer
+    let (i2c, bus_clear) = RecoveringI2c::new(i2c, bus_clear, Config::default()).free();
+    bus_clear.free();
 
     Lsm303dlhc::new(i2c).unwrap();
 
@@ -40,3 +57,9 @@ pub fn init() -> (&'static i2c1::RegisterBlock, Delay, ITM) {
 
     unsafe { (&mut *(I2C1::ptr() as *mut _), delay, cp.ITM) }
 }
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIM7() {
+    bus_clear::on_timeout();
+}
@@ -0,0 +1,57 @@
+//! Readings for `discovery_common::ahrs::Mahony`, in the board frame it
+//! expects: x points to the East LED, y to the North LED and z out of the
+//! board.
+//!
+//! The L3GD20's axes already point that way. The LSM303DLHC is turned by 90°,
+//! its x axis points to the South LED and its y axis to the East LED.
+
+use discovery_common::calibration::Calibration;
+use discovery_common::vector::Vector3;
+use stm32f3_discovery::{
+    lsm303dlhc::I16x3,
+    stm32f3xx_hal::{
+        gpio::{
+            gpioa::{PA5, PA6, PA7},
+            gpioe::PE3,
+            Output, PushPull, AF5,
+        },
+        spi::{self, Spi},
+        stm32::SPI1,
+    },
+};
+
+use crate::calibration::{Error, ACCEL_SENSITIVITY};
+use crate::{calibrated_mag, Lsm303dlhc};
+
+pub type Spi1 = Spi<SPI1, (PA5<AF5>, PA6<AF5>, PA7<AF5>)>;
+pub type Nss = PE3<Output<PushPull>>;
+pub type L3gd20 = l3gd20::L3gd20<Spi1, Nss>;
+
+/// The L3GD20 starts out with a full scale of 250 dps, where one LSB is
+/// 8.75 mdps (table 4 of its datasheet, DocID022116)
+const DPS_PER_LSB: f32 = 0.00875;
+
+/// The angular rate in rad/s
+pub fn gyro(l3gd20: &mut L3gd20) -> Result<Vector3, spi::Error> {
+    let rate = l3gd20.gyro()?;
+    let [x, y, z] = [rate.x, rate.y, rate.z].map(|r| (f32::from(r) * DPS_PER_LSB).to_radians());
+    Ok(Vector3::new(x, y, z))
+}
+
+/// The acceleration in mg
+pub fn accel(lsm303dlhc: &mut Lsm303dlhc) -> Result<Vector3, Error> {
+    let accel = lsm303dlhc.accel()?;
+    Ok(to_board(
+        [accel.x, accel.y, accel.z].map(|a| ACCEL_SENSITIVITY.acceleration(a).mg()),
+    ))
+}
+
+/// The calibrated magnetic field in nT
+pub fn mag(lsm303dlhc: &mut Lsm303dlhc, calibration: &Calibration) -> Result<Vector3, Error> {
+    let mag: I16x3 = lsm303dlhc.mag()?;
+    Ok(to_board(calibrated_mag(mag, calibration)))
+}
+
+fn to_board([x, y, z]: [i32; 3]) -> Vector3 {
+    Vector3::new(y as f32, -x as f32, z as f32)
+}
@@ -0,0 +1,184 @@
+//! Plays the calibration game from `discovery_common::calibrator` with the
+//! LSM303DLHC and the compass LEDs.
+
+use discovery_common::button::{self, Button, ButtonEvent, Recognizer};
+use discovery_common::calibration::Calibration;
+use discovery_common::calibrator::{self, Algorithm, CalibrationError, CalibrationUi, Request};
+use discovery_common::i2c;
+use discovery_common::sensor::{Accelerometer, Magnetometer};
+use discovery_common::units::{AccelSensitivity, Chip, MagRange, Range, Resolution};
+use stm32f3_discovery::{
+    leds::Led,
+    lsm303dlhc::I16x3,
+    stm32f3xx_hal::{delay::Delay, hal::blocking::delay::DelayUs, i2c::Error as BusError},
+    switch_hal::OutputSwitch,
+};
+
+use crate::{user_button, Lsm303dlhc};
+
+/// The magnetometer's default range
+const MAG_RANGE: MagRange = MagRange::Gauss1_3;
+/// The accelerometer's default range, `Lsm303dlhc::new` turns on high resolution
+pub(crate) const ACCEL_SENSITIVITY: AccelSensitivity = AccelSensitivity {
+    chip: Chip::Lsm303dlhc,
+    range: Range::G2,
+    resolution: Resolution::High,
+};
+/// How long each LED is lit in the completion animation, in ms
+const DONE_STEP_MS: u32 = 50;
+/// The game only checks the button once per frame, so a click does not have
+/// to wait for a possible second one
+const BUTTON_CONFIG: button::Config = button::Config {
+    debounce_ms: 20,
+    double_click_ms: 0,
+    long_press_ms: 800,
+};
+
+pub type Error = i2c::Error<BusError>;
+
+/// Runs the calibration game with the ellipsoid fit until it yields a good
+/// enough calibration. The LEDs light up clockwise as it progresses and
+/// all of them blink if a calibration is rejected and the game starts over.
+/// A single LED chases around the compass once the calibration is accepted.
+///
+/// Clicking the user button starts the game over, holding it down gives up
+/// with `CalibrationError::Aborted`.
+pub fn calc_calibration(
+    lsm303dlhc: &mut Lsm303dlhc,
+    leds: &mut [Led; 8],
+    delay: &mut Delay,
+) -> Result<Calibration, CalibrationError<Error>> {
+    calibrator::calc_calibration(
+        &mut Compass(lsm303dlhc),
+        &mut LedUi::new(leds),
+        delay,
+        Algorithm::Ellipsoid,
+    )
+}
+
+/// Corrects a magnetometer reading, the result is in nT
+pub fn calibrated_mag(mag: I16x3, calibration: &Calibration) -> [i32; 3] {
+    calibration.apply(to_nanotesla(mag))
+}
+
+fn to_nanotesla(mag: I16x3) -> [i32; 3] {
+    MAG_RANGE
+        .magnetic_field([mag.x, mag.y, mag.z])
+        .map(|field| field.nt())
+}
+
+/// The LSM303DLHC as the calibration game sees it. Its x axis points to the
+/// South LED, its y axis to the East LED.
+pub struct Compass<'a>(pub &'a mut Lsm303dlhc);
+
+impl Magnetometer for Compass<'_> {
+    type Error = Error;
+
+    /// In the sensor's frame. It has no data ready flag, so every call returns
+    /// the latest reading.
+    fn magnetic_field(&mut self) -> Result<Option<[i32; 3]>, Error> {
+        Ok(Some(to_nanotesla(self.0.mag()?)))
+    }
+}
+
+impl Accelerometer for Compass<'_> {
+    type Error = Error;
+
+    fn acceleration(&mut self) -> Result<Option<[i32; 3]>, Error> {
+        let accel = self.0.accel()?;
+        let [x, y, z] =
+            [accel.x, accel.y, accel.z].map(|a| ACCEL_SENSITIVITY.acceleration(a).mg());
+        Ok(Some([y, -x, z]))
+    }
+}
+
+/// Shows the game on the eight compass LEDs and takes requests from the user
+/// button
+pub struct LedUi<'a> {
+    leds: &'a mut [Led; 8],
+    button: Recognizer,
+    /// The time the button is timed with, in ms. It only counts the delays of
+    /// the game itself, which is all the recognizer needs.
+    now: u32,
+}
+
+impl<'a> LedUi<'a> {
+    pub fn new(leds: &'a mut [Led; 8]) -> LedUi<'a> {
+        LedUi {
+            leds,
+            button: Recognizer::new(BUTTON_CONFIG),
+            now: 0,
+        }
+    }
+
+    fn light(&mut self, count: usize) {
+        for (i, led) in self.leds.iter_mut().enumerate() {
+            if i < count {
+                led.on().ok();
+            } else {
+                led.off().ok();
+            }
+        }
+    }
+
+    fn delay<D: DelayUs<u32>>(&mut self, delay: &mut D, ms: u32) {
+        delay.delay_us(ms * 1000);
+        self.now = self.now.wrapping_add(ms);
+    }
+}
+
+impl CalibrationUi for LedUi<'_> {
+    fn show_progress<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        _collected: [[u8; 5]; 5],
+        _cursor: (usize, usize),
+        percent: u8,
+        ms: u32,
+    ) {
+        // Too few LEDs for the grid, count up from North instead
+        self.light(usize::from(percent) * self.leds.len() / 100);
+        self.delay(delay, ms);
+    }
+
+    fn show_rejected<D: DelayUs<u32>>(&mut self, delay: &mut D, ms: u32) {
+        const BLINKS: u32 = 4;
+        for _ in 0..BLINKS {
+            self.light(self.leds.len());
+            self.delay(delay, ms / BLINKS / 2);
+            self.light(0);
+            self.delay(delay, ms / BLINKS / 2);
+        }
+    }
+
+    fn show_done<D: DelayUs<u32>>(&mut self, delay: &mut D) {
+        // Chase once around the compass
+        for i in 0..self.leds.len() {
+            for (j, led) in self.leds.iter_mut().enumerate() {
+                if i == j {
+                    led.on().ok();
+                } else {
+                    led.off().ok();
+                }
+            }
+            self.delay(delay, DONE_STEP_MS);
+        }
+        self.light(0);
+    }
+
+    fn request(&mut self) -> Option<Request> {
+        let mut request = None;
+        // The board has a single button, it plays the part of A
+        self.button.input(
+            Button::A,
+            user_button::is_pressed(),
+            self.now,
+            |event| match event {
+                ButtonEvent::Click(_) => request = Some(Request::Restart),
+                ButtonEvent::LongPress(_) => request = Some(Request::Abort),
+                _ => {}
+            },
+        );
+        request
+    }
+}
@@ -14,40 +14,79 @@ pub use stm32f3_discovery::{
     switch_hal,
 };
 
+pub use discovery_common::ahrs::{Gains, Mahony};
+pub use discovery_common::direction::Direction;
+pub use discovery_common::i2c;
+
+use discovery_common::i2c::{Config, RecoveringI2c};
 use stm32f3_discovery::{
     lsm303dlhc,
     stm32f3xx_hal::{
-        gpio::gpiob::{PB6, PB7},
-        gpio::AF4,
         i2c::I2c,
         prelude::*,
-        stm32::{self, I2C1},
+        spi::Spi,
+        stm32::{self, interrupt},
     },
 };
 
-pub type Lsm303dlhc = lsm303dlhc::Lsm303dlhc<I2c<I2C1, (PB6<AF4>, PB7<AF4>)>>;
-
-/// Cardinal directions. Each one matches one of the user LEDs.
-pub enum Direction {
-    /// North / LD3
-    North,
-    /// Northeast / LD5
-    Northeast,
-    /// East / LD7
-    East,
-    /// Southeast / LD9
-    Southeast,
-    /// South / LD10
-    South,
-    /// Southwest / LD8
-    Southwest,
-    /// West / LD6
-    West,
-    /// Northwest / LD4
-    Northwest,
+use discovery_f3::bus_clear::{self, BusClear, I2cBus};
+
+use ahrs::{Nss, Spi1};
+
+pub mod ahrs;
+pub use ahrs::L3gd20;
+pub mod calibration;
+pub use calibration::{calc_calibration, calibrated_mag};
+pub mod user_button;
+
+pub type Lsm303dlhc = lsm303dlhc::Lsm303dlhc<RecoveringI2c<I2cBus, BusClear>>;
+
+/// The user LED pointing in `direction`, as an index into `Leds::into_array`
+pub fn direction_to_led(direction: Direction) -> usize {
+    match direction {
+        // LD3
+        Direction::North => 0,
+        // LD5
+        Direction::NorthEast => 1,
+        // LD7
+        Direction::East => 2,
+        // LD9
+        Direction::SouthEast => 3,
+        // LD10
+        Direction::South => 4,
+        // LD8
+        Direction::SouthWest => 5,
+        // LD6
+        Direction::West => 6,
+        // LD4
+        Direction::NorthWest => 7,
+    }
 }
 
 pub fn init() -> (Leds, Lsm303dlhc, Delay, ITM) {
+    let parts = setup();
+    (parts.leds, parts.lsm303dlhc, parts.delay, parts.itm)
+}
+
+/// Like `init`, but also sets up the L3GD20 gyroscope on SPI1 for the
+/// orientation estimate, see `ahrs`
+pub fn init_ahrs() -> (Leds, Lsm303dlhc, L3gd20, Delay, ITM) {
+    let parts = setup();
+    let l3gd20 = L3gd20::new(parts.spi, parts.nss).unwrap();
+    (parts.leds, parts.lsm303dlhc, l3gd20, parts.delay, parts.itm)
+}
+
+struct Parts {
+    leds: Leds,
+    lsm303dlhc: Lsm303dlhc,
+    delay: Delay,
+    itm: ITM,
+    spi: Spi1,
+    /// The gyroscope's chip select
+    nss: Nss,
+}
+
+fn setup() -> Parts {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = stm32::Peripherals::take().unwrap();
 
@@ -69,16 +108,64 @@ pub fn init() -> (Leds, Lsm303dlhc, Delay, ITM) {
         &mut gpioe.moder,
         &mut gpioe.otyper,
     );
+    // The gyroscope only listens on SPI1 while its chip select is low
+    let mut nss = gpioe
+        .pe3
+        .into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper);
+    nss.set_high().unwrap();
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
+    user_button::set(
+        gpioa
+            .pa0
+            .into_floating_input(&mut gpioa.moder, &mut gpioa.pupdr),
+    );
+
+    let sck = gpioa.pa5.into_af5(&mut gpioa.moder, &mut gpioa.afrl);
+    let miso = gpioa.pa6.into_af5(&mut gpioa.moder, &mut gpioa.afrl);
+    let mosi = gpioa.pa7.into_af5(&mut gpioa.moder, &mut gpioa.afrl);
+    let spi = Spi::spi1(
+        dp.SPI1,
+        (sck, miso, mosi),
+        l3gd20::MODE,
+        1.mhz(),
+        clocks,
+        &mut rcc.apb2,
+    );
 
     let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
     let scl = gpiob.pb6.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
     let sda = gpiob.pb7.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
 
-    let i2c = I2c::new(dp.I2C1, (scl, sda), 400.khz(), clocks, &mut rcc.apb1);
+    let frequency = 400.khz();
+    let i2c = I2c::new(dp.I2C1, (scl, sda), frequency, clocks, &mut rcc.apb1);
+    let bus_clear = BusClear::new(
+        gpiob.moder,
+        gpiob.otyper,
+        gpiob.afrl,
+        clocks,
+        rcc.apb1,
+        dp.TIM7,
+        frequency,
+    );
+    let i2c = RecoveringI2c::new(i2c, bus_clear, Config::default());
 
     let lsm303dlhc = Lsm303dlhc::new(i2c).unwrap();
 
     let delay = Delay::new(cp.SYST, clocks);
 
-    (leds, lsm303dlhc, delay, cp.ITM)
+    Parts {
+        leds,
+        lsm303dlhc,
+        delay,
+        itm: cp.ITM,
+        spi,
+        nss,
+    }
+}
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIM7() {
+    bus_clear::on_timeout();
 }
@@ -0,0 +1,25 @@
+//! The blue user button B1 on PA0. It is high while pressed, and polled
+//! rather than watched with an interrupt.
+
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use stm32f3_discovery::stm32f3xx_hal::{
+    gpio::{gpioa::PA0, Floating, Input},
+    prelude::*,
+};
+
+static PIN: Mutex<RefCell<Option<PA0<Input<Floating>>>>> = Mutex::new(RefCell::new(None));
+
+pub(crate) fn set(pin: PA0<Input<Floating>>) {
+    cortex_m::interrupt::free(|cs| *PIN.borrow(cs).borrow_mut() = Some(pin));
+}
+
+/// Whether the button is held down right now, `false` before `init`
+pub fn is_pressed() -> bool {
+    cortex_m::interrupt::free(|cs| {
+        PIN.borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(false, |pin| pin.is_high().unwrap())
+    })
+}
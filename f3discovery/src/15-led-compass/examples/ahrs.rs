@@ -0,0 +1,59 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use aux15::{ahrs, direction_to_led, Direction, Gains, Mahony};
+#[allow(unused_imports)]
+use aux15::{entry, iprint, iprintln, prelude::*, switch_hal::OutputSwitch};
+
+/// How often the filter is updated
+const SAMPLE_RATE_HZ: f32 = 50.0;
+/// The gyroscope follows fast turns, so the other sensors only have to pull
+/// gently. The integral gain learns the gyroscope's bias.
+const GAINS: Gains = Gains { kp: 1.0, ki: 0.05 };
+
+#[entry]
+fn main() -> ! {
+    let (leds, mut lsm303dlhc, mut l3gd20, mut delay, mut itm) = aux15::init_ahrs();
+    let mut leds = leds.into_array();
+
+    let calibration = aux15::calc_calibration(&mut lsm303dlhc, &mut leds, &mut delay).unwrap();
+    let mut filter = Mahony::new(SAMPLE_RATE_HZ, GAINS);
+
+    loop {
+        let readings = (
+            ahrs::gyro(&mut l3gd20),
+            ahrs::accel(&mut lsm303dlhc),
+            ahrs::mag(&mut lsm303dlhc, &calibration),
+        );
+        match readings {
+            (Ok(gyro), Ok(accel), Ok(mag)) => {
+                filter.update(Some(gyro), accel, mag);
+                let q = filter.orientation();
+                let euler = filter.euler();
+                iprintln!(
+                    &mut itm.stim[0],
+                    "orientation: {:.3} {:.3} {:.3} {:.3} pitch: {:.1} roll: {:.1} yaw: {:.1}",
+                    q.w,
+                    q.x,
+                    q.y,
+                    q.z,
+                    euler.pitch,
+                    euler.roll,
+                    euler.yaw
+                );
+
+                let dir = Direction::from_heading(filter.heading());
+                leds.iter_mut().for_each(|led| led.off().unwrap());
+                leds[direction_to_led(dir)].on().unwrap();
+            }
+            (Err(e), _, _) => iprintln!(&mut itm.stim[0], "Gyroscope error: {:?}", e),
+            (_, Err(e), _) | (_, _, Err(e)) => {
+                iprintln!(&mut itm.stim[0], "Compass error: {:?}", e)
+            }
+        }
+
+        // Reading the sensors takes a fraction of this
+        delay.delay_ms((1000.0 / SAMPLE_RATE_HZ) as u16);
+    }
+}
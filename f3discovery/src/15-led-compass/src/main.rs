@@ -10,7 +10,10 @@ fn main() -> ! {
     let (_leds, mut lsm303dlhc, mut delay, mut itm) = aux15::init();
 
     loop {
-        iprintln!(&mut itm.stim[0], "{:?}", lsm303dlhc.mag().unwrap());
+        match lsm303dlhc.mag() {
+            Ok(mag) => iprintln!(&mut itm.stim[0], "{:?}", mag),
+            Err(e) => iprintln!(&mut itm.stim[0], "Magnetometer error: {:?}", e),
+        }
         delay.delay_ms(1_000_u16);
     }
 }
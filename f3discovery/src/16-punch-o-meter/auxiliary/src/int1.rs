@@ -0,0 +1,49 @@
+//! The LSM303DLHC's INT1 line on PE4, which goes high once the accelerometer
+//! FIFO reached its watermark.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::Mutex;
+use stm32f3_discovery::stm32f3xx_hal::{
+    gpio::{gpioe::PE4, Edge, ExtiPin, Floating, Input},
+    prelude::*,
+    stm32::{self, interrupt, EXTI, SYSCFG},
+};
+
+static PIN: Mutex<RefCell<Option<PE4<Input<Floating>>>>> = Mutex::new(RefCell::new(None));
+/// INT1 went high since the last `take`
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Raises the EXTI4 interrupt on every rising edge of INT1
+pub fn listen(mut pin: PE4<Input<Floating>>, syscfg: &mut SYSCFG, exti: &mut EXTI) {
+    pin.make_interrupt_source(syscfg);
+    pin.trigger_on_edge(exti, Edge::RISING);
+    pin.enable_interrupt(exti);
+    cortex_m::interrupt::free(|cs| *PIN.borrow(cs).borrow_mut() = Some(pin));
+    // Unmasking the interrupt is unsafe
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(stm32::Interrupt::EXTI4);
+    }
+}
+
+/// Whether INT1 went high since the last call or still is high
+pub fn take() -> bool {
+    // Still being high after the last check means there was no edge to catch
+    let high = cortex_m::interrupt::free(|cs| {
+        PIN.borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(false, |pin| pin.is_high().unwrap())
+    });
+    PENDING.swap(false, Ordering::Relaxed) || high
+}
+
+#[interrupt]
+fn EXTI4() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(pin) = PIN.borrow(cs).borrow_mut().as_mut() {
+            pin.clear_interrupt_pending_bit();
+        }
+    });
+    PENDING.store(true, Ordering::Relaxed);
+}
@@ -12,20 +12,75 @@ pub use stm32f3_discovery::{
     stm32f3xx_hal::{delay::Delay, prelude, time::MonoTimer},
 };
 
+pub use discovery_common::accel_fifo::{self, AccelFifo};
+pub use discovery_common::i2c;
+pub use discovery_common::peak::{self, PeakDetector};
+pub use discovery_common::units::{self, AccelSensitivity, Acceleration};
+
+use discovery_common::i2c::{Config, RecoveringI2c};
 use stm32f3_discovery::stm32f3xx_hal::{
-    gpio::gpiob::{PB6, PB7},
-    gpio::AF4,
+    gpio::{gpioe::PE4, Floating, Input},
     i2c::I2c,
     prelude::*,
-    stm32::{self, I2C1},
+    stm32::{self, interrupt, EXTI, SYSCFG},
+    time::Instant,
 };
 
-pub type Lsm303dlhc = lsm303dlhc::Lsm303dlhc<I2c<I2C1, (PB6<AF4>, PB7<AF4>)>>;
+use discovery_f3::bus_clear::{self, BusClear, I2cBus};
+
+pub mod int1;
+
+pub type Bus = RecoveringI2c<I2cBus, BusClear>;
+pub type Lsm303dlhc = lsm303dlhc::Lsm303dlhc<Bus>;
 
 pub fn init() -> (Lsm303dlhc, Delay, MonoTimer, ITM) {
+    let parts = setup();
+    let lsm303dlhc = Lsm303dlhc::new(parts.i2c).unwrap();
+    (lsm303dlhc, parts.delay, parts.mono_timer, parts.itm)
+}
+
+/// Like `init`, but the accelerometer is read through its FIFO.
+/// `int1::take` tells when the FIFO reached its watermark.
+pub fn init_fifo(config: accel_fifo::Config) -> (AccelFifo<Bus>, Delay, MonoTimer, ITM) {
+    let mut parts = setup();
+    let mut fifo = AccelFifo::new(parts.i2c, config);
+    fifo.start().unwrap();
+    int1::listen(parts.int1, &mut parts.syscfg, &mut parts.exti);
+    (fifo, parts.delay, parts.mono_timer, parts.itm)
+}
+
+/// How to convert `Lsm303dlhc::accel` readings at a given sensitivity into
+/// an `Acceleration`. `Lsm303dlhc::new` picks the high resolution mode.
+pub fn accel_sensitivity(sensitivity: Sensitivity) -> AccelSensitivity {
+    AccelSensitivity {
+        chip: units::Chip::Lsm303dlhc,
+        range: match sensitivity {
+            Sensitivity::G1 => units::Range::G2,
+            Sensitivity::G2 => units::Range::G4,
+            Sensitivity::G4 => units::Range::G8,
+            Sensitivity::G12 => units::Range::G16,
+        },
+        resolution: units::Resolution::High,
+    }
+}
+
+struct Parts {
+    i2c: Bus,
+    delay: Delay,
+    mono_timer: MonoTimer,
+    itm: ITM,
+    int1: PE4<Input<Floating>>,
+    syscfg: SYSCFG,
+    exti: EXTI,
+}
+
+fn setup() -> Parts {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = stm32::Peripherals::take().unwrap();
 
+    // EXTI needs SYSCFG to pick the pin it watches
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
 
@@ -36,17 +91,75 @@ pub fn init() -> (Lsm303dlhc, Delay, MonoTimer, ITM) {
         .pe3
         .into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper);
     nss.set_high().unwrap();
+    let int1 = gpioe
+        .pe4
+        .into_floating_input(&mut gpioe.moder, &mut gpioe.pupdr);
 
     let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
     let scl = gpiob.pb6.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
     let sda = gpiob.pb7.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
 
-    let i2c = I2c::new(dp.I2C1, (scl, sda), 400.khz(), clocks, &mut rcc.apb1);
-
-    let lsm303dlhc = Lsm303dlhc::new(i2c).unwrap();
+    let frequency = 400.khz();
+    let i2c = I2c::new(dp.I2C1, (scl, sda), frequency, clocks, &mut rcc.apb1);
+    let bus_clear = BusClear::new(
+        gpiob.moder,
+        gpiob.otyper,
+        gpiob.afrl,
+        clocks,
+        rcc.apb1,
+        dp.TIM7,
+        frequency,
+    );
+    let i2c = RecoveringI2c::new(i2c, bus_clear, Config::default());
 
     let delay = Delay::new(cp.SYST, clocks);
     let mono_timer = MonoTimer::new(cp.DWT, clocks);
 
-    (lsm303dlhc, delay, mono_timer, cp.ITM)
+    Parts {
+        i2c,
+        delay,
+        mono_timer,
+        itm: cp.ITM,
+        int1,
+        syscfg: dp.SYSCFG,
+        exti: dp.EXTI,
+    }
+}
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIM7() {
+    bus_clear::on_timeout();
+}
+
+/// Milliseconds since it was created, for timestamping samples. The cycle
+/// counter behind `MonoTimer` wraps around after a few minutes, so `now` has
+/// to be called at least that often.
+pub struct Clock {
+    start: Instant,
+    cycles_per_ms: u64,
+    /// Cycles counted up to the last wrap around
+    wrapped: u64,
+    last: u32,
+}
+
+impl Clock {
+    pub fn new(mono_timer: &MonoTimer) -> Clock {
+        Clock {
+            start: mono_timer.now(),
+            cycles_per_ms: u64::from(mono_timer.frequency().0 / 1000),
+            wrapped: 0,
+            last: 0,
+        }
+    }
+
+    /// Wraps around after about 49 days
+    pub fn now(&mut self) -> u32 {
+        let cycles = self.start.elapsed();
+        if cycles < self.last {
+            self.wrapped += 1 << 32;
+        }
+        self.last = cycles;
+        ((self.wrapped + u64::from(cycles)) / self.cycles_per_ms) as u32
+    }
 }
@@ -0,0 +1,54 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+#[allow(unused_imports)]
+use aux16::{
+    accel_fifo, entry, iprint, iprintln, peak, prelude::*, units, Acceleration, Clock, PeakDetector,
+};
+
+#[entry]
+fn main() -> ! {
+    // extend sensing range to `[-16g, +16g]` and sample fast enough to catch
+    // the peak of a punch
+    let config = accel_fifo::Config {
+        chip: units::Chip::Lsm303dlhc,
+        data_rate: accel_fifo::DataRate::Hz400,
+        range: units::Range::G16,
+        watermark: 16,
+    };
+    let (mut fifo, _delay, mono_timer, mut itm) = aux16::init_fifo(config);
+
+    let mut detector = PeakDetector::new(peak::Config {
+        threshold: Acceleration::from_mg(500),
+        window_ms: 1_000,
+        cooldown_ms: 500,
+    });
+    let mut clock = Clock::new(&mono_timer);
+    let mut samples = [[Acceleration::ZERO; 3]; accel_fifo::LEN];
+    loop {
+        // INT1 fires once 16 samples are waiting, they are read in one go
+        if !aux16::int1::take() {
+            continue;
+        }
+        let now = clock.now();
+        let drained = match fifo.drain(&mut samples) {
+            Ok(drained) => drained,
+            Err(e) => {
+                iprintln!(&mut itm.stim[0], "Accelerometer error: {:?}", e);
+                continue;
+            }
+        };
+        if drained.overrun {
+            iprintln!(&mut itm.stim[0], "FIFO overrun, samples were lost");
+        }
+
+        for (i, sample) in samples[..drained.count].iter().enumerate() {
+            // The newest sample is the last one
+            let age = (drained.count - 1 - i) as u32 * 1000 / config.data_rate.hz();
+            if let Some(peak) = detector.update(sample[0], now.wrapping_sub(age)) {
+                iprintln!(&mut itm.stim[0], "Max acceleration: {:.2}", peak.value);
+            }
+        }
+    }
+}
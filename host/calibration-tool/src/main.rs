@@ -0,0 +1,309 @@
+//! Calibrates the micro:bit compass from the host.
+//!
+//! Start the `09-led-compass` program while holding button B, it then streams
+//! raw samples over serial. This tool collects a dense point cloud while you
+//! turn the board in every direction, fits an ellipsoid to it and sends the
+//! resulting calibration back to the board, which stores it in flash.
+//!
+//! ```text
+//! calibration-tool <serial port> [samples]
+//! calibration-tool <serial port> --upload "CAL ..."
+//! calibration-tool <serial port> --declination <WMM.COF> <latitude> <longitude> <year>
+//! ```
+//!
+//! The second form uploads a calibration printed earlier, e.g. one exported
+//! from another run. The third one works while the compass is running
+//! normally: it computes the magnetic declination at a place and time from
+//! the World Magnetic Model coefficients, which NOAA publishes as `WMM.COF`,
+//! and sends it to the board so it can point to true north.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use discovery_common::calibration::{Calibration, Sample};
+use discovery_common::ellipsoid;
+use discovery_common::vector::Vector3;
+use discovery_common::wmm::{self, Model};
+use serialport::SerialPort;
+
+const BAUD_RATE: u32 = 115_200;
+const DEFAULT_SAMPLES: usize = 300;
+/// Samples closer than this to one we already have are dropped, in nT
+const MIN_SPACING: f32 = 1_000.0;
+/// The board reads the serial port once per round of its main loop, which
+/// waits `POLL_MS` (10 ms) and then handles a sensor reading. It only buffers
+/// a single byte in between.
+const BYTE_DELAY: Duration = Duration::from_millis(20);
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [port, flag, calibration] if flag.as_str() == "--upload" => upload_line(port, calibration),
+        [port, flag, cof, latitude, longitude, year] if flag.as_str() == "--declination" => {
+            declination(port, cof, latitude, longitude, year)
+        }
+        [port] => calibrate(port, DEFAULT_SAMPLES),
+        [port, samples] => match samples.parse() {
+            Ok(samples) => calibrate(port, samples),
+            Err(_) => Err(format!("invalid number of samples: {}", samples)),
+        },
+        _ => {
+            eprintln!("usage: calibration-tool <serial port> [samples]");
+            eprintln!("       calibration-tool <serial port> --upload \"CAL ...\"");
+            eprintln!(
+                "       calibration-tool <serial port> --declination <WMM.COF> <latitude> <longitude> <year>"
+            );
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn calibrate(port: &str, count: usize) -> Result<(), String> {
+    let port = open(port)?;
+    let mut reader = BufReader::new(port.try_clone().map_err(|e| e.to_string())?);
+
+    eprintln!("Turn the board slowly in every direction");
+    let mut samples: Vec<Vector3> = Vec::with_capacity(count);
+    while samples.len() < count {
+        if add_sample(&mut samples, &read_line(&mut reader, None)?) {
+            eprint!("\r{}/{} samples", samples.len(), count);
+        }
+    }
+    eprintln!();
+
+    let (calibration, residual) = fit(&samples)?;
+    eprintln!(
+        "Field strength {} nT, RMS residual {:.2}%",
+        calibration.radius(),
+        residual * 100.0
+    );
+    println!("{}", calibration);
+
+    send(port, reader, &calibration.to_string())?;
+    eprintln!("Calibration stored on the board");
+    Ok(())
+}
+
+/// Adds the magnetometer reading on a line the board streamed, unless it is
+/// too close to one we already have. Returns whether it was added.
+fn add_sample(samples: &mut Vec<Vector3>, line: &str) -> bool {
+    let sample = match line.parse::<Sample>() {
+        Ok(sample) => sample,
+        // The board also echoes its current calibration, ignore anything else
+        Err(_) => return false,
+    };
+    let [x, y, z] = sample.mag;
+    let point = Vector3::new(x as f32, y as f32, z as f32);
+    if samples.iter().any(|p| (*p - point).norm() < MIN_SPACING) {
+        return false;
+    }
+    samples.push(point);
+    true
+}
+
+/// Fits an ellipsoid to the samples. Also returns how far the corrected
+/// samples are from the sphere, as the RMS of their relative errors.
+fn fit(samples: &[Vector3]) -> Result<(Calibration, f32), String> {
+    let fit = ellipsoid::fit(samples).ok_or("the samples do not describe an ellipsoid")?;
+    let calibration = Calibration::from(fit);
+    let residual = samples
+        .iter()
+        .map(|sample| {
+            let error = (calibration.apply_f32(*sample).norm() - fit.radius) / fit.radius;
+            error * error
+        })
+        .sum::<f32>()
+        / samples.len() as f32;
+    Ok((calibration, residual.sqrt()))
+}
+
+fn upload_line(port: &str, line: &str) -> Result<(), String> {
+    let calibration: Calibration = line
+        .parse()
+        .map_err(|e| format!("invalid calibration: {:?}", e))?;
+    let port = open(port)?;
+    let reader = BufReader::new(port.try_clone().map_err(|e| e.to_string())?);
+    send(port, reader, &calibration.to_string())?;
+    eprintln!("Calibration stored on the board");
+    Ok(())
+}
+
+fn declination(
+    port: &str,
+    cof: &str,
+    latitude: &str,
+    longitude: &str,
+    year: &str,
+) -> Result<(), String> {
+    let number = |value: &str| {
+        value
+            .parse::<f32>()
+            .map_err(|_| format!("invalid number: {}", value))
+    };
+    let (latitude, longitude, year) = (number(latitude)?, number(longitude)?, number(year)?);
+
+    let text = fs::read_to_string(cof).map_err(|e| format!("failed to read {}: {}", cof, e))?;
+    let mut lines = text.lines();
+    let epoch = lines
+        .next()
+        .and_then(wmm::parse_header)
+        .ok_or("missing WMM.COF header")?;
+    let coefficients: Vec<_> = lines.filter_map(wmm::parse_coefficient).collect();
+    if !(epoch..epoch + 5.0).contains(&year) {
+        eprintln!(
+            "warning: the model is only valid for five years from {}",
+            epoch
+        );
+    }
+
+    let model = Model {
+        epoch,
+        coefficients: &coefficients,
+    };
+    let declination = model.declination(latitude, longitude, 0.0, year);
+    println!("Declination {:.2}°", declination);
+
+    let port = open(port)?;
+    let reader = BufReader::new(port.try_clone().map_err(|e| e.to_string())?);
+    send(port, reader, &format!("DECL {:.2}", declination))?;
+    eprintln!("Declination stored on the board");
+    Ok(())
+}
+
+/// Sends a line to the board and waits for it to acknowledge it
+fn send(
+    mut port: Box<dyn SerialPort>,
+    mut reader: BufReader<Box<dyn SerialPort>>,
+    line: &str,
+) -> Result<(), String> {
+    for byte in format!("{}\r\n", line).bytes() {
+        port.write_all(&[byte]).map_err(|e| e.to_string())?;
+        thread::sleep(BYTE_DELAY);
+    }
+
+    let deadline = Instant::now() + ACK_TIMEOUT;
+    loop {
+        match read_line(&mut reader, Some(deadline))?.as_str() {
+            "OK" => return Ok(()),
+            "ERR" => return Err(format!("the board rejected `{}`", line)),
+            // Samples or headings sent before the board noticed the line
+            _ => continue,
+        }
+    }
+}
+
+fn open(port: &str) -> Result<Box<dyn SerialPort>, String> {
+    serialport::new(port, BAUD_RATE)
+        .timeout(Duration::from_secs(1))
+        .open()
+        .map_err(|e| format!("failed to open {}: {}", port, e))
+}
+
+/// Waits for a whole line, until `deadline` if there is one
+fn read_line(reader: &mut impl BufRead, deadline: Option<Instant>) -> Result<String, String> {
+    let mut line = String::new();
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err("serial port closed".into()),
+            Ok(_) => return Ok(line.trim().to_string()),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err("no answer from the board".into());
+                }
+                // Keep what was read so far and wait for the rest of the line
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The stream of a board turned by hand for a few minutes. The sensor has
+    /// a hard-iron offset of (-21300, 9750, 33600) nT and a soft-iron
+    /// distortion of up to 12 %, in a 48 µT field. It starts in the middle of
+    /// a line and contains the calibration the board echoes.
+    const CAPTURE: &str = include_str!("../tests/capture.txt");
+    const CENTER: [f32; 3] = [-21_300.0, 9_750.0, 33_600.0];
+
+    fn collect(text: &str, count: usize) -> Vec<Vector3> {
+        let mut samples = Vec::new();
+        for line in text.lines() {
+            if samples.len() == count {
+                break;
+            }
+            add_sample(&mut samples, line);
+        }
+        samples
+    }
+
+    #[test]
+    fn only_takes_samples() {
+        let mut samples = Vec::new();
+        assert!(add_sample(
+            &mut samples,
+            "S -20250 20400 -12300 -45 151 994"
+        ));
+        assert_eq!(samples, [Vector3::new(-20_250.0, 20_400.0, -12_300.0)]);
+        assert!(!add_sample(
+            &mut samples,
+            "CAL 0 0 0 1024 0 0 0 1024 0 0 0 1024 0"
+        ));
+        assert!(!add_sample(&mut samples, "50 -9300 42 -12 1003"));
+        assert!(!add_sample(&mut samples, "S -20250 20400"));
+        assert!(!add_sample(&mut samples, ""));
+        assert_eq!(samples.len(), 1);
+    }
+
+    #[test]
+    fn drops_samples_close_to_earlier_ones() {
+        let mut samples = Vec::new();
+        assert!(add_sample(&mut samples, "S 0 0 40000 0 0 1000"));
+        assert!(!add_sample(&mut samples, "S 600 -600 40000 0 0 1000"));
+        assert!(add_sample(&mut samples, "S 0 1050 40000 0 0 1000"));
+        assert_eq!(samples.len(), 2);
+    }
+
+    #[test]
+    fn fits_a_capture() {
+        let samples = collect(CAPTURE, DEFAULT_SAMPLES);
+        assert_eq!(samples.len(), DEFAULT_SAMPLES);
+
+        let (calibration, residual) = fit(&samples).unwrap();
+        assert!(residual < 0.01, "residual {}", residual);
+        for (found, expected) in calibration.center().iter().zip(CENTER) {
+            assert!(
+                (*found as f32 - expected).abs() < 500.0,
+                "center {:?}",
+                calibration.center()
+            );
+        }
+        // Every corrected sample lands near the sphere, not just on average
+        let radius = calibration.radius() as f32;
+        for sample in &samples {
+            let error = (calibration.apply_f32(*sample).norm() - radius) / radius;
+            assert!(
+                error.abs() < 0.03,
+                "{:?} is {:.1}% off",
+                sample,
+                error * 100.0
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_too_few_samples() {
+        assert!(fit(&collect(CAPTURE, 5)).is_err());
+    }
+}
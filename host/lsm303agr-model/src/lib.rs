@@ -0,0 +1,530 @@
+//! A behavioral model of the LSM303AGR for testing sensor code on the host.
+//!
+//! The model answers on both I2C addresses of the chip, keeps a register file
+//! for the accelerometer and the magnetometer and produces new samples at the
+//! configured output data rate. Time is virtual: every transaction advances the
+//! clock by the time it would take on the bus, `advance_us` skips ahead. The
+//! vectors the sensors "see" are set with `set_acceleration` and
+//! `set_magnetic_field`, the magnetometer additionally applies a hard- and
+//! soft-iron distortion on top.
+//!
+//! Since it implements the blocking `embedded_hal` I2C traits it can be handed
+//! straight to a driver:
+//!
+//! ```
+//! use lsm303agr::Lsm303agr;
+//! use lsm303agr_model::Lsm303agrModel;
+//!
+//! let mut model = Lsm303agrModel::new();
+//! model.set_magnetic_field([20_000.0, 0.0, -40_000.0]);
+//! let mut sensor = Lsm303agr::new_with_i2c(model);
+//! sensor.init().unwrap();
+//! ```
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+pub mod register;
+use register::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Nothing is listening on this address
+    AddressNack(u8),
+    /// The transfer touches a register outside the register map of the
+    /// sensor at `address`
+    DataNack { address: u8, register: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Accelerometer,
+    Magnetometer,
+}
+
+/// Operating modes of the accelerometer, they decide the output resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AccelMode {
+    LowPower,
+    Normal,
+    HighResolution,
+}
+
+const ACCEL_REGISTERS: usize = 0x40;
+const MAG_REGISTERS: usize = 0x70;
+
+/// Magnetometer sensitivity, 1.5 mgauss/LSB
+const MAG_NT_PER_LSB: f64 = 150.0;
+
+const IDENTITY: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+pub struct Lsm303agrModel {
+    accel: [u8; ACCEL_REGISTERS],
+    mag: [u8; MAG_REGISTERS],
+    /// Where a plain `Read` continues, set by the last write
+    pointer: Option<(Device, u8)>,
+
+    now_us: u64,
+    bus_hz: u32,
+    next_accel_us: Option<u64>,
+    next_mag_us: Option<u64>,
+
+    acceleration_mg: [f64; 3],
+    field_nt: [f64; 3],
+    hard_iron_nt: [f64; 3],
+    soft_iron: [[f64; 3]; 3],
+    temperature: f64,
+}
+
+impl Default for Lsm303agrModel {
+    fn default() -> Lsm303agrModel {
+        Lsm303agrModel::new()
+    }
+}
+
+impl Lsm303agrModel {
+    /// A freshly powered up chip lying flat on the table
+    pub fn new() -> Lsm303agrModel {
+        let mut model = Lsm303agrModel {
+            accel: [0; ACCEL_REGISTERS],
+            mag: [0; MAG_REGISTERS],
+            pointer: None,
+            now_us: 0,
+            bus_hz: 100_000,
+            next_accel_us: None,
+            next_mag_us: None,
+            acceleration_mg: [0.0, 0.0, 1000.0],
+            field_nt: [0.0; 3],
+            hard_iron_nt: [0.0; 3],
+            soft_iron: IDENTITY,
+            temperature: 25.0,
+        };
+        model.reset_accel();
+        model.reset_mag();
+        model
+    }
+
+    pub fn set_bus_frequency(&mut self, hz: u32) {
+        self.bus_hz = hz;
+    }
+
+    pub fn now_us(&self) -> u64 {
+        self.now_us
+    }
+
+    /// Lets time pass without any bus traffic
+    pub fn advance_us(&mut self, us: u64) {
+        self.now_us += us;
+        self.update();
+    }
+
+    /// Acceleration the chip measures in its own frame, in mg
+    pub fn set_acceleration(&mut self, mg: [f64; 3]) {
+        self.acceleration_mg = mg;
+    }
+
+    /// Undistorted magnetic field in the frame of the chip, in nT
+    pub fn set_magnetic_field(&mut self, nt: [f64; 3]) {
+        self.field_nt = nt;
+    }
+
+    /// Constant offset added by magnetized parts of the board, in nT
+    pub fn set_hard_iron(&mut self, nt: [f64; 3]) {
+        self.hard_iron_nt = nt;
+    }
+
+    /// Distortion of the field by soft magnetic materials around the chip
+    pub fn set_soft_iron(&mut self, matrix: [[f64; 3]; 3]) {
+        self.soft_iron = matrix;
+    }
+
+    pub fn set_temperature(&mut self, celsius: f64) {
+        self.temperature = celsius;
+    }
+
+    /// `None` outside the register map
+    pub fn accel_register(&self, register: u8) -> Option<u8> {
+        self.accel.get(register as usize).copied()
+    }
+
+    /// `None` outside the register map
+    pub fn mag_register(&self, register: u8) -> Option<u8> {
+        self.mag.get(register as usize).copied()
+    }
+
+    /// The field the magnetometer actually sees: `soft_iron * field + hard_iron`
+    pub fn measured_field(&self) -> [f64; 3] {
+        let mut out = self.hard_iron_nt;
+        for (row, out) in self.soft_iron.iter().zip(out.iter_mut()) {
+            *out += row[0] * self.field_nt[0] + row[1] * self.field_nt[1] + row[2] * self.field_nt[2];
+        }
+        out
+    }
+
+    fn reset_accel(&mut self) {
+        self.accel = [0; ACCEL_REGISTERS];
+        self.accel[WHO_AM_I_A as usize] = WHO_AM_I_A_VALUE;
+        // All axes enabled, powered down
+        self.accel[CTRL_REG1_A as usize] = 0x07;
+        self.next_accel_us = None;
+    }
+
+    fn reset_mag(&mut self) {
+        let offsets = OFFSET_X_REG_L_M as usize..=OFFSET_Z_REG_H_M as usize;
+        let saved_offsets = self.mag;
+        self.mag = [0; MAG_REGISTERS];
+        // The hard-iron offset registers survive a soft reset
+        self.mag[offsets.clone()].copy_from_slice(&saved_offsets[offsets]);
+        self.mag[WHO_AM_I_M as usize] = WHO_AM_I_M_VALUE;
+        self.mag[CFG_REG_A_M as usize] = MD_IDLE;
+        self.next_mag_us = None;
+    }
+
+    fn registers(device: Device) -> usize {
+        match device {
+            Device::Accelerometer => ACCEL_REGISTERS,
+            Device::Magnetometer => MAG_REGISTERS,
+        }
+    }
+
+    fn device(address: u8) -> Result<Device, Error> {
+        match address {
+            ACCELEROMETER_ADDR => Ok(Device::Accelerometer),
+            MAGNETOMETER_ADDR => Ok(Device::Magnetometer),
+            _ => Err(Error::AddressNack(address)),
+        }
+    }
+
+    fn accel_mode(&self) -> AccelMode {
+        if self.accel[CTRL_REG1_A as usize] & LPEN != 0 {
+            AccelMode::LowPower
+        } else if self.accel[CTRL_REG4_A as usize] & HR != 0 {
+            AccelMode::HighResolution
+        } else {
+            AccelMode::Normal
+        }
+    }
+
+    fn accel_period_us(&self) -> Option<u64> {
+        let hz = match self.accel[CTRL_REG1_A as usize] >> 4 {
+            1 => 1,
+            2 => 10,
+            3 => 25,
+            4 => 50,
+            5 => 100,
+            6 => 200,
+            7 => 400,
+            8 if self.accel_mode() == AccelMode::LowPower => 1620,
+            9 if self.accel_mode() == AccelMode::LowPower => 5376,
+            9 => 1344,
+            _ => return None,
+        };
+        Some(1_000_000 / hz)
+    }
+
+    fn mag_period_us(&self) -> u64 {
+        let hz = match (self.mag[CFG_REG_A_M as usize] >> 2) & 0b11 {
+            0 => 10,
+            1 => 20,
+            2 => 50,
+            _ => 100,
+        };
+        1_000_000 / hz
+    }
+
+    /// Sensitivity in mg/digit and the number of significant bits, table 3 of the datasheet
+    fn accel_sensitivity(&self) -> (f64, u32) {
+        let full_scale = (self.accel[CTRL_REG4_A as usize] >> 4) & 0b11;
+        let (sensitivities, bits) = match self.accel_mode() {
+            AccelMode::LowPower => ([15.63, 31.26, 62.52, 187.58], 8),
+            AccelMode::Normal => ([3.9, 7.82, 15.63, 46.9], 10),
+            AccelMode::HighResolution => ([0.98, 1.95, 3.9, 11.72], 12),
+        };
+        (sensitivities[full_scale as usize], bits)
+    }
+
+    fn update(&mut self) {
+        while let Some(at) = self.next_accel_us {
+            if at > self.now_us {
+                break;
+            }
+            self.sample_accel();
+            self.next_accel_us = self.accel_period_us().map(|period| at + period);
+        }
+
+        while let Some(at) = self.next_mag_us {
+            if at > self.now_us {
+                break;
+            }
+            self.sample_mag();
+            match self.mag[CFG_REG_A_M as usize] & MD_MASK {
+                MD_CONTINUOUS => self.next_mag_us = Some(at + self.mag_period_us()),
+                _ => {
+                    // A single measurement drops back into idle mode
+                    self.mag[CFG_REG_A_M as usize] |= MD_IDLE;
+                    self.next_mag_us = None;
+                }
+            }
+        }
+    }
+
+    fn sample_accel(&mut self) {
+        let (sensitivity, bits) = self.accel_sensitivity();
+        let max = (1 << (bits - 1)) - 1;
+        for (axis, mg) in self.acceleration_mg.iter().enumerate() {
+            let value = ((mg / sensitivity).round() as i32).clamp(-max - 1, max);
+            // Output is left justified
+            let raw = ((value << (16 - bits)) as i16).to_le_bytes();
+            let register = OUT_X_L_A as usize + 2 * axis;
+            self.accel[register..register + 2].copy_from_slice(&raw);
+        }
+
+        let temperature = (((self.temperature - 25.0) * 256.0) as i16).to_le_bytes();
+        self.accel[OUT_TEMP_L_A as usize..=OUT_TEMP_H_A as usize].copy_from_slice(&temperature);
+        self.accel[STATUS_REG_AUX_A as usize] |= 1 << 2;
+
+        let status = &mut self.accel[STATUS_REG_A as usize];
+        if *status & ZYXDA != 0 {
+            *status |= ZYXOR;
+        }
+        *status |= ZYXDA;
+    }
+
+    fn sample_mag(&mut self) {
+        let field = self.measured_field();
+        for (axis, nt) in field.iter().enumerate() {
+            let offset_register = OFFSET_X_REG_L_M as usize + 2 * axis;
+            let offset = i16::from_le_bytes([self.mag[offset_register], self.mag[offset_register + 1]]);
+            let value = (nt / MAG_NT_PER_LSB).round() as i32 - i32::from(offset);
+            let raw = (value.clamp(i16::MIN.into(), i16::MAX.into()) as i16).to_le_bytes();
+            let register = OUTX_L_REG_M as usize + 2 * axis;
+            self.mag[register..register + 2].copy_from_slice(&raw);
+        }
+
+        let status = &mut self.mag[STATUS_REG_M as usize];
+        if *status & ZYXDA != 0 {
+            *status |= ZYXOR;
+        }
+        *status |= ZYXDA;
+    }
+
+    /// Advances the clock by the time `bytes` take on the bus, 8 bits plus ACK each
+    fn bus_time(&mut self, bytes: usize) {
+        let bits = bytes as u64 * 9;
+        self.now_us += bits * 1_000_000 / u64::from(self.bus_hz);
+        self.update();
+    }
+
+    fn read_register(&mut self, device: Device, register: u8) -> u8 {
+        match device {
+            Device::Accelerometer => {
+                let value = self.accel[register as usize];
+                match register {
+                    OUT_Z_H_A => self.accel[STATUS_REG_A as usize] = 0,
+                    OUT_TEMP_H_A => self.accel[STATUS_REG_AUX_A as usize] = 0,
+                    _ => {}
+                }
+                value
+            }
+            Device::Magnetometer => {
+                let value = self.mag[register as usize];
+                if register == OUTZ_H_REG_M {
+                    self.mag[STATUS_REG_M as usize] = 0;
+                }
+                value
+            }
+        }
+    }
+
+    fn write_register(&mut self, device: Device, register: u8, value: u8) {
+        match (device, register) {
+            (Device::Accelerometer, CTRL_REG5_A) if value & BOOT != 0 => self.reset_accel(),
+            (Device::Accelerometer, CTRL_REG1_A) => {
+                self.accel[CTRL_REG1_A as usize] = value;
+                self.next_accel_us = self.accel_period_us().map(|period| self.now_us + period);
+            }
+            (Device::Accelerometer, TEMP_CFG_REG_A..=0x26)
+            | (Device::Accelerometer, 0x2e | 0x30 | 0x32..=0x34 | 0x36..=0x38 | 0x3a..=0x3f) => {
+                self.accel[register as usize] = value
+            }
+            (Device::Magnetometer, CFG_REG_A_M) if value & (SOFT_RST | REBOOT) != 0 => self.reset_mag(),
+            (Device::Magnetometer, CFG_REG_A_M) => {
+                self.mag[CFG_REG_A_M as usize] = value;
+                self.next_mag_us = match value & MD_MASK {
+                    MD_CONTINUOUS | MD_SINGLE => Some(self.now_us + self.mag_period_us()),
+                    _ => None,
+                };
+            }
+            (Device::Magnetometer, OFFSET_X_REG_L_M..=OFFSET_Z_REG_H_M)
+            | (Device::Magnetometer, CFG_REG_B_M | CFG_REG_C_M | INT_CTRL_REG_M | 0x65 | 0x66) => {
+                self.mag[register as usize] = value
+            }
+            // Read only or reserved, the chip ignores those writes
+            _ => {}
+        }
+    }
+
+    /// Splits a sub-address into the register and whether it auto-increments
+    fn sub_address(device: Device, sub_address: u8) -> (u8, bool) {
+        match device {
+            Device::Accelerometer => (sub_address & !ACCEL_AUTO_INCREMENT, sub_address & ACCEL_AUTO_INCREMENT != 0),
+            // The magnetometer always increments
+            Device::Magnetometer => (sub_address, true),
+        }
+    }
+
+    /// Checks that a transfer of `len` bytes starting at `sub_address` stays
+    /// within the register map, the chip does not acknowledge anything else
+    fn check_range(address: u8, device: Device, sub_address: u8, len: usize) -> Result<(), Error> {
+        let (register, increment) = Self::sub_address(device, sub_address);
+        let last = if increment && len > 0 {
+            register as usize + len - 1
+        } else {
+            register as usize
+        };
+        if last < Self::registers(device) {
+            Ok(())
+        } else {
+            Err(Error::DataNack {
+                address,
+                register: Self::registers(device).max(register as usize) as u8,
+            })
+        }
+    }
+
+    fn read_from(&mut self, address: u8, device: Device, buffer: &mut [u8]) -> Result<(), Error> {
+        let sub_address = match self.pointer {
+            Some((pointer_device, sub_address)) if pointer_device == device => sub_address,
+            _ => 0,
+        };
+        Self::check_range(address, device, sub_address, buffer.len())?;
+        let (mut register, increment) = Self::sub_address(device, sub_address);
+        for byte in buffer.iter_mut() {
+            *byte = self.read_register(device, register);
+            if increment {
+                register = register.wrapping_add(1);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Lsm303agrModel {
+    type Error = Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.bus_time(1);
+        let device = Self::device(address)?;
+        self.bus_time(bytes.len());
+
+        if let Some((sub_address, data)) = bytes.split_first() {
+            Self::check_range(address, device, *sub_address, data.len())?;
+            self.pointer = Some((device, *sub_address));
+            let (mut register, increment) = Self::sub_address(device, *sub_address);
+            for value in data {
+                self.write_register(device, register, *value);
+                if increment {
+                    register = register.wrapping_add(1);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for Lsm303agrModel {
+    type Error = Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.bus_time(1);
+        let device = Self::device(address)?;
+        self.bus_time(buffer.len());
+        self.read_from(address, device, buffer)
+    }
+}
+
+impl WriteRead for Lsm303agrModel {
+    type Error = Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.bus_time(1);
+        let device = Self::device(address)?;
+        // The repeated start sends the address a second time
+        self.bus_time(bytes.len() + 1 + buffer.len());
+
+        if let Some(sub_address) = bytes.first() {
+            self.pointer = Some((device, *sub_address));
+        }
+        self.read_from(address, device, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nacks_unknown_address() {
+        let mut model = Lsm303agrModel::new();
+        assert_eq!(model.write(0x42, &[0x00]), Err(Error::AddressNack(0x42)));
+    }
+
+    #[test]
+    fn nacks_registers_outside_the_map() {
+        let mut model = Lsm303agrModel::new();
+        let mut buffer = [0; 2];
+        assert_eq!(
+            model.write_read(MAGNETOMETER_ADDR, &[0x70], &mut buffer),
+            Err(Error::DataNack {
+                address: MAGNETOMETER_ADDR,
+                register: 0x70,
+            })
+        );
+        // Running off the end with auto-increment
+        assert_eq!(
+            model.write_read(MAGNETOMETER_ADDR, &[0x6f], &mut buffer),
+            Err(Error::DataNack {
+                address: MAGNETOMETER_ADDR,
+                register: 0x70,
+            })
+        );
+        assert_eq!(
+            model.write(ACCELEROMETER_ADDR, &[0x7f, 0x00]),
+            Err(Error::DataNack {
+                address: ACCELEROMETER_ADDR,
+                register: 0x7f,
+            })
+        );
+        assert_eq!(model.accel_register(0x40), None);
+        assert_eq!(model.mag_register(0xff), None);
+    }
+
+    #[test]
+    fn keeps_block_data_update() {
+        let mut model = Lsm303agrModel::new();
+        model.write(MAGNETOMETER_ADDR, &[CFG_REG_C_M, 0x10]).unwrap();
+        assert_eq!(model.mag_register(CFG_REG_C_M), Some(0x10));
+    }
+
+    #[test]
+    fn samples_at_data_rate() {
+        let mut model = Lsm303agrModel::new();
+        model.set_acceleration([100.0, -200.0, 1000.0]);
+        // 50 Hz, normal mode
+        model.write(ACCELEROMETER_ADDR, &[CTRL_REG1_A, 0x47]).unwrap();
+        model.advance_us(19_000);
+        assert_eq!(model.accel_register(STATUS_REG_A), Some(0));
+        model.advance_us(1_000);
+        assert_eq!(model.accel_register(STATUS_REG_A), Some(ZYXDA));
+
+        let mut data = [0; 6];
+        model
+            .write_read(ACCELEROMETER_ADDR, &[OUT_X_L_A | ACCEL_AUTO_INCREMENT], &mut data)
+            .unwrap();
+        let x = i16::from_le_bytes([data[0], data[1]]) >> 6;
+        let y = i16::from_le_bytes([data[2], data[3]]) >> 6;
+        let z = i16::from_le_bytes([data[4], data[5]]) >> 6;
+        // 3.9 mg per digit
+        assert_eq!([x, y, z], [26, -51, 256]);
+        assert_eq!(model.accel_register(STATUS_REG_A), Some(0));
+    }
+}
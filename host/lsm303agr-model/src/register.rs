@@ -0,0 +1,51 @@
+//! Register addresses of the LSM303AGR, see section 7 of the datasheet.
+
+pub const ACCELEROMETER_ADDR: u8 = 0b0011001;
+pub const MAGNETOMETER_ADDR: u8 = 0b0011110;
+
+/// Setting the MSB of the accelerometer sub-address enables auto-increment
+pub const ACCEL_AUTO_INCREMENT: u8 = 0x80;
+
+pub const STATUS_REG_AUX_A: u8 = 0x07;
+pub const OUT_TEMP_L_A: u8 = 0x0c;
+pub const OUT_TEMP_H_A: u8 = 0x0d;
+pub const WHO_AM_I_A: u8 = 0x0f;
+pub const TEMP_CFG_REG_A: u8 = 0x1f;
+pub const CTRL_REG1_A: u8 = 0x20;
+pub const CTRL_REG4_A: u8 = 0x23;
+pub const CTRL_REG5_A: u8 = 0x24;
+pub const STATUS_REG_A: u8 = 0x27;
+pub const OUT_X_L_A: u8 = 0x28;
+pub const OUT_Z_H_A: u8 = 0x2d;
+
+pub const OFFSET_X_REG_L_M: u8 = 0x45;
+pub const OFFSET_Z_REG_H_M: u8 = 0x4a;
+pub const WHO_AM_I_M: u8 = 0x4f;
+pub const CFG_REG_A_M: u8 = 0x60;
+pub const CFG_REG_B_M: u8 = 0x61;
+pub const CFG_REG_C_M: u8 = 0x62;
+pub const INT_CTRL_REG_M: u8 = 0x63;
+pub const STATUS_REG_M: u8 = 0x67;
+pub const OUTX_L_REG_M: u8 = 0x68;
+pub const OUTZ_H_REG_M: u8 = 0x6d;
+
+pub const WHO_AM_I_A_VALUE: u8 = 0x33;
+pub const WHO_AM_I_M_VALUE: u8 = 0x40;
+
+// Status register bits, the same for both sensors
+pub const ZYXDA: u8 = 1 << 3;
+pub const ZYXOR: u8 = 1 << 7;
+
+// CTRL_REG1_A
+pub const LPEN: u8 = 1 << 3;
+// CTRL_REG4_A
+pub const HR: u8 = 1 << 3;
+// CTRL_REG5_A
+pub const BOOT: u8 = 1 << 7;
+// CFG_REG_A_M
+pub const SOFT_RST: u8 = 1 << 5;
+pub const REBOOT: u8 = 1 << 6;
+pub const MD_MASK: u8 = 0b11;
+pub const MD_CONTINUOUS: u8 = 0b00;
+pub const MD_SINGLE: u8 = 0b01;
+pub const MD_IDLE: u8 = 0b11;
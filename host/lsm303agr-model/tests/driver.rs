@@ -0,0 +1,61 @@
+//! Runs the `lsm303agr` driver the chapters use against the model.
+
+use lsm303agr::{AccelOutputDataRate, Lsm303agr, MagOutputDataRate};
+use lsm303agr_model::Lsm303agrModel;
+
+/// Every poll takes a few hundred us on the bus, so this is plenty to wait
+/// for the slowest data rate used here
+const MAX_POLLS: usize = 10_000;
+
+#[test]
+fn init_and_ids() {
+    let mut sensor = Lsm303agr::new_with_i2c(Lsm303agrModel::new());
+    sensor.init().unwrap();
+    assert!(sensor.accelerometer_id().unwrap().is_correct());
+    assert!(sensor.magnetometer_id().unwrap().is_correct());
+}
+
+#[test]
+fn accel_status_and_data() {
+    let mut model = Lsm303agrModel::new();
+    model.set_acceleration([250.0, -500.0, 1000.0]);
+    let mut sensor = Lsm303agr::new_with_i2c(model);
+    sensor.init().unwrap();
+    sensor.set_accel_odr(AccelOutputDataRate::Hz50).unwrap();
+
+    // No sample before the first period is over
+    assert!(!sensor.accel_status().unwrap().xyz_new_data);
+    let polls = (0..MAX_POLLS)
+        .position(|_| sensor.accel_status().unwrap().xyz_new_data)
+        .unwrap();
+    assert!(polls > 0);
+
+    let data = sensor.accel_data().unwrap();
+    // Normal mode resolves about 4 mg
+    assert!((data.x - 250).abs() <= 8, "{:?}", data);
+    assert!((data.y + 500).abs() <= 8, "{:?}", data);
+    assert!((data.z - 1000).abs() <= 8, "{:?}", data);
+    // Reading the data clears the flag
+    assert!(!sensor.accel_status().unwrap().xyz_new_data);
+}
+
+#[test]
+fn mag_status_and_data() {
+    let mut model = Lsm303agrModel::new();
+    model.set_magnetic_field([20_000.0, -5_000.0, -40_000.0]);
+    model.set_hard_iron([1_500.0, 0.0, 0.0]);
+    let mut sensor = Lsm303agr::new_with_i2c(model);
+    sensor.init().unwrap();
+    sensor.set_mag_odr(MagOutputDataRate::Hz10).unwrap();
+    let mut sensor = sensor.into_mag_continuous().ok().unwrap();
+
+    (0..MAX_POLLS)
+        .find(|_| sensor.mag_status().unwrap().xyz_new_data)
+        .unwrap();
+
+    let data = sensor.mag_data().unwrap();
+    // 150 nT per digit, plus the hard iron offset
+    assert!((data.x - 21_500).abs() <= 150, "{:?}", data);
+    assert!((data.y + 5_000).abs() <= 150, "{:?}", data);
+    assert!((data.z + 40_000).abs() <= 150, "{:?}", data);
+}
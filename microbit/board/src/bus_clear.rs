@@ -0,0 +1,143 @@
+//! Frees the internal I2C bus when a sensor got stuck holding SDA low.
+//!
+//! TIMER2 watches every transaction. If one hangs, the app's `TIMER2`
+//! interrupt handler calls `on_timeout` to reset the chip. The bus is cleared
+//! when `RecoveringI2c` is created again after the reset.
+
+// Reading the line levels and the timer registers needs the raw PAC
+#![allow(unsafe_code)]
+
+use cortex_m::peripheral::SCB;
+use discovery_common::i2c::BusRecovery;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use microbit::hal::gpio::{Level, OpenDrainConfig};
+use microbit::pac::{self, TIMER2};
+
+#[cfg(feature = "v1")]
+use microbit::{
+    hal::twi::{Instance, Pins, Twi as I2c},
+    pac::{twi0::frequency::FREQUENCY_A, GPIO as P0},
+};
+
+#[cfg(feature = "v2")]
+use microbit::{
+    hal::twim::{Instance, Pins, Twim as I2c},
+    pac::{twim0::frequency::FREQUENCY_A, P0},
+};
+
+#[cfg(feature = "v1")]
+const SCL_PIN: u32 = 0;
+#[cfg(feature = "v1")]
+const SDA_PIN: u32 = 30;
+#[cfg(feature = "v1")]
+const CYCLES_PER_US: u32 = 16;
+
+#[cfg(feature = "v2")]
+const SCL_PIN: u32 = 8;
+#[cfg(feature = "v2")]
+const SDA_PIN: u32 = 16;
+#[cfg(feature = "v2")]
+const CYCLES_PER_US: u32 = 64;
+
+// Half a clock period at 100kHz, slow enough for any slave
+const HALF_PERIOD_US: u32 = 5;
+
+// 16 MHz / 2^4 ticks once per us
+const PRESCALER: u32 = 4;
+// TIMER2 only counts to 16 bits on the nRF51
+const BITMODE_16BIT: u32 = 0;
+const MAX_TIMEOUT_US: u32 = 0xffff;
+
+pub struct BusClear {
+    timer: TIMER2,
+    /// What the peripheral is set up with again after a bus clear
+    frequency: FREQUENCY_A,
+}
+
+impl BusClear {
+    /// `frequency` has to be the one the I2C peripheral was created with
+    pub fn new(timer: TIMER2, frequency: FREQUENCY_A) -> BusClear {
+        timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        timer.bitmode.write(|w| unsafe { w.bits(BITMODE_16BIT) });
+        timer.prescaler.write(|w| unsafe { w.bits(PRESCALER) });
+        timer.intenset.write(|w| w.compare0().set());
+        unsafe { pac::NVIC::unmask(pac::Interrupt::TIMER2) };
+        BusClear { timer, frequency }
+    }
+
+    pub fn free(self) -> TIMER2 {
+        self.disarm();
+        pac::NVIC::mask(pac::Interrupt::TIMER2);
+        self.timer
+    }
+
+    fn disarm(&self) {
+        self.timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.timer.events_compare[0].write(|w| unsafe { w.bits(0) });
+    }
+}
+
+impl<T: Instance> BusRecovery<I2c<T>> for BusClear {
+    fn is_idle(&mut self, _bus: &mut I2c<T>) -> bool {
+        // The peripheral owns the pins, but it leaves their input buffers
+        // connected so we can still look at the line levels.
+        let lines = unsafe { (*P0::ptr()).in_.read().bits() };
+        lines & (1 << SCL_PIN) != 0 && lines & (1 << SDA_PIN) != 0
+    }
+
+    fn bus_clear(&mut self, bus: I2c<T>) -> I2c<T> {
+        let (i2c, pins) = bus.free();
+        let mut scl = pins
+            .scl
+            .into_open_drain_output(OpenDrainConfig::Standard0Disconnect1, Level::High);
+        let sda = pins.sda;
+
+        // Clock out whatever the slave still wants to send until it lets go of SDA
+        for _ in 0..9 {
+            if sda.is_high().unwrap() {
+                break;
+            }
+            scl.set_low().unwrap();
+            self.delay_us(HALF_PERIOD_US);
+            scl.set_high().unwrap();
+            self.delay_us(HALF_PERIOD_US);
+        }
+
+        // STOP condition: SDA goes high while SCL is high
+        let mut sda = sda.into_open_drain_output(OpenDrainConfig::Standard0Disconnect1, Level::High);
+        scl.set_low().unwrap();
+        sda.set_low().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+        scl.set_high().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+        sda.set_high().unwrap();
+        self.delay_us(HALF_PERIOD_US);
+
+        let pins = Pins {
+            scl: scl.into_floating_input(),
+            sda: sda.into_floating_input(),
+        };
+        I2c::new(i2c, pins, self.frequency)
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        cortex_m::asm::delay(us * CYCLES_PER_US);
+    }
+
+    fn arm(&mut self, timeout_us: u32) {
+        let timeout = timeout_us.min(MAX_TIMEOUT_US);
+        self.timer.cc[0].write(|w| unsafe { w.bits(timeout) });
+        self.timer.tasks_clear.write(|w| unsafe { w.bits(1) });
+        self.timer.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn disarm(&mut self) {
+        BusClear::disarm(self);
+    }
+}
+
+/// A transaction hung, start over. Call this from the `TIMER2` interrupt
+/// handler, it is only enabled while `BusClear` owns the timer.
+pub fn on_timeout() -> ! {
+    SCB::sys_reset()
+}
@@ -0,0 +1,243 @@
+//! Buttons A and B as a queue of events, see `discovery_common::button`.
+//!
+//! GPIOTE raises an interrupt on every edge of either button, the handler
+//! timestamps it with RTC0 and hands it to the recognizer. Timeouts like long
+//! presses are checked whenever the main loop asks for the next event.
+//!
+//! GPIOTE only has the one interrupt, so the accelerometer's INT1 line is
+//! watched here as well, see `listen_int1`. The main loop recognizes gestures
+//! in the accelerometer data and queues them next to the button events with
+//! `push_gesture`, so there is a single stream of events to handle.
+
+// Unmasking the interrupts is unsafe
+#![allow(unsafe_code)]
+
+use core::cell::RefCell;
+use cortex_m::interrupt::{CriticalSection, Mutex};
+use discovery_common::button::{Button, ButtonEvent, Config, Recognizer};
+use discovery_common::gesture::Gesture;
+use discovery_common::queue::Queue;
+use embedded_hal::digital::v2::InputPin;
+use microbit::board::Buttons;
+use microbit::hal::gpio::{Floating, Input, Pin};
+use microbit::hal::gpiote::Gpiote;
+use microbit::hal::rtc::{Rtc, RtcInterrupt};
+use microbit::pac::{self, interrupt, RTC0};
+use rtt_target::rprintln;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Button(ButtonEvent),
+    Gesture(Gesture),
+}
+
+/// Events the main loop has not picked up yet, more are dropped
+const QUEUE_LEN: usize = 16;
+/// RTC0 counts the 32.768 kHz clock divided by `PRESCALER + 1`, so 1024 Hz
+const PRESCALER: u32 = 31;
+/// The counter is 24 bits wide
+const COUNTER_BITS: u32 = 24;
+
+struct State {
+    gpiote: Gpiote,
+    rtc: Rtc<RTC0>,
+    /// How often the counter wrapped around
+    overflows: u32,
+    button_a: Pin<Input<Floating>>,
+    button_b: Pin<Input<Floating>>,
+    recognizer: Recognizer,
+    queue: Queue<Event, QUEUE_LEN>,
+    int1: Option<Pin<Input<Floating>>>,
+    /// INT1 went high since the last `take_int1`
+    int1_pending: bool,
+}
+
+impl State {
+    /// Milliseconds since `start`, wrapping around after about 49 days
+    fn now(&mut self) -> u32 {
+        let mut counter = self.rtc.get_counter();
+        // The counter might have wrapped while interrupts were disabled, or
+        // right after it was read. Then the reading might be from before the
+        // overflow that is counted now, read it again.
+        if self.count_overflow() {
+            counter = self.rtc.get_counter();
+        }
+        let ticks = (u64::from(self.overflows) << COUNTER_BITS) + u64::from(counter);
+        (ticks * 1000 / u64::from(32_768 / (PRESCALER + 1))) as u32
+    }
+
+    /// Returns whether the counter wrapped since the last call
+    fn count_overflow(&mut self) -> bool {
+        let overflow = self.rtc.is_event_triggered(RtcInterrupt::Overflow);
+        if overflow {
+            self.rtc.reset_event(RtcInterrupt::Overflow);
+            self.overflows = self.overflows.wrapping_add(1);
+        }
+        overflow
+    }
+
+    fn push(queue: &mut Queue<Event, QUEUE_LEN>, event: Event) {
+        if queue.push(event).is_err() {
+            rprintln!("Event queue full, dropped {:?}", event);
+        }
+    }
+}
+
+static STATE: Mutex<RefCell<Option<State>>> = Mutex::new(RefCell::new(None));
+
+pub fn start(gpiote: pac::GPIOTE, rtc: RTC0, buttons: Buttons, config: Config) {
+    let gpiote = Gpiote::new(gpiote);
+    let button_a = buttons.button_a.degrade();
+    let button_b = buttons.button_b.degrade();
+    gpiote
+        .channel0()
+        .input_pin(&button_a)
+        .toggle()
+        .enable_interrupt();
+    gpiote
+        .channel1()
+        .input_pin(&button_b)
+        .toggle()
+        .enable_interrupt();
+
+    let mut rtc = Rtc::new(rtc, PRESCALER).unwrap();
+    rtc.enable_event(RtcInterrupt::Overflow);
+    rtc.enable_interrupt(RtcInterrupt::Overflow, None);
+    rtc.enable_counter();
+
+    let mut state = State {
+        gpiote,
+        rtc,
+        overflows: 0,
+        button_a,
+        button_b,
+        recognizer: Recognizer::new(config),
+        queue: Queue::new(),
+        int1: None,
+        int1_pending: false,
+    };
+    // A button might already be down, e.g. from forcing a calibration at boot
+    let now = state.now();
+    for (button, pressed) in [
+        (Button::A, state.button_a.is_low().unwrap()),
+        (Button::B, state.button_b.is_low().unwrap()),
+    ] {
+        let State {
+            recognizer, queue, ..
+        } = &mut state;
+        recognizer.input(button, pressed, now, |event| {
+            State::push(queue, Event::Button(event))
+        });
+    }
+
+    cortex_m::interrupt::free(move |cs| {
+        *STATE.borrow(cs).borrow_mut() = Some(state);
+    });
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::GPIOTE);
+        pac::NVIC::unmask(pac::Interrupt::RTC0);
+    }
+}
+
+/// The oldest event not picked up yet
+pub fn next_event() -> Option<Event> {
+    cortex_m::interrupt::free(|cs| {
+        let mut state = STATE.borrow(cs).borrow_mut();
+        let state = state.as_mut()?;
+        let now = state.now();
+        let State {
+            recognizer, queue, ..
+        } = state;
+        recognizer.poll(now, |event| State::push(queue, Event::Button(event)));
+        queue.pop()
+    })
+}
+
+/// Queues a gesture behind the button events that happened before it
+pub fn push_gesture(gesture: Gesture) {
+    cortex_m::interrupt::free(|cs| {
+        with_state(cs, |state| {
+            State::push(&mut state.queue, Event::Gesture(gesture))
+        })
+    });
+}
+
+/// Milliseconds since `start`, the clock the events are timed with
+pub fn now() -> u32 {
+    cortex_m::interrupt::free(|cs| STATE.borrow(cs).borrow_mut().as_mut().map_or(0, State::now))
+}
+
+/// Watches the accelerometer's INT1 line for rising edges. Has to be called
+/// after `start`.
+pub fn listen_int1(pin: Pin<Input<Floating>>) {
+    cortex_m::interrupt::free(|cs| {
+        with_state(cs, |state| {
+            state
+                .gpiote
+                .channel2()
+                .input_pin(&pin)
+                .lo_to_hi()
+                .enable_interrupt();
+            state.int1 = Some(pin);
+        })
+    });
+}
+
+/// Whether INT1 went high since the last call or still is high
+pub fn take_int1() -> bool {
+    cortex_m::interrupt::free(|cs| {
+        let mut pending = false;
+        with_state(cs, |state| {
+            // Still being high after the last check means there was no edge to catch
+            let high = matches!(&state.int1, Some(pin) if pin.is_high().unwrap());
+            pending = state.int1_pending || high;
+            state.int1_pending = false;
+        });
+        pending
+    })
+}
+
+fn with_state(cs: &CriticalSection, f: impl FnOnce(&mut State)) {
+    if let Some(state) = STATE.borrow(cs).borrow_mut().as_mut() {
+        f(state);
+    }
+}
+
+#[interrupt]
+fn GPIOTE() {
+    cortex_m::interrupt::free(|cs| {
+        with_state(cs, |state| {
+            let now = state.now();
+            let mut changed = [None, None];
+            if state.gpiote.channel0().is_event_triggered() {
+                state.gpiote.channel0().reset_events();
+                changed[0] = Some((Button::A, state.button_a.is_low().unwrap()));
+            }
+            if state.gpiote.channel1().is_event_triggered() {
+                state.gpiote.channel1().reset_events();
+                changed[1] = Some((Button::B, state.button_b.is_low().unwrap()));
+            }
+            if state.gpiote.channel2().is_event_triggered() {
+                state.gpiote.channel2().reset_events();
+                state.int1_pending = true;
+            }
+            let State {
+                recognizer, queue, ..
+            } = state;
+            for (button, pressed) in changed.iter().flatten() {
+                recognizer.input(*button, *pressed, now, |event| {
+                    State::push(queue, Event::Button(event))
+                });
+            }
+        })
+    });
+}
+
+#[interrupt]
+fn RTC0() {
+    cortex_m::interrupt::free(|cs| {
+        with_state(cs, |state| {
+            state.count_overflow();
+        })
+    });
+}
@@ -0,0 +1,42 @@
+//! Shows greyscale images on the LED matrix. Unlike the blocking display the
+//! main loop does not have to keep refreshing it, the TIMER1 interrupt does.
+
+// Unmasking the interrupt is unsafe
+#![allow(unsafe_code)]
+
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use microbit::display::nonblocking::{Display, GreyscaleImage};
+use microbit::gpio::DisplayPins;
+use microbit::pac::{self, interrupt, TIMER1};
+
+static DISPLAY: Mutex<RefCell<Option<Display<TIMER1>>>> = Mutex::new(RefCell::new(None));
+
+pub fn start(timer: TIMER1, pins: DisplayPins) {
+    let display = Display::new(timer, pins);
+    cortex_m::interrupt::free(move |cs| {
+        *DISPLAY.borrow(cs).borrow_mut() = Some(display);
+    });
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::TIMER1);
+    }
+}
+
+/// Shows an image with brightness values from 0 to 9
+pub fn show(image: [[u8; 5]; 5]) {
+    let image = GreyscaleImage::new(&image);
+    cortex_m::interrupt::free(|cs| {
+        if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
+            display.show(&image);
+        }
+    });
+}
+
+#[interrupt]
+fn TIMER1() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
+            display.handle_display_event();
+        }
+    });
+}
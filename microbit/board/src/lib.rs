@@ -0,0 +1,9 @@
+//! Board support shared by the micro:bit chapters. Build it with the same
+//! `v1` or `v2` feature as the chapter.
+
+#![deny(unsafe_code)]
+#![no_std]
+
+pub mod buttons;
+pub mod bus_clear;
+pub mod greyscale;
@@ -0,0 +1,122 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use cortex_m_rt::entry;
+use rtt_target::{rtt_init_print, rprintln};
+use panic_rtt_target as _;
+
+use microbit::{display::blocking::Display, hal::Timer, pac::interrupt};
+
+#[cfg(feature = "v1")]
+use microbit::{
+    hal::twi,
+    pac::twi0::frequency::FREQUENCY_A,
+};
+
+#[cfg(feature = "v2")]
+use microbit::{
+    hal::twim,
+    pac::twim0::frequency::FREQUENCY_A,
+};
+
+use discovery_common::i2c::{self, Config, RecoveringI2c};
+use discovery_common::probe::{probe, ProbeError};
+use discovery_microbit::bus_clear::{self, BusClear};
+
+// The sensor is there and it is the chip we expected
+const FOUND: [[u8; 5]; 5] = [
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 1],
+    [0, 0, 0, 1, 0],
+    [1, 0, 1, 0, 0],
+    [0, 1, 0, 0, 0],
+];
+
+// Nobody answered on the bus, the sensor is missing
+const MISSING_SENSOR: [[u8; 5]; 5] = [
+    [1, 0, 0, 0, 1],
+    [0, 1, 0, 1, 0],
+    [0, 0, 1, 0, 0],
+    [0, 1, 0, 1, 0],
+    [1, 0, 0, 0, 1],
+];
+
+// Something answered, but it is not an LSM303
+const WRONG_ID: [[u8; 5]; 5] = [
+    [0, 1, 1, 1, 0],
+    [1, 0, 0, 0, 1],
+    [0, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+];
+
+// Any other bus error
+const BUS_ERROR: [[u8; 5]; 5] = [
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+];
+
+// A data NACK means the device acknowledged its address, so it is there and
+// that is a bus error like any other
+#[cfg(feature = "v1")]
+fn is_address_nack(e: &i2c::Error<twi::Error>) -> bool {
+    matches!(e, i2c::Error::Bus(twi::Error::AddressNack))
+}
+
+#[cfg(feature = "v2")]
+fn is_address_nack(e: &i2c::Error<twim::Error>) -> bool {
+    matches!(e, i2c::Error::Bus(twim::Error::AddressNack))
+}
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    let board = microbit::Board::take().unwrap();
+
+    let mut timer = Timer::new(board.TIMER0);
+    let mut display = Display::new(board.display_pins);
+
+    #[cfg(feature = "v1")]
+    let i2c = twi::Twi::new(board.TWI0, board.i2c.into(), FREQUENCY_A::K100);
+
+    #[cfg(feature = "v2")]
+    let i2c = twim::Twim::new(board.TWIM0, board.i2c_internal.into(), FREQUENCY_A::K100);
+
+    let mut i2c = RecoveringI2c::new(i2c, BusClear::new(board.TIMER2, FREQUENCY_A::K100), Config::default());
+
+    let result = probe(&mut i2c, is_address_nack);
+    rprintln!("I2C statistics: {:?}", i2c.stats());
+
+    let icon = match result {
+        Ok(variant) => {
+            rprintln!("Found a {:?}", variant);
+            FOUND
+        }
+        Err(ProbeError::Nack(sensor)) => {
+            rprintln!("The {:?} did not respond", sensor);
+            MISSING_SENSOR
+        }
+        Err(ProbeError::WrongId { sensor, expected, found }) => {
+            rprintln!("The {:?} id is {:#x}, expected {:#x}", sensor, found, expected);
+            WRONG_ID
+        }
+        Err(ProbeError::Bus(e)) => {
+            rprintln!("Bus error: {:?}", e);
+            BUS_ERROR
+        }
+    };
+
+    loop {
+        display.show(&mut timer, icon, 1000);
+    }
+}
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIMER2() {
+    bus_clear::on_timeout();
+}
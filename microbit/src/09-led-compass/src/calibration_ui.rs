@@ -0,0 +1,136 @@
+//! How the calibration game looks and feels on the micro:bit: the LED matrix
+//! shows the collected cells with the cursor blinking in between, button A
+//! starts over and button B gives up.
+
+use discovery_common::calibrator::{CalibrationUi, Quality, Request};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::InputPin;
+use microbit::display::blocking::Display;
+use rtt_target::rprintln;
+
+/// Shown for a moment when a calibration is rejected, before starting over
+const TRY_AGAIN: [[u8; 5]; 5] = [
+    [1, 0, 0, 0, 1],
+    [0, 1, 0, 1, 0],
+    [0, 0, 1, 0, 0],
+    [0, 1, 0, 1, 0],
+    [1, 0, 0, 0, 1],
+];
+
+/// The end of the completion animation, a tick
+const DONE: [[u8; 5]; 5] = [
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 1],
+    [0, 0, 0, 1, 0],
+    [1, 0, 1, 0, 0],
+    [0, 1, 0, 0, 0],
+];
+
+/// Each ring of the completion animation is shown this long, in ms
+const RING_MS: u32 = 100;
+const DONE_MS: u32 = 1000;
+
+/// Shows the game on the LED matrix. Pressing `restart` starts over, pressing
+/// `abort` gives up on it.
+pub struct MatrixUi<'a, A, B> {
+    display: &'a mut Display,
+    restart: &'a A,
+    abort: &'a B,
+    /// Whether `restart` was down at the last check, so holding it down only
+    /// restarts once
+    restart_held: bool,
+    /// Whether the cursor is lit in the current frame
+    cursor_lit: bool,
+    /// Last progress logged, to only log changes
+    percent: Option<u8>,
+}
+
+impl<'a, A: InputPin, B: InputPin> MatrixUi<'a, A, B> {
+    pub fn new(display: &'a mut Display, restart: &'a A, abort: &'a B) -> Self {
+        MatrixUi {
+            display,
+            restart,
+            abort,
+            // Button A might still be down from forcing the calibration at boot
+            restart_held: is_pressed(restart),
+            cursor_lit: true,
+            percent: None,
+        }
+    }
+}
+
+impl<A: InputPin, B: InputPin> CalibrationUi for MatrixUi<'_, A, B> {
+    fn show_progress<D: DelayUs<u32>>(
+        &mut self,
+        delay: &mut D,
+        collected: [[u8; 5]; 5],
+        cursor: (usize, usize),
+        percent: u8,
+        ms: u32,
+    ) {
+        if self.percent != Some(percent) {
+            rprintln!("Calibrating: {}%", percent);
+            self.percent = Some(percent);
+        }
+        self.cursor_lit = !self.cursor_lit;
+        self.display
+            .show(delay, with_cursor(collected, cursor, self.cursor_lit), ms);
+    }
+
+    fn show_rejected<D: DelayUs<u32>>(&mut self, delay: &mut D, ms: u32) {
+        rprintln!("Calibration rejected, try again");
+        self.percent = None;
+        self.display.show(delay, TRY_AGAIN, ms);
+    }
+
+    fn show_done<D: DelayUs<u32>>(&mut self, delay: &mut D) {
+        rprintln!("Calibration done");
+        for radius in 0..3 {
+            self.display.show(delay, ring(radius), RING_MS);
+        }
+        self.display.show(delay, DONE, DONE_MS);
+        self.display.clear();
+    }
+
+    fn request(&mut self) -> Option<Request> {
+        if is_pressed(self.abort) {
+            return Some(Request::Abort);
+        }
+        let pressed = is_pressed(self.restart);
+        let restart = pressed && !self.restart_held;
+        self.restart_held = pressed;
+        if restart {
+            rprintln!("Restarting calibration");
+            self.percent = None;
+            Some(Request::Restart)
+        } else {
+            None
+        }
+    }
+
+    fn report_quality(&mut self, quality: &Quality) {
+        rprintln!("Calibration quality: {:?}", quality);
+    }
+}
+
+fn is_pressed<P: InputPin>(button: &P) -> bool {
+    matches!(button.is_low(), Ok(true))
+}
+
+/// The collected cells with the cursor cell switched on or off
+fn with_cursor(mut collected: [[u8; 5]; 5], cursor: (usize, usize), lit: bool) -> [[u8; 5]; 5] {
+    collected[cursor.0][cursor.1] = u8::from(lit);
+    collected
+}
+
+/// The square ring `radius` pixels out from the center of the matrix
+fn ring(radius: usize) -> [[u8; 5]; 5] {
+    let mut image = [[0; 5]; 5];
+    for (row, line) in image.iter_mut().enumerate() {
+        for (column, pixel) in line.iter_mut().enumerate() {
+            let distance = row.abs_diff(2).max(column.abs_diff(2));
+            *pixel = u8::from(distance == radius);
+        }
+    }
+    image
+}
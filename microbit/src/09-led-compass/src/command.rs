@@ -0,0 +1,58 @@
+//! Commands accepted over serial while the compass is running, one per line.
+
+use discovery_common::wmm::WMM2025;
+
+/// How long a release of the model is valid for
+const MODEL_YEARS: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// `DECL <degrees>`: the magnetic declination, positive if magnetic north
+    /// lies east of true north
+    SetDeclination(f32),
+    /// `LOC <latitude> <longitude> <year>`: where and when the compass is
+    /// used, in degrees north and east and as a decimal year such as
+    /// `2026.8`. The board works out the declination from the World Magnetic
+    /// Model, so the year has to be within the five years it is valid for.
+    /// The poles are fine, the model handles them.
+    SetLocation {
+        latitude: f32,
+        longitude: f32,
+        year: f32,
+    },
+}
+
+pub fn parse(line: &str) -> Option<Command> {
+    let mut fields = line.split_ascii_whitespace();
+    let command = match fields.next()? {
+        "DECL" => {
+            let declination: f32 = fields.next()?.parse().ok()?;
+            if !(-180.0..=180.0).contains(&declination) {
+                return None;
+            }
+            Command::SetDeclination(declination)
+        }
+        "LOC" => {
+            let latitude: f32 = fields.next()?.parse().ok()?;
+            let longitude: f32 = fields.next()?.parse().ok()?;
+            let year: f32 = fields.next()?.parse().ok()?;
+            let epoch = WMM2025.epoch;
+            if !(-90.0..=90.0).contains(&latitude)
+                || !(-180.0..=360.0).contains(&longitude)
+                || !(epoch..epoch + MODEL_YEARS).contains(&year)
+            {
+                return None;
+            }
+            Command::SetLocation {
+                latitude,
+                longitude,
+                year,
+            }
+        }
+        _ => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(command)
+}
@@ -0,0 +1,23 @@
+//! Turns calibrated magnetometer data into a compass heading.
+
+pub use discovery_common::angle::normalize;
+use discovery_common::{tilt, vector::Vector3};
+use lsm303agr::Measurement;
+
+/// Heading of the top edge of the board in degrees. 0 is magnetic north and
+/// the heading grows clockwise, so 90 is east. The accelerometer is used to
+/// compensate for the board being tilted, both measurements have to be in the
+/// cartesian frame.
+pub fn tilt_compensated_heading(mag: Measurement, accel: Measurement) -> f32 {
+    tilt::heading(to_vector(mag), to_vector(accel))
+}
+
+pub fn to_vector(measurement: Measurement) -> Vector3 {
+    Vector3::new(measurement.x as f32, measurement.y as f32, measurement.z as f32)
+}
+
+/// Turns a heading relative to magnetic north into one relative to true
+/// north, `declination` is positive if magnetic north lies east of it.
+pub fn true_heading(magnetic: f32, declination: f32) -> f32 {
+    normalize(magnetic + declination)
+}
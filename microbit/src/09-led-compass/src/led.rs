@@ -1,14 +1,12 @@
-#[derive(Debug)]
-pub enum Direction{
-    North,
-    NorthEast,
-    East,
-    SouthEast,
-    South,
-    SouthWest,
-    West,
-    NorthWest,
-}
+pub use discovery_common::direction::Direction;
+use discovery_common::filter::SectorHysteresis;
+use discovery_common::needle::{needle, MAX_BRIGHTNESS};
+
+use crate::heading::normalize;
+
+/// How far past the border between two arrows north has to be before the
+/// other one is shown, in degrees
+const ARROW_HYSTERESIS: f32 = 5.0;
 
 const NORTH: [[u8; 5]; 5] = [
     [0, 0, 1, 0, 0],
@@ -19,54 +17,6 @@ const NORTH: [[u8; 5]; 5] = [
 ];
 
 const NORTH_EAST: [[u8; 5]; 5] = [
-    [1, 1, 1, 0, 0],
-    [1, 1, 0, 0, 0],   
-    [1, 0, 1, 0, 0],
-    [0, 0, 0, 1, 0],
-    [0, 0, 0, 0, 1],   
-];
-
-const EAST: [[u8; 5]; 5] = [
-    [0, 0, 1, 0, 0],
-    [0, 1, 0, 0, 0],   
-    [1, 1, 1, 1, 1],
-    [0, 1, 0, 0, 0],
-    [0, 0, 1, 0, 0],   
-];
-
-const SOUTH_EAST: [[u8; 5]; 5] = [
-    [0, 0, 0, 0, 1],
-    [0, 0, 0, 1, 0],   
-    [1, 0, 1, 0, 0],
-    [1, 1, 0, 0, 0],
-    [1, 1, 1, 0, 0],   
-];
-
-const SOUTH: [[u8; 5]; 5] = [
-    [0, 0, 1, 0, 0],
-    [0, 0, 1, 0, 0],   
-    [1, 0, 1, 0, 1],
-    [0, 1, 1, 1, 0],
-    [0, 0, 1, 0, 0],   
-];
-
-const SOUTH_WEST: [[u8; 5]; 5] = [
-    [1, 0, 0, 0, 0],
-    [0, 1, 0, 0, 0],   
-    [0, 0, 1, 0, 1],
-    [0, 0, 0, 1, 1],
-    [0, 0, 1, 1, 1],   
-];
-
-const WEST: [[u8; 5]; 5] = [
-    [0, 0, 1, 0, 0],
-    [0, 0, 0, 1, 0],   
-    [1, 1, 1, 1, 1],
-    [0, 0, 0, 1, 0],
-    [0, 0, 1, 0, 0],   
-];
-
-const NORTH_WEST: [[u8; 5]; 5] = [
     [0, 0, 1, 1, 1],
     [0, 0, 0, 1, 1],   
     [0, 0, 1, 0, 1],
@@ -74,15 +24,85 @@ const NORTH_WEST: [[u8; 5]; 5] = [
     [1, 0, 0, 0, 0],   
 ];
 
+/// Turns an image a quarter turn clockwise
+fn rotate(image: [[u8; 5]; 5]) -> [[u8; 5]; 5] {
+    let mut rotated = [[0; 5]; 5];
+    for (row, line) in rotated.iter_mut().enumerate() {
+        for (column, led) in line.iter_mut().enumerate() {
+            *led = image[4 - column][row];
+        }
+    }
+    rotated
+}
+
+/// The other arrows are `NORTH` and `NORTH_EAST` turned clockwise, so they
+/// can't end up pointing the mirrored way
 pub fn direction_to_led(direction: Direction) -> [[u8; 5]; 5] {
-    match direction {
-        Direction::North => NORTH,
-        Direction::NorthEast => NORTH_EAST,
-        Direction::East => EAST,
-        Direction::SouthEast => SOUTH_EAST,
-        Direction::South => SOUTH,
-        Direction::SouthWest => SOUTH_WEST,
-        Direction::West => WEST,
-        Direction::NorthWest => NORTH_WEST,
+    let steps = direction as u8;
+    let mut image = if steps % 2 == 0 { NORTH } else { NORTH_EAST };
+    for _ in 0..steps / 2 {
+        image = rotate(image);
+    }
+    image
+}
+
+/// How the compass shows where north is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// One of the eight arrows above
+    Arrow,
+    /// A single point on the outer LEDs, blended between two of them
+    Needle,
+    /// Like `Needle`, with the middle LED lit as well
+    NeedleWithCenter,
+}
+
+impl Style {
+    /// The style after this one, wrapping around
+    pub fn next(self) -> Style {
+        match self {
+            Style::Arrow => Style::Needle,
+            Style::Needle => Style::NeedleWithCenter,
+            Style::NeedleWithCenter => Style::Arrow,
+        }
+    }
+}
+
+pub struct CompassFace {
+    style: Style,
+    arrow: SectorHysteresis,
+}
+
+impl CompassFace {
+    pub const fn new(style: Style) -> CompassFace {
+        CompassFace {
+            style,
+            arrow: SectorHysteresis::new(8, ARROW_HYSTERESIS),
+        }
+    }
+
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// The greyscale image pointing towards north while the board is turned to `heading`
+    pub fn render(&mut self, heading: f32) -> [[u8; 5]; 5] {
+        let north = normalize(360.0 - heading);
+        match self.style {
+            Style::Arrow => {
+                let sector = self.arrow.update(north);
+                let mut image = direction_to_led(Direction::North.rotate(sector as i32));
+                for led in image.iter_mut().flatten() {
+                    *led *= MAX_BRIGHTNESS;
+                }
+                image
+            }
+            Style::Needle => needle(north, false),
+            Style::NeedleWithCenter => needle(north, true),
+        }
     }
 }
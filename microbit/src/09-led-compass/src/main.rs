@@ -2,24 +2,100 @@
 #![no_main]
 #![no_std]
 
+use core::fmt::Write;
 use cortex_m_rt::entry;
 use panic_rtt_target as _;
 use rtt_target::{rprintln, rtt_init_print};
 
+use discovery_common::ahrs::{Gains, Mahony};
+use discovery_common::button::{self, Button, ButtonEvent};
+use discovery_common::filter::{CircularEma, HeadingFilter};
+use discovery_common::i2c::{Config, RecoveringI2c};
+use discovery_common::line::LineBuffer;
+use discovery_common::units::{Angle, MagneticField};
+use discovery_common::wmm::WMM2025;
+use embedded_hal::serial::Read;
+
+use discovery_microbit::buttons::{self, Event};
+use discovery_microbit::bus_clear::{self, BusClear};
+use discovery_microbit::greyscale;
+
 mod calibration;
-use crate::calibration::calc_calibration;
+use crate::calibration::{calc_calibration_with, Algorithm};
+mod calibration_ui;
 use crate::calibration::calibrated_measurement;
+use crate::calibration::to_cartesian;
+
+mod command;
+use crate::command::Command;
+
+mod heading;
+use crate::heading::{tilt_compensated_heading, to_vector, true_heading};
+
+mod led;
+use crate::led::{CompassFace, Direction, Style};
+
+mod storage;
+use crate::storage::CalibrationStorage;
 
-use microbit::{display::blocking::Display, hal::Timer};
+mod stream;
+use crate::stream::stream_calibration;
+
+use microbit::{display::blocking::Display, hal::prelude::*, hal::Timer, pac::interrupt};
 
 #[cfg(feature = "v1")]
-use microbit::{hal::twi, pac::twi0::frequency::FREQUENCY_A};
+use microbit::{
+    hal::twi,
+    hal::uart,
+    hal::uart::{Baudrate, Parity},
+    pac::twi0::frequency::FREQUENCY_A,
+};
+
+#[cfg(feature = "v2")]
+use microbit::{
+    hal::twim,
+    hal::uarte,
+    hal::uarte::{Baudrate, Parity},
+    pac::twim0::frequency::FREQUENCY_A,
+};
 
 #[cfg(feature = "v2")]
-use microbit::{hal::twim, pac::twim0::frequency::FREQUENCY_A};
+mod serial_setup;
+#[cfg(feature = "v2")]
+use serial_setup::UartePort;
 
 use lsm303agr::{AccelOutputDataRate, Lsm303agr, MagOutputDataRate};
 
+/// `Algorithm::HillClimb` is the one the micro:bit firmware uses
+const CALIBRATION_ALGORITHM: Algorithm = Algorithm::Ellipsoid;
+/// `Style::Arrow` shows the arrows from the first solution
+const DISPLAY_STYLE: Style = Style::Needle;
+/// `HeadingFilter::Median(CircularMedian::new())` ignores outliers, `HeadingFilter::Raw` turns smoothing off
+const HEADING_FILTER: HeadingFilter<5> = HeadingFilter::Average(CircularEma::new(0.3));
+/// The magnetometer's output data rate, one orientation update per reading
+const SAMPLE_RATE_HZ: f32 = 10.0;
+/// Without a gyroscope the filter can follow the accelerometer and magnetometer closely
+const AHRS_GAINS: Gains = Gains { kp: 5.0, ki: 0.0 };
+/// Long enough for `LOC` with a few decimals on every number
+const COMMAND_LEN: usize = 48;
+/// How long the main loop waits between two rounds. The host tools send a
+/// command slower than one byte per round, the UART only buffers a few.
+const POLL_MS: u32 = 10;
+/// How long the main loop waits for the rest of a command before it carries
+/// on with the compass
+const COMMAND_GAP_MS: u32 = 100;
+/// How long the error is shown before the sensor setup is tried again
+const RETRY_MS: u32 = 500;
+
+// The sensor could not be set up
+const SENSOR_ERROR: [[u8; 5]; 5] = [
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+];
+
 #[entry]
 fn main() -> ! {
     rtt_init_print!();
@@ -31,22 +107,198 @@ fn main() -> ! {
     #[cfg(feature = "v2")]
     let i2c = { twim::Twim::new(board.TWIM0, board.i2c_internal.into(), FREQUENCY_A::K100) };
 
+    #[cfg(feature = "v1")]
+    let mut serial = {
+        uart::Uart::new(
+            board.UART0,
+            board.uart.into(),
+            Parity::EXCLUDED,
+            Baudrate::BAUD115200,
+        )
+    };
+
+    #[cfg(feature = "v2")]
+    let mut serial = {
+        let serial = uarte::Uarte::new(
+            board.UARTE0,
+            board.uart.into(),
+            Parity::EXCLUDED,
+            Baudrate::BAUD115200,
+        );
+        UartePort::new(serial)
+    };
+
     let mut timer = Timer::new(board.TIMER0);
     let mut display = Display::new(board.display_pins);
 
+    let i2c = RecoveringI2c::new(i2c, BusClear::new(board.TIMER2, FREQUENCY_A::K100), Config::default());
+
+    // Like the readings below, a failed setup is logged and tried again
     let mut sensor = Lsm303agr::new_with_i2c(i2c);
-    sensor.init().unwrap();
-    sensor.set_mag_odr(MagOutputDataRate::Hz10).unwrap();
-    sensor.set_accel_odr(AccelOutputDataRate::Hz10).unwrap();
-    let mut sensor = sensor.into_mag_continuous().ok().unwrap();
+    let mut sensor = loop {
+        let configured = sensor
+            .init()
+            .and_then(|_| sensor.set_mag_odr(MagOutputDataRate::Hz10))
+            .and_then(|_| sensor.set_accel_odr(AccelOutputDataRate::Hz10));
+        match configured {
+            Ok(()) => match sensor.into_mag_continuous() {
+                Ok(sensor) => break sensor,
+                Err(e) => {
+                    rprintln!("Magnetometer error: {:?}", e.error);
+                    sensor = e.dev;
+                }
+            },
+            Err(e) => rprintln!("Magnetometer error: {:?}", e),
+        }
+        display.show(&mut timer, SENSOR_ERROR, RETRY_MS);
+    };
+
+    // Holding button A during boot forces a new calibration, holding button B
+    // hands it off to a host instead
+    let force_calibration = board.buttons.button_a.is_low().unwrap();
+    let host_calibration = board.buttons.button_b.is_low().unwrap();
+    let mut storage = CalibrationStorage::new(board.NVMC);
+    let stored = storage.load();
+    let calibration = match stored {
+        _ if host_calibration => {
+            rprintln!("Streaming samples, waiting for a calibration from the host");
+            stream_calibration(&mut sensor, &mut serial, &mut storage)
+        }
+        Some(calibration) if !force_calibration => {
+            rprintln!("Loaded calibration from flash");
+            calibration
+        }
+        _ => match calc_calibration_with(
+            &mut sensor,
+            &mut display,
+            &mut timer,
+            &board.buttons.button_a,
+            &board.buttons.button_b,
+            CALIBRATION_ALGORITHM,
+        ) {
+            Ok(calibration) => {
+                if let Err(e) = storage.store(&calibration) {
+                    rprintln!("Failed to store calibration: {:?}", e);
+                }
+                calibration
+            }
+            // Keep the previous calibration, a rough heading beats none at all
+            Err(e) => {
+                rprintln!("Calibration failed: {:?}", e);
+                stored.unwrap_or_default()
+            }
+        },
+    };
+    rprintln!("Calibration: {}", calibration);
+    write!(serial, "{}\r\n", calibration).ok();
+    let mut declination = storage.load_declination().unwrap_or(0.0);
+    rprintln!("Declination: {:.1}", declination);
 
-    let calibration = calc_calibration(&mut sensor, &mut display, &mut timer);
-    rprintln!("Calibration: {:?}", calibration);
     rprintln!("Calibration done, entering busy loop");
+    // The calibration game is done with the blocking display, from now on the
+    // display refreshes itself in the background
+    greyscale::start(board.TIMER1, display.free());
+    buttons::start(board.GPIOTE, board.RTC0, board.buttons, button::Config::default());
+
+    let mut line = LineBuffer::<COMMAND_LEN>::new();
+    let mut last_byte = 0;
+    let mut filter = HEADING_FILTER;
+    let mut face = CompassFace::new(DISPLAY_STYLE);
+    let mut ahrs = Mahony::new(SAMPLE_RATE_HZ, AHRS_GAINS);
     loop {
-        while !sensor.mag_status().unwrap().xyz_new_data {}
-        let mut data = sensor.mag_data().unwrap();
-        data = calibrated_measurement(data, &calibration);
-        rprintln!("x: {}, y: {}, z: {}", data.x, data.y, data.z);
+        while let Some(event) = buttons::next_event() {
+            rprintln!("Event: {:?}", event);
+            // Clicking button A cycles through the display styles
+            if event == Event::Button(ButtonEvent::Click(Button::A)) {
+                face.set_style(face.style().next());
+            }
+        }
+
+        while let Ok(byte) = serial.read() {
+            last_byte = buttons::now();
+            let reply = match line.push(byte) {
+                None => continue,
+                Some(Ok(received)) => match command::parse(received) {
+                    Some(Command::SetDeclination(value)) => {
+                        declination = value;
+                        if let Err(e) = storage.store_declination(value) {
+                            rprintln!("Failed to store declination: {:?}", e);
+                        }
+                        "OK"
+                    }
+                    Some(Command::SetLocation {
+                        latitude,
+                        longitude,
+                        year,
+                    }) => {
+                        // `command::parse` only accepts years the model is valid for
+                        declination = WMM2025.declination(latitude, longitude, 0.0, year);
+                        rprintln!("Declination: {:.1}", declination);
+                        if let Err(e) = storage.store_declination(declination) {
+                            rprintln!("Failed to store declination: {:?}", e);
+                        }
+                        "OK"
+                    }
+                    None => "ERR",
+                },
+                Some(Err(e)) => {
+                    rprintln!("Invalid line: {:?}", e);
+                    "ERR"
+                }
+            };
+            write!(serial, "{}\r\n", reply).ok();
+        }
+        // Keep reading while the rest of a command is on its way
+        if line.is_receiving() && buttons::now().wrapping_sub(last_byte) < COMMAND_GAP_MS {
+            continue;
+        }
+
+        // Bus errors have already been retried, log them and keep going
+        let data = match sensor.mag_status() {
+            Ok(status) if !status.xyz_new_data => None,
+            Ok(_) => Some(
+                sensor
+                    .mag_data()
+                    .and_then(|mag| Ok((mag, sensor.accel_data()?))),
+            ),
+            Err(e) => Some(Err(e)),
+        };
+        match data {
+            Some(Ok((mag, accel))) => {
+                let data = calibrated_measurement(mag, &calibration);
+                let accel = to_cartesian(accel);
+                let magnetic = tilt_compensated_heading(data, accel);
+                ahrs.update(None, to_vector(accel), to_vector(data));
+                let heading = filter.update(true_heading(magnetic, declination));
+                rprintln!(
+                    "x: {}, y: {}, z: {}, magnetic: {}, heading: {} ({})",
+                    MagneticField::from_nt(data.x),
+                    MagneticField::from_nt(data.y),
+                    MagneticField::from_nt(data.z),
+                    Angle::from_degrees(magnetic),
+                    Angle::from_degrees(heading),
+                    Direction::from_heading(heading)
+                );
+                write!(serial, "heading: {:.1}\r\n", heading).ok();
+                let q = ahrs.orientation();
+                let euler = ahrs.euler();
+                write!(
+                    serial,
+                    "orientation: {:.3} {:.3} {:.3} {:.3} pitch: {:.1} roll: {:.1} yaw: {:.1}\r\n",
+                    q.w, q.x, q.y, q.z, euler.pitch, euler.roll, euler.yaw
+                )
+                .ok();
+                greyscale::show(face.render(heading));
+            }
+            Some(Err(e)) => rprintln!("Magnetometer error: {:?}", e),
+            None => {}
+        }
+        timer.delay_ms(POLL_MS);
     }
 }
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIMER2() {
+    bus_clear::on_timeout();
+}
@@ -0,0 +1,131 @@
+//! Keeps the compass settings in a reserved flash page so they survive a reset.
+//!
+//! The page is cut off the end of `FLASH` in `memory.x`. It holds one record
+//! per setting, each in a slot of its own: a magic number, the record version,
+//! the encoded setting and a CRC-32 over everything before it.
+
+use crate::calibration::Calibration;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use microbit::hal::nvmc::{Nvmc, NvmcError};
+use microbit::pac::NVMC;
+
+/// Must match the region removed from `FLASH` in `memory.x`
+const STORAGE_ADDR: usize = 0x3f000;
+/// One page on the nRF52, four on the nRF51
+const STORAGE_LEN: usize = 4096;
+
+const HEADER_LEN: usize = 8;
+const CRC_LEN: usize = 4;
+/// Every record starts at a multiple of this
+const SLOT_LEN: usize = 128;
+const SLOTS: usize = 2;
+
+struct Record {
+    slot: usize,
+    magic: u32,
+    version: u16,
+}
+
+const CALIBRATION: Record = Record {
+    slot: 0,
+    magic: 0x424c_4143, // "CALB"
+    version: 2,
+};
+
+const DECLINATION: Record = Record {
+    slot: 1,
+    magic: 0x4c43_4544, // "DECL"
+    version: 1,
+};
+
+pub struct CalibrationStorage {
+    nvmc: Nvmc<NVMC>,
+}
+
+impl CalibrationStorage {
+    #[allow(unsafe_code)]
+    pub fn new(nvmc: NVMC) -> CalibrationStorage {
+        // The linker never places anything in this page, so nobody else holds a reference to it
+        let storage = unsafe { core::slice::from_raw_parts_mut(STORAGE_ADDR as *mut u8, STORAGE_LEN) };
+        CalibrationStorage {
+            nvmc: Nvmc::new(nvmc, storage),
+        }
+    }
+
+    /// Returns the stored calibration, or `None` if there is none, it was
+    /// written by an incompatible version or is corrupted.
+    pub fn load(&mut self) -> Option<Calibration> {
+        let mut calibration = [0; Calibration::ENCODED_LEN];
+        self.load_record(&CALIBRATION, &mut calibration)?;
+        Some(Calibration::from_bytes(&calibration))
+    }
+
+    pub fn store(&mut self, calibration: &Calibration) -> Result<(), NvmcError> {
+        self.store_record(&CALIBRATION, &calibration.to_bytes())
+    }
+
+    /// Returns the stored magnetic declination in degrees, see `load`
+    pub fn load_declination(&mut self) -> Option<f32> {
+        let mut declination = [0; 4];
+        self.load_record(&DECLINATION, &mut declination)?;
+        Some(f32::from_le_bytes(declination))
+    }
+
+    pub fn store_declination(&mut self, declination: f32) -> Result<(), NvmcError> {
+        self.store_record(&DECLINATION, &declination.to_le_bytes())
+    }
+
+    fn load_record(&mut self, record: &Record, payload: &mut [u8]) -> Option<()> {
+        let mut buffer = [0; SLOT_LEN];
+        let data_len = HEADER_LEN + payload.len();
+        let buffer = &mut buffer[..data_len + CRC_LEN];
+        self.nvmc.read((record.slot * SLOT_LEN) as u32, buffer).ok()?;
+
+        let magic = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+        let version = u16::from_le_bytes([buffer[4], buffer[5]]);
+        if magic != record.magic || version != record.version {
+            return None;
+        }
+
+        let (data, crc) = buffer.split_at(data_len);
+        if crc32(data) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return None;
+        }
+
+        payload.copy_from_slice(&data[HEADER_LEN..]);
+        Some(())
+    }
+
+    /// Erasing works on whole pages only, so the other records are read
+    /// first and written back along with the new one.
+    fn store_record(&mut self, record: &Record, payload: &[u8]) -> Result<(), NvmcError> {
+        let mut page = [0; SLOTS * SLOT_LEN];
+        self.nvmc.read(0, &mut page)?;
+
+        let slot = &mut page[record.slot * SLOT_LEN..][..SLOT_LEN];
+        let data_len = HEADER_LEN + payload.len();
+        slot.fill(0xff);
+        slot[0..4].copy_from_slice(&record.magic.to_le_bytes());
+        slot[4..6].copy_from_slice(&record.version.to_le_bytes());
+        slot[6..HEADER_LEN].fill(0);
+        slot[HEADER_LEN..data_len].copy_from_slice(payload);
+        let crc = crc32(&slot[..data_len]);
+        slot[data_len..data_len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        self.nvmc.erase(0, STORAGE_LEN as u32)?;
+        self.nvmc.write(0, &page)
+    }
+}
+
+/// CRC-32 (IEEE 802.3), bit by bit since we only ever check a few bytes
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
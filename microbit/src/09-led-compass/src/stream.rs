@@ -0,0 +1,72 @@
+//! Calibrating on a host: the board streams raw samples over serial and waits
+//! for the fitted calibration to be sent back, see `host/calibration-tool`.
+//!
+//! Every new magnetometer reading is sent as a `Sample` line. As soon as the
+//! host starts sending, streaming pauses until a whole line arrived. If it is
+//! a valid `Calibration` it is stored and acknowledged with `OK`, otherwise
+//! the board answers with `ERR` and goes back to streaming.
+
+use core::fmt::{Debug, Write};
+use discovery_common::line::LineBuffer;
+use embedded_hal::blocking::i2c;
+use embedded_hal::serial::Read;
+use lsm303agr::interface::I2cInterface;
+use lsm303agr::mode::MagContinuous;
+use lsm303agr::Lsm303agr;
+use rtt_target::rprintln;
+
+use crate::calibration::{raw_sample, Calibration};
+use crate::storage::CalibrationStorage;
+
+/// Comfortably longer than a `Calibration` in its text form
+const LINE_LEN: usize = 160;
+
+pub fn stream_calibration<I, E, S>(
+    sensor: &mut Lsm303agr<I2cInterface<I>, MagContinuous>,
+    serial: &mut S,
+    storage: &mut CalibrationStorage,
+) -> Calibration
+where
+    I: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: Debug,
+    S: Write + Read<u8>,
+{
+    let mut line = LineBuffer::<LINE_LEN>::new();
+    loop {
+        // Stay quiet while a line is coming in, the receiver only buffers a few bytes
+        let streaming = !line.is_receiving();
+        if streaming && matches!(sensor.mag_status(), Ok(status) if status.xyz_new_data) {
+            match sensor
+                .mag_data()
+                .and_then(|mag| Ok((mag, sensor.accel_data()?)))
+            {
+                Ok((mag, accel)) => {
+                    write!(serial, "{}\r\n", raw_sample(mag, accel)).ok();
+                }
+                Err(e) => rprintln!("Magnetometer error: {:?}", e),
+            }
+        }
+
+        let received = match serial.read().ok().and_then(|byte| line.push(byte)) {
+            Some(received) => received,
+            None => continue,
+        };
+        match received.map(str::parse::<Calibration>) {
+            Ok(Ok(calibration)) => {
+                if let Err(e) = storage.store(&calibration) {
+                    rprintln!("Failed to store calibration: {:?}", e);
+                }
+                write!(serial, "OK\r\n").ok();
+                return calibration;
+            }
+            Ok(Err(e)) => {
+                rprintln!("Invalid calibration: {:?}", e);
+                write!(serial, "ERR\r\n").ok();
+            }
+            Err(e) => {
+                rprintln!("Invalid line: {:?}", e);
+                write!(serial, "ERR\r\n").ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use cortex_m_rt::entry;
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use discovery_common::accel_fifo::{self, AccelFifo, DataRate};
+use discovery_common::button;
+use discovery_common::font::{Glyph, BLANK};
+use discovery_common::gesture::{self, GestureDetector, HardwareEvent, Source};
+use discovery_common::i2c::{Config, RecoveringI2c};
+use discovery_common::units::{Acceleration, Chip, Range};
+
+use discovery_microbit::buttons::{self, Event};
+use discovery_microbit::bus_clear::{self, BusClear};
+use discovery_microbit::greyscale;
+
+use microbit::pac::interrupt;
+
+#[cfg(feature = "v1")]
+use microbit::{hal::twi, pac::twi0::frequency::FREQUENCY_A};
+
+#[cfg(feature = "v2")]
+use microbit::{hal::twim, pac::twim0::frequency::FREQUENCY_A};
+
+/// The same samples as the punch-o-meter, a shake stays well below 8 G
+const FIFO_CONFIG: accel_fifo::Config = accel_fifo::Config {
+    chip: Chip::Lsm303agr,
+    data_rate: DataRate::Hz400,
+    range: Range::G8,
+    watermark: 16,
+};
+/// How long the display lights up for every gesture, in ms
+const FLASH_MS: u32 = 200;
+/// How long the error is shown before the sensor setup is tried again
+const RETRY_MS: u32 = 500;
+
+// Every gesture lights up the whole display
+const FLASH: Glyph = [[5; 5]; 5];
+// The sensor could not be set up
+const SENSOR_ERROR: Glyph = [
+    [0, 0, 9, 0, 0],
+    [0, 0, 9, 0, 0],
+    [0, 0, 9, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 9, 0, 0],
+];
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    let board = microbit::Board::take().unwrap();
+
+    #[cfg(feature = "v1")]
+    let i2c = { twi::Twi::new(board.TWI0, board.i2c.into(), FREQUENCY_A::K100) };
+
+    #[cfg(feature = "v2")]
+    let i2c = { twim::Twim::new(board.TWIM0, board.i2c_internal.into(), FREQUENCY_A::K100) };
+
+    // Draining the FIFO is never retried, see `accel_fifo`
+    let bus_clear = BusClear::new(board.TIMER2, FREQUENCY_A::K100);
+    let i2c = RecoveringI2c::new(i2c, bus_clear, Config::default());
+
+    greyscale::start(board.TIMER1, board.display_pins);
+    // The buttons driver also queues the gestures and watches INT1
+    buttons::start(
+        board.GPIOTE,
+        board.RTC0,
+        board.buttons,
+        button::Config::default(),
+    );
+    #[cfg(feature = "v1")]
+    buttons::listen_int1(board.pins.p0_28.into_floating_input().degrade());
+    #[cfg(feature = "v2")]
+    buttons::listen_int1(board.pins.p0_25.into_floating_input().degrade());
+
+    // The accelerometer looks for taps and falls itself, between two samples
+    // a short tap might be missed
+    let config = gesture::Config {
+        taps: Source::Hardware,
+        freefall: Source::Hardware,
+        ..gesture::Config::default()
+    };
+    let mut fifo = AccelFifo::new(i2c, FIFO_CONFIG);
+    while let Err(e) = fifo
+        .start()
+        .and_then(|_| fifo.enable_taps(config.tap_threshold, config.tap_limit_ms))
+        .and_then(|_| fifo.enable_freefall(config.freefall_threshold, config.freefall_ms))
+    {
+        rprintln!("Accelerometer error: {:?}", e);
+        greyscale::show(SENSOR_ERROR);
+        let since = buttons::now();
+        while buttons::now().wrapping_sub(since) < RETRY_MS {
+            cortex_m::asm::wfi();
+        }
+    }
+
+    let mut gestures = GestureDetector::new(config);
+    let mut flashed = None;
+    let mut samples = [[Acceleration::ZERO; 3]; accel_fifo::LEN];
+    loop {
+        let now = buttons::now();
+        while let Some(event) = buttons::next_event() {
+            if let Event::Gesture(gesture) = event {
+                rprintln!("Gesture: {:?}", gesture);
+                flashed = Some(now);
+            }
+        }
+
+        if buttons::take_int1() {
+            match fifo.drain(&mut samples) {
+                Ok(drained) => {
+                    for (i, sample) in samples[..drained.count].iter().enumerate() {
+                        // The newest sample is the last one
+                        let age =
+                            (drained.count - 1 - i) as u32 * 1000 / FIFO_CONFIG.data_rate.hz();
+                        let time = now.wrapping_sub(age);
+                        gestures.update(to_board(*sample), time, buttons::push_gesture);
+                    }
+                }
+                Err(e) => rprintln!("Accelerometer error: {:?}", e),
+            }
+            // Reading the sources also clears them, so INT1 can fire again
+            match fifo.events() {
+                Ok(events) => {
+                    if events.tap {
+                        gestures.hardware(HardwareEvent::Tap, now, buttons::push_gesture);
+                    }
+                    if events.freefall {
+                        gestures.hardware(HardwareEvent::Freefall, now, buttons::push_gesture);
+                    }
+                }
+                Err(e) => rprintln!("Accelerometer error: {:?}", e),
+            }
+        }
+        // A tap is only reported once no second one followed
+        gestures.poll(now, buttons::push_gesture);
+
+        greyscale::show(match flashed {
+            Some(since) if now.wrapping_sub(since) < FLASH_MS => FLASH,
+            _ => BLANK,
+        });
+        // Any interrupt wakes us up again, INT1 as well as the display's
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Turns a sample into the board frame the gesture detector works in, the
+/// same way the LED compass does
+fn to_board([x, y, z]: [Acceleration; 3]) -> [Acceleration; 3] {
+    [x, -y, z]
+}
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIMER2() {
+    bus_clear::on_timeout();
+}
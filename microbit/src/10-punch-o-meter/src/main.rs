@@ -3,13 +3,195 @@
 #![no_std]
 
 use cortex_m_rt::entry;
-use rtt_target::rtt_init_print;
 use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use discovery_common::accel_fifo::{self, AccelFifo, DataRate};
+use discovery_common::button::{self, Button, ButtonEvent};
+use discovery_common::font::{self, Glyph, BLANK};
+use discovery_common::i2c::{Config, RecoveringI2c};
+use discovery_common::peak::{self, PeakDetector, State};
+use discovery_common::units::{Acceleration, Chip, Range};
+
+use discovery_microbit::buttons::{self, Event};
+use discovery_microbit::bus_clear::{self, BusClear};
+use discovery_microbit::greyscale;
+
+use microbit::pac::interrupt;
+
+#[cfg(feature = "v1")]
+use microbit::{hal::twi, pac::twi0::frequency::FREQUENCY_A};
+
+#[cfg(feature = "v2")]
+use microbit::{hal::twim, pac::twim0::frequency::FREQUENCY_A};
+
+/// The threshold is the X acceleration that starts a measurement. The board
+/// lies flat, so gravity does not add to it.
+const PEAK_CONFIG: peak::Config<Acceleration> = peak::Config {
+    threshold: Acceleration::from_mg(1_000),
+    window_ms: 1_000,
+    cooldown_ms: 500,
+};
+/// Punches are over in a few ms, so sample often. Allow the sensor to
+/// measure up to 16 G since human punches can actually be quite fast. INT1
+/// fires every 40 ms, leaving the FIFO half empty as slack.
+const FIFO_CONFIG: accel_fifo::Config = accel_fifo::Config {
+    chip: Chip::Lsm303agr,
+    data_rate: DataRate::Hz400,
+    range: Range::G16,
+    watermark: 16,
+};
+/// How long each character of the result is shown, in ms
+const GLYPH_MS: u32 = 500;
+const BRIGHTNESS: u8 = 9;
+/// How long the error is shown before the sensor setup is tried again
+const RETRY_MS: u32 = 500;
+
+// The sensor could not be set up
+const SENSOR_ERROR: Glyph = [
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+];
+
+/// The last result on the display
+struct Shown {
+    since: u32,
+    text: [char; 4],
+    len: usize,
+}
 
 #[entry]
 fn main() -> ! {
     rtt_init_print!();
     let board = microbit::Board::take().unwrap();
 
-    loop {}
+    #[cfg(feature = "v1")]
+    let i2c = { twi::Twi::new(board.TWI0, board.i2c.into(), FREQUENCY_A::K100) };
+
+    #[cfg(feature = "v2")]
+    let i2c = { twim::Twim::new(board.TWIM0, board.i2c_internal.into(), FREQUENCY_A::K100) };
+
+    // Draining the FIFO is never retried, see `accel_fifo`
+    let bus_clear = BusClear::new(board.TIMER2, FREQUENCY_A::K100);
+    let i2c = RecoveringI2c::new(i2c, bus_clear, Config::default());
+
+    greyscale::start(board.TIMER1, board.display_pins);
+    buttons::start(
+        board.GPIOTE,
+        board.RTC0,
+        board.buttons,
+        button::Config::default(),
+    );
+    #[cfg(feature = "v1")]
+    buttons::listen_int1(board.pins.p0_28.into_floating_input().degrade());
+    #[cfg(feature = "v2")]
+    buttons::listen_int1(board.pins.p0_25.into_floating_input().degrade());
+
+    // Like the readings below, a failed setup is logged and tried again
+    let mut fifo = AccelFifo::new(i2c, FIFO_CONFIG);
+    while let Err(e) = fifo.start() {
+        rprintln!("Accelerometer error: {:?}", e);
+        greyscale::show(brighten(SENSOR_ERROR));
+        let since = buttons::now();
+        while buttons::now().wrapping_sub(since) < RETRY_MS {
+            cortex_m::asm::wfi();
+        }
+    }
+
+    let mut detector = PeakDetector::new(PEAK_CONFIG);
+    let mut result = None;
+    let mut samples = [[Acceleration::ZERO; 3]; accel_fifo::LEN];
+    loop {
+        while let Some(event) = buttons::next_event() {
+            // Button A throws away the last result
+            if event == Event::Button(ButtonEvent::Pressed(Button::A)) {
+                rprintln!("Reset");
+                detector.reset();
+                result = None;
+            }
+        }
+
+        let now = buttons::now();
+        if buttons::take_int1() {
+            match fifo.drain(&mut samples) {
+                Ok(drained) => {
+                    if drained.overrun {
+                        rprintln!("FIFO overrun, samples were lost");
+                    }
+                    for (i, sample) in samples[..drained.count].iter().enumerate() {
+                        // The newest sample is the last one
+                        let age =
+                            (drained.count - 1 - i) as u32 * 1000 / FIFO_CONFIG.data_rate.hz();
+                        let time = now.wrapping_sub(age);
+                        feed(&mut detector, &mut result, sample[0], time, now);
+                    }
+                }
+                Err(e) => rprintln!("Accelerometer error: {:?}", e),
+            }
+        }
+
+        greyscale::show(brighten(match &result {
+            None => BLANK,
+            Some(Shown { since, text, len }) => {
+                // A blank after the last character separates the repetitions
+                let shown = (now.wrapping_sub(*since) / GLYPH_MS) as usize % (len + 1);
+                font::glyph(if shown < *len { text[shown] } else { ' ' })
+            }
+        }));
+        // Any interrupt wakes us up again, INT1 as well as the display's
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Hands the X acceleration sampled at `time` to the detector, and the
+/// result to the display once there is one
+fn feed(
+    detector: &mut PeakDetector<Acceleration>,
+    result: &mut Option<Shown>,
+    x: Acceleration,
+    time: u32,
+    now: u32,
+) {
+    let armed = detector.state() == State::Armed;
+    if let Some(peak) = detector.update(x, time) {
+        rprintln!("Max acceleration: {}", peak.value);
+        let (text, len) = to_text(peak.value.mg());
+        *result = Some(Shown {
+            since: now,
+            text,
+            len,
+        });
+    }
+    if armed && matches!(detector.state(), State::Measuring { .. }) {
+        rprintln!("START!");
+        *result = None;
+    }
+}
+
+/// A positive acceleration in mg as g with one decimal, like `12.3` or
+/// `4.5`, and how many characters that is
+fn to_text(mg: i32) -> ([char; 4], usize) {
+    let tenths = (mg + 50) / 100;
+    let digit = |value: i32| char::from_digit((value % 10) as u32, 10).unwrap();
+    if tenths >= 100 {
+        (
+            [digit(tenths / 100), digit(tenths / 10), '.', digit(tenths)],
+            4,
+        )
+    } else {
+        ([digit(tenths / 10), '.', digit(tenths), ' '], 3)
+    }
+}
+
+fn brighten(glyph: Glyph) -> [[u8; 5]; 5] {
+    glyph.map(|row| row.map(|led| led * BRIGHTNESS))
+}
+
+/// Resets the chip when an I2C transaction hung, see `bus_clear`
+#[interrupt]
+fn TIMER2() {
+    bus_clear::on_timeout();
 }
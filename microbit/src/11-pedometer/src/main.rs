@@ -0,0 +1,141 @@
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use cortex_m_rt::entry;
+use lsm303agr::{AccelOutputDataRate, Lsm303agr};
+use panic_rtt_target as _;
+use rtt_target::{rprintln, rtt_init_print};
+
+use discovery_common::button::{self, Button, ButtonEvent};
+use discovery_common::font;
+use discovery_common::pedometer::{self, Pedometer};
+use discovery_common::units::Acceleration;
+
+use discovery_microbit::buttons::{self, Event};
+use discovery_microbit::greyscale;
+
+mod saved;
+
+#[cfg(feature = "v1")]
+use microbit::{hal::twi, pac::twi0::frequency::FREQUENCY_A};
+
+#[cfg(feature = "v2")]
+use microbit::{hal::twim, pac::twim0::frequency::FREQUENCY_A};
+
+/// How long the count stays in place before it moves on by a column, in ms
+const SCROLL_MS: u32 = 150;
+const BRIGHTNESS: u8 = 9;
+
+/// The count that is scrolling across the display
+struct Shown {
+    since: u32,
+    text: [char; 10],
+    len: usize,
+}
+
+impl Shown {
+    fn new(steps: u32, now: u32) -> Shown {
+        let (text, len) = to_text(steps);
+        Shown {
+            since: now,
+            text,
+            len,
+        }
+    }
+
+    fn done(&self, now: u32) -> bool {
+        now.wrapping_sub(self.since) >= font::scroll_len(self.len) as u32 * SCROLL_MS
+    }
+
+    fn image(&self, now: u32) -> [[u8; 5]; 5] {
+        let offset = (now.wrapping_sub(self.since) / SCROLL_MS) as usize;
+        font::scroll(&self.text[..self.len], offset).map(|row| row.map(|led| led * BRIGHTNESS))
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    let board = microbit::Board::take().unwrap();
+
+    #[cfg(feature = "v1")]
+    let i2c = { twi::Twi::new(board.TWI0, board.i2c.into(), FREQUENCY_A::K100) };
+
+    #[cfg(feature = "v2")]
+    let i2c = { twim::Twim::new(board.TWIM0, board.i2c_internal.into(), FREQUENCY_A::K100) };
+
+    let mut sensor = Lsm303agr::new_with_i2c(i2c);
+    sensor.init().unwrap();
+    // Plenty for steps, which come at most three times a second
+    sensor.set_accel_odr(AccelOutputDataRate::Hz50).unwrap();
+
+    greyscale::start(board.TIMER1, board.display_pins);
+    buttons::start(
+        board.GPIOTE,
+        board.RTC0,
+        board.buttons,
+        button::Config::default(),
+    );
+
+    let mut pedometer = Pedometer::new(pedometer::Config::default());
+    match saved::load() {
+        Some(steps) => {
+            rprintln!("Continuing from {} steps", steps);
+            pedometer.set_steps(steps);
+        }
+        None => rprintln!("Counting from 0"),
+    }
+    let mut shown = Shown::new(pedometer.steps(), buttons::now());
+    loop {
+        while let Some(event) = buttons::next_event() {
+            // Holding button A starts over
+            if event == Event::Button(ButtonEvent::LongPress(Button::A)) {
+                rprintln!("Reset");
+                pedometer.set_steps(0);
+                saved::store(0);
+                shown = Shown::new(0, buttons::now());
+            }
+        }
+
+        let now = buttons::now();
+        // Bus errors are logged, the next sample is probably fine again
+        match sensor.accel_status() {
+            Ok(status) if !status.xyz_new_data => {}
+            Ok(_) => match sensor.accel_data() {
+                Ok(accel) => {
+                    let accel = [accel.x, accel.y, accel.z].map(Acceleration::from_mg);
+                    if pedometer.update(accel, now) {
+                        rprintln!("Steps: {}", pedometer.steps());
+                        saved::store(pedometer.steps());
+                    }
+                }
+                Err(e) => rprintln!("Accelerometer error: {:?}", e),
+            },
+            Err(e) => rprintln!("Accelerometer error: {:?}", e),
+        }
+
+        // A new count starts scrolling once the last one is off the display
+        if shown.done(now) {
+            shown = Shown::new(pedometer.steps(), now);
+        }
+        greyscale::show(shown.image(now));
+    }
+}
+
+/// The digits of `steps`, and how many there are
+fn to_text(steps: u32) -> ([char; 10], usize) {
+    let mut text = [' '; 10];
+    let mut len = 0;
+    let mut rest = steps;
+    loop {
+        text[len] = char::from_digit(rest % 10, 10).unwrap();
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    text[..len].reverse();
+    (text, len)
+}
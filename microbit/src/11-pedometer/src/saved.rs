@@ -0,0 +1,37 @@
+//! Keeps the step count in RAM that is not cleared at startup, so it
+//! survives pressing the reset button. After a power cycle the RAM holds
+//! garbage instead, so the count is stored with a marker and a check value.
+
+// Reading and writing the `static mut` is unsafe
+#![allow(unsafe_code)]
+
+use core::mem::MaybeUninit;
+use core::ptr::{self, addr_of, addr_of_mut};
+
+/// Tells our count apart from whatever was in RAM, "STEP"
+const MAGIC: u32 = 0x5354_4550;
+
+/// `cortex-m-rt` leaves `.uninit` sections alone at startup
+#[link_section = ".uninit.STEPS"]
+static mut SAVED: MaybeUninit<[u32; 3]> = MaybeUninit::uninit();
+
+/// The count stored before the last reset, if any
+pub fn load() -> Option<u32> {
+    // Every bit pattern is a valid `[u32; 3]`, and volatile keeps the
+    // compiler from assuming anything about memory it never wrote
+    let [magic, steps, check] = unsafe { ptr::read_volatile(addr_of!(SAVED).cast::<[u32; 3]>()) };
+    if magic == MAGIC && check == !steps {
+        Some(steps)
+    } else {
+        None
+    }
+}
+
+pub fn store(steps: u32) {
+    unsafe {
+        ptr::write_volatile(
+            addr_of_mut!(SAVED).cast::<[u32; 3]>(),
+            [MAGIC, steps, !steps],
+        );
+    }
+}